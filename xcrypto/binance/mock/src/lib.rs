@@ -0,0 +1,525 @@
+pub mod rest;
+pub mod stream;
+pub mod wsapi;
+
+use log::*;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+use tokio::sync::broadcast;
+use tokio::sync::mpsc::UnboundedSender;
+use xcrypto::tungstenite::Message;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Venue {
+    Spot,
+    Usdt,
+}
+
+// symbol, price, tick size, lot size
+const PRODUCTS: [(&str, f64, f64, f64); 3] = [
+    ("BTCUSDT", 60000.0, 0.01, 0.00001),
+    ("ETHUSDT", 3000.0, 0.01, 0.0001),
+    ("DOGEUSDT", 0.15, 0.00001, 1.0),
+];
+
+pub fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+#[derive(Debug, Clone)]
+pub struct Product {
+    pub symbol: String,
+    pub price: f64,
+    pub tick_size: f64,
+    pub lot_size: f64,
+}
+
+impl Product {
+    pub fn bid(&self, level: usize) -> f64 {
+        self.price - self.tick_size * (level + 1) as f64
+    }
+
+    pub fn ask(&self, level: usize) -> f64 {
+        self.price + self.tick_size * (level + 1) as f64
+    }
+
+    fn to_json(&self, venue: Venue) -> Value {
+        let notional = match venue {
+            Venue::Spot => json!({
+                "filterType": "NOTIONAL",
+                "minNotional": "5.00000000",
+                "applyMinToMarket": true,
+                "maxNotional": "9000000.00000000",
+                "applyMaxToMarket": false,
+                "avgPriceMins": 5
+            }),
+            Venue::Usdt => json!({"filterType": "MIN_NOTIONAL", "notional": "5"}),
+        };
+
+        let mut product = json!({
+            "symbol": self.symbol,
+            "status": "TRADING",
+            "baseAsset": self.symbol.trim_end_matches("USDT"),
+            "quoteAsset": "USDT",
+            "orderTypes": ["LIMIT", "LIMIT_MAKER", "MARKET"],
+            "filters": [
+                {
+                    "filterType": "PRICE_FILTER",
+                    "minPrice": format!("{}", self.tick_size),
+                    "maxPrice": "1000000",
+                    "tickSize": format!("{}", self.tick_size)
+                },
+                {
+                    "filterType": "LOT_SIZE",
+                    "minQty": format!("{}", self.lot_size),
+                    "maxQty": "100000",
+                    "stepSize": format!("{}", self.lot_size)
+                },
+                notional
+            ]
+        });
+
+        if venue == Venue::Usdt {
+            product["deliveryDate"] = json!(4133404800000i64);
+            product["onboardDate"] = json!(1569398400000i64);
+            product["orderTypes"] = json!(["LIMIT", "MARKET"]);
+            product["timeInForce"] = json!(["GTC", "IOC", "FOK", "GTX"]);
+        }
+        product
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WorkingOrder {
+    pub venue: Venue,
+    pub symbol: String,
+    pub side: String,
+    pub order_type: String,
+    pub tif: String,
+    pub price: f64,
+    pub quantity: f64,
+    pub client_order_id: String,
+    pub order_id: i64,
+}
+
+pub struct Exchange {
+    products: HashMap<String, Product>,
+    // client order id -> resting order
+    orders: HashMap<String, WorkingOrder>,
+    // listen key -> user data stream
+    listeners: HashMap<String, UnboundedSender<Message>>,
+    listen_key: u64,
+    order_id: i64,
+    // symbol -> last diff depth update id
+    depth_ids: HashMap<String, u64>,
+    snapshots: usize,
+    reject: bool,
+    // usdt future symbol -> position amount and entry price
+    positions: HashMap<String, (f64, f64)>,
+    wallet: f64,
+    // spot asset -> free balance
+    assets: HashMap<String, f64>,
+    // request weight and orders counted since start, the windows never roll over
+    weight: u64,
+    order_count: u64,
+    order_limit: u64,
+    // milliseconds the clock of the exchange is ahead of the local one
+    clock: i64,
+    // the websocket api takes requests and never answers them
+    mute: bool,
+}
+
+impl Exchange {
+    fn new() -> Self {
+        let products = PRODUCTS
+            .iter()
+            .map(|(symbol, price, tick_size, lot_size)| {
+                (
+                    symbol.to_string(),
+                    Product {
+                        symbol: symbol.to_string(),
+                        price: *price,
+                        tick_size: *tick_size,
+                        lot_size: *lot_size,
+                    },
+                )
+            })
+            .collect();
+
+        Self {
+            products,
+            orders: HashMap::default(),
+            listeners: HashMap::default(),
+            listen_key: 0,
+            order_id: 0,
+            depth_ids: HashMap::default(),
+            snapshots: 0,
+            reject: false,
+            positions: HashMap::default(),
+            wallet: 10000.0,
+            assets: HashMap::from([("USDT".into(), 100000.0), ("BTC".into(), 1.0)]),
+            weight: 0,
+            order_count: 0,
+            order_limit: 100,
+            clock: 0,
+            mute: false,
+        }
+    }
+
+    pub fn product(&self, symbol: &str) -> Option<&Product> {
+        self.products.get(&symbol.to_uppercase())
+    }
+
+    pub fn exchange_info(&self, venue: Venue) -> Value {
+        let symbols: Vec<_> = self.products.values().map(|p| p.to_json(venue)).collect();
+        json!({
+            "timezone": "UTC",
+            "serverTime": self.server_time(),
+            "rateLimits": self.rate_limits(false),
+            "symbols": symbols
+        })
+    }
+
+    pub fn server_time(&self) -> i64 {
+        now() + self.clock
+    }
+
+    // the limits of exchangeInfo, websocket api responses add the count of each
+    pub fn rate_limits(&self, count: bool) -> Value {
+        let limits = [
+            ("REQUEST_WEIGHT", "MINUTE", 1, 6000, self.weight),
+            ("ORDERS", "SECOND", 10, self.order_limit, self.order_count),
+        ];
+        let limits: Vec<_> = limits
+            .into_iter()
+            .map(|(rate_limit_type, interval, num, limit, used)| {
+                let mut limit = json!({
+                    "rateLimitType": rate_limit_type,
+                    "interval": interval,
+                    "intervalNum": num,
+                    "limit": limit
+                });
+                if count {
+                    limit["count"] = json!(used);
+                }
+                limit
+            })
+            .collect();
+        json!(limits)
+    }
+
+    // X-MBX-USED-WEIGHT-1M and X-MBX-ORDER-COUNT-10S
+    pub fn used(&self) -> (u64, u64) {
+        (self.weight, self.order_count)
+    }
+
+    pub fn count_request(&mut self) {
+        self.weight += 1;
+    }
+
+    pub fn count_order(&mut self) {
+        self.order_count += 1;
+    }
+
+    pub fn new_listen_key(&mut self, venue: Venue) -> String {
+        self.listen_key += 1;
+        format!("{:?}{}", venue, self.listen_key).to_lowercase()
+    }
+
+    pub fn is_listen_key(&self, key: &str) -> bool {
+        let id = key
+            .strip_prefix("spot")
+            .or_else(|| key.strip_prefix("usdt"))
+            .and_then(|id| id.parse::<u64>().ok());
+        matches!(id, Some(id) if id > 0 && id <= self.listen_key)
+    }
+
+    pub fn listen(&mut self, key: &str, tx: UnboundedSender<Message>) {
+        self.listeners.insert(key.into(), tx);
+    }
+
+    pub fn next_order_id(&mut self) -> i64 {
+        self.order_id += 1;
+        self.order_id
+    }
+
+    // advance the update id of a symbol by n, returning the previous and the new one
+    pub fn depth_update(&mut self, symbol: &str, n: u64) -> (u64, u64) {
+        let id = self.depth_ids.entry(symbol.to_uppercase()).or_insert(1000);
+        let prev = *id;
+        *id += n;
+        (prev, *id)
+    }
+
+    pub fn depth_snapshot(&mut self, venue: Venue, symbol: &str) -> Option<Value> {
+        let product = self.product(symbol)?.clone();
+        let (_, id) = self.depth_update(symbol, 0);
+        self.snapshots += 1;
+
+        let levels = |side: fn(&Product, usize) -> f64| -> Vec<[String; 2]> {
+            (0..100)
+                .map(|i| [side(&product, i).to_string(), "1".to_string()])
+                .collect()
+        };
+        let mut snapshot = json!({
+            "lastUpdateId": id,
+            "bids": levels(Product::bid),
+            "asks": levels(Product::ask)
+        });
+        if venue == Venue::Usdt {
+            snapshot["E"] = json!(now());
+            snapshot["T"] = json!(now());
+        }
+        Some(snapshot)
+    }
+
+    pub fn rejecting(&self) -> bool {
+        self.reject
+    }
+
+    pub fn muted(&self) -> bool {
+        self.mute
+    }
+
+    pub fn insert(&mut self, order: WorkingOrder) {
+        self.orders.insert(order.client_order_id.clone(), order);
+    }
+
+    pub fn remove(&mut self, client_order_id: &str) -> Option<WorkingOrder> {
+        self.orders.remove(client_order_id)
+    }
+
+    // apply a fill to the account, returning the account event of it
+    pub fn account_update(&mut self, order: &WorkingOrder, price: f64) -> Option<Value> {
+        let time = now();
+        let net = match order.side.as_str() {
+            "BUY" => order.quantity,
+            _ => -order.quantity,
+        };
+
+        match order.venue {
+            Venue::Spot => {
+                let base = order.symbol.strip_suffix("USDT")?.to_string();
+                *self.assets.entry(base.clone()).or_default() += net;
+                *self.assets.entry("USDT".into()).or_default() -= net * price;
+
+                let balances: Vec<_> = [base, "USDT".into()]
+                    .iter()
+                    .map(|asset| json!({"a": asset, "f": self.assets[asset].to_string(), "l": "0"}))
+                    .collect();
+                Some(json!({
+                    "e": "outboundAccountPosition",
+                    "E": time,
+                    "u": time,
+                    "B": balances
+                }))
+            }
+            Venue::Usdt => {
+                let mark = self.product(&order.symbol)?.price;
+                let (amount, entry) = self.positions.entry(order.symbol.clone()).or_default();
+                if *amount == 0.0 || (*amount > 0.0) == (net > 0.0) {
+                    *entry = (*entry * amount.abs() + price * net.abs()) / (*amount + net).abs();
+                } else if amount.abs() < net.abs() {
+                    *entry = price;
+                }
+                *amount += net;
+                if amount.abs() < 1e-12 {
+                    *amount = 0.0;
+                    *entry = 0.0;
+                }
+
+                let (amount, entry) = (*amount, *entry);
+                Some(json!({
+                    "e": "ACCOUNT_UPDATE",
+                    "E": time,
+                    "T": time,
+                    "a": {
+                        "m": "ORDER",
+                        "B": [{
+                            "a": "USDT",
+                            "wb": self.wallet.to_string(),
+                            "cw": self.wallet.to_string(),
+                            "bc": "0"
+                        }],
+                        "P": [{
+                            "s": order.symbol,
+                            "pa": amount.to_string(),
+                            "ep": entry.to_string(),
+                            "bep": entry.to_string(),
+                            "cr": "0",
+                            "up": ((mark - entry) * amount).to_string(),
+                            "mt": "cross",
+                            "iw": "0",
+                            "ps": "BOTH"
+                        }]
+                    }
+                }))
+            }
+        }
+    }
+
+    pub fn position_risk(&self) -> Value {
+        let positions: Vec<_> = self
+            .products
+            .keys()
+            .map(|symbol| {
+                let (amount, entry) = self.positions.get(symbol).cloned().unwrap_or_default();
+                json!({
+                    "symbol": symbol,
+                    "positionAmt": amount.to_string(),
+                    "entryPrice": entry.to_string(),
+                    "marginType": "cross",
+                    "positionSide": "BOTH"
+                })
+            })
+            .collect();
+        json!(positions)
+    }
+
+    pub fn spot_account(&self) -> Value {
+        let balances: Vec<_> = self
+            .assets
+            .iter()
+            .filter(|(_, free)| **free != 0.0)
+            .map(|(asset, free)| json!({"asset": asset, "free": free.to_string(), "locked": "0"}))
+            .collect();
+        json!({"updateTime": now(), "accountType": "SPOT", "balances": balances})
+    }
+
+    // push an event to every user data stream of the venue
+    pub fn publish(&mut self, venue: Venue, event: &Value) {
+        let prefix = format!("{:?}", venue).to_lowercase();
+        let msg = event.to_string();
+        self.listeners.retain(|key, tx| {
+            !key.starts_with(&prefix) || tx.send(Message::Text(msg.clone())).is_ok()
+        });
+    }
+}
+
+pub type SharedExchange = Arc<Mutex<Exchange>>;
+
+pub struct MockExchange {
+    rest: SocketAddr,
+    stream: SocketAddr,
+    fstream: SocketAddr,
+    exchange: SharedExchange,
+    shutdown: broadcast::Sender<()>,
+}
+
+impl MockExchange {
+    // bind to random local ports
+    pub async fn start() -> anyhow::Result<Self> {
+        Self::bind("127.0.0.1:0", "127.0.0.1:0", "127.0.0.1:0").await
+    }
+
+    pub async fn bind(rest: &str, stream: &str, fstream: &str) -> anyhow::Result<Self> {
+        let exchange = Arc::new(Mutex::new(Exchange::new()));
+        let (shutdown, _) = broadcast::channel(1);
+
+        let listener = TcpListener::bind(rest).await?;
+        let rest = listener.local_addr()?;
+        tokio::spawn(rest::serve(listener, exchange.clone()));
+
+        let listener = TcpListener::bind(stream).await?;
+        let stream = listener.local_addr()?;
+        tokio::spawn(stream::serve(
+            listener,
+            Venue::Spot,
+            exchange.clone(),
+            shutdown.clone(),
+        ));
+
+        let listener = TcpListener::bind(fstream).await?;
+        let fstream = listener.local_addr()?;
+        tokio::spawn(stream::serve(
+            listener,
+            Venue::Usdt,
+            exchange.clone(),
+            shutdown.clone(),
+        ));
+
+        info!("Mock rest {}, stream {}, fstream {}", rest, stream, fstream);
+        Ok(Self {
+            rest,
+            stream,
+            fstream,
+            exchange,
+            shutdown,
+        })
+    }
+
+    pub fn rest_url(&self) -> String {
+        format!("http://{}", self.rest)
+    }
+
+    pub fn stream_url(&self) -> String {
+        format!("ws://{}/ws", self.stream)
+    }
+
+    pub fn fstream_url(&self) -> String {
+        format!("ws://{}/ws", self.fstream)
+    }
+
+    // the websocket api is served next to the streams of its venue
+    pub fn ws_api_url(&self) -> String {
+        format!("ws://{}/ws-api/v3", self.stream)
+    }
+
+    pub fn fws_api_url(&self) -> String {
+        format!("ws://{}/ws-fapi/v1", self.fstream)
+    }
+
+    // reject every new order with -2010
+    pub fn reject_orders(&self, reject: bool) {
+        self.exchange.lock().unwrap().reject = reject;
+    }
+
+    // the ORDERS limit of 10 seconds exchangeInfo lists
+    pub fn order_limit(&self, limit: u64) {
+        self.exchange.lock().unwrap().order_limit = limit;
+    }
+
+    // move the clock of the exchange ahead of the local one, or behind it when negative
+    pub fn skew_clock(&self, ms: i64) {
+        self.exchange.lock().unwrap().clock = ms;
+    }
+
+    // websocket api requests after the logon go unanswered
+    pub fn mute_ws_api(&self, mute: bool) {
+        self.exchange.lock().unwrap().mute = mute;
+    }
+
+    // lose some diff depth updates of every symbol
+    pub fn skip_depth_updates(&self, n: u64) {
+        let mut exchange = self.exchange.lock().unwrap();
+        for id in exchange.depth_ids.values_mut() {
+            *id += n;
+        }
+    }
+
+    // depth snapshots served so far
+    pub fn snapshots(&self) -> usize {
+        self.exchange.lock().unwrap().snapshots
+    }
+
+    // drop every market and user data connection
+    pub fn disconnect(&self) {
+        let _ = self.shutdown.send(());
+    }
+
+    pub fn working_orders(&self) -> Vec<WorkingOrder> {
+        self.exchange
+            .lock()
+            .unwrap()
+            .orders
+            .values()
+            .cloned()
+            .collect()
+    }
+}
@@ -0,0 +1,34 @@
+use clap::Parser;
+use log::info;
+use logger::*;
+use mock::MockExchange;
+
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Args {
+    #[arg(long, default_value = "127.0.0.1:8000", help = "Rest address")]
+    rest: String,
+    #[arg(long, default_value = "127.0.0.1:8001", help = "Spot stream address")]
+    stream: String,
+    #[arg(long, default_value = "127.0.0.1:8002", help = "Usdt stream address")]
+    fstream: String,
+    #[arg(short, long, default_value_t = Level::Info)]
+    level: Level,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let _logger = init(Some("log/mock"), args.level);
+
+    let mock = MockExchange::bind(&args.rest, &args.stream, &args.fstream).await?;
+    info!(
+        "rest: {}, stream: {}, fstream: {}",
+        mock.rest_url(),
+        mock.stream_url(),
+        mock.fstream_url()
+    );
+
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
@@ -0,0 +1,552 @@
+use crate::{now, Exchange, SharedExchange, Venue, WorkingOrder};
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use log::*;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use tokio::net::TcpListener;
+
+type Params = HashMap<String, String>;
+
+pub async fn serve(listener: TcpListener, exchange: SharedExchange) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                debug!("Rest connect {}", peer);
+                let exchange = exchange.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(move |req| handle(exchange.clone(), req));
+                    if let Err(e) = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await
+                    {
+                        error!("{}", e);
+                    }
+                });
+            }
+            Err(e) => error!("{}", e),
+        }
+    }
+}
+
+fn error(code: i32, msg: &str) -> (StatusCode, Value) {
+    (StatusCode::BAD_REQUEST, json!({"code": code, "msg": msg}))
+}
+
+async fn handle(
+    exchange: SharedExchange,
+    req: Request<Incoming>,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    let query = req.uri().query().unwrap_or_default();
+    let params: Params = url::form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .collect();
+    info!("{} {} {:?}", req.method(), req.uri().path(), params);
+
+    let venue = if req.uri().path().starts_with("/fapi") {
+        Venue::Usdt
+    } else {
+        Venue::Spot
+    };
+
+    let (status, body, (weight, orders)) = {
+        let mut exchange = exchange.lock().unwrap();
+        exchange.count_request();
+        let (status, body) = match (req.method(), req.uri().path()) {
+            (&Method::GET, "/api/v3/time" | "/fapi/v1/time") => (
+                StatusCode::OK,
+                json!({"serverTime": exchange.server_time()}),
+            ),
+            (&Method::GET, "/api/v3/exchangeInfo" | "/fapi/v1/exchangeInfo") => {
+                (StatusCode::OK, exchange.exchange_info(venue))
+            }
+            (
+                &Method::POST,
+                "/api/v3/userDataStream" | "/sapi/v1/userDataStream" | "/fapi/v1/listenKey",
+            ) => (
+                StatusCode::OK,
+                json!({"listenKey": exchange.new_listen_key(venue)}),
+            ),
+            (
+                &Method::PUT | &Method::DELETE,
+                "/api/v3/userDataStream" | "/sapi/v1/userDataStream" | "/fapi/v1/listenKey",
+            ) => (StatusCode::OK, json!({})),
+            (&Method::GET, "/api/v3/depth" | "/fapi/v1/depth") => {
+                let symbol = params.get("symbol").cloned().unwrap_or_default();
+                match exchange.depth_snapshot(venue, &symbol) {
+                    Some(snapshot) => (StatusCode::OK, snapshot),
+                    None => error(-1121, "Invalid symbol."),
+                }
+            }
+            (&Method::GET, "/fapi/v2/positionRisk") => match validate_signature(&exchange, &params)
+            {
+                Some(e) => e,
+                None => (StatusCode::OK, exchange.position_risk()),
+            },
+            (&Method::GET, "/api/v3/account") => match validate_signature(&exchange, &params) {
+                Some(e) => e,
+                None => (StatusCode::OK, exchange.spot_account()),
+            },
+            (&Method::POST, "/api/v3/order" | "/sapi/v1/margin/order" | "/fapi/v1/order") => {
+                new_order(&mut exchange, venue, &params)
+            }
+            (&Method::DELETE, "/api/v3/order" | "/sapi/v1/margin/order" | "/fapi/v1/order") => {
+                cancel_order(&mut exchange, venue, &params)
+            }
+            (&Method::POST, "/api/v3/order/cancelReplace") => {
+                cancel_replace(&mut exchange, venue, &params)
+            }
+            (&Method::PUT, "/fapi/v1/order") => modify_order(&mut exchange, venue, &params),
+            (&Method::POST, "/fapi/v1/batchOrders") => batch_orders(&mut exchange, &params),
+            (&Method::DELETE, "/fapi/v1/batchOrders") => batch_cancel(&mut exchange, &params),
+            _ => (
+                StatusCode::NOT_FOUND,
+                json!({"code": -1000, "msg": "Unsupported endpoint."}),
+            ),
+        };
+        (status, body, exchange.used())
+    };
+
+    debug!("{} {}", status, body);
+    Ok(Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .header("X-MBX-USED-WEIGHT-1M", weight)
+        .header("X-MBX-ORDER-COUNT-10S", orders)
+        .body(Full::new(Bytes::from(body.to_string())))
+        .unwrap())
+}
+
+fn validate_signature(exchange: &Exchange, params: &Params) -> Option<(StatusCode, Value)> {
+    for key in ["timestamp", "signature"] {
+        if !params.contains_key(key) {
+            return Some(error(
+                -1102,
+                &format!("Mandatory parameter '{}' was not sent.", key),
+            ));
+        }
+    }
+
+    // the same window binance allows around its own clock
+    let get = |key: &str| params.get(key).and_then(|v| v.parse::<i64>().ok());
+    let timestamp = get("timestamp").unwrap_or_default();
+    let recvwindow = get("recvWindow").unwrap_or(5000);
+    let server = exchange.server_time();
+    if timestamp >= server + 1000 || server - timestamp > recvwindow {
+        return Some(error(
+            -1021,
+            "Timestamp for this request is outside of the recvWindow.",
+        ));
+    }
+    None
+}
+
+pub(crate) fn new_order(
+    exchange: &mut Exchange,
+    venue: Venue,
+    params: &Params,
+) -> (StatusCode, Value) {
+    if let Some(e) = validate_signature(exchange, params) {
+        return e;
+    }
+
+    exchange.count_order();
+    let get = |key: &str| params.get(key).cloned().unwrap_or_default();
+    let product = match exchange.product(&get("symbol")) {
+        Some(product) => product.clone(),
+        None => return error(-1121, "Invalid symbol."),
+    };
+    if exchange.rejecting() {
+        return error(-2010, "Order rejected by mock exchange.");
+    }
+
+    let quantity = match get("quantity").parse::<f64>() {
+        Ok(quantity) if quantity > 0.0 => quantity,
+        _ => return error(-1100, "Illegal characters found in parameter 'quantity'."),
+    };
+
+    let order = WorkingOrder {
+        venue,
+        symbol: product.symbol.clone(),
+        side: get("side"),
+        order_type: get("type"),
+        tif: params
+            .get("timeInForce")
+            .cloned()
+            .unwrap_or_else(|| "GTC".into()),
+        price: get("price").parse().unwrap_or_default(),
+        quantity,
+        client_order_id: get("newClientOrderId"),
+        order_id: exchange.next_order_id(),
+    };
+
+    // best price on the opposite side
+    let touch = match order.side.as_str() {
+        "BUY" => product.ask(0),
+        _ => product.bid(0),
+    };
+    let crossed = match order.side.as_str() {
+        "BUY" => order.price >= touch,
+        _ => order.price <= touch,
+    };
+
+    let fill = match order.order_type.as_str() {
+        "MARKET" => true,
+        "LIMIT" => crossed,
+        "LIMIT_MAKER" if crossed => return error(-2010, "Order would immediately match and take."),
+        "LIMIT_MAKER" => false,
+        _ => return error(-1116, "Invalid orderType."),
+    };
+
+    exchange.publish(venue, &report(&order, "NEW", 0.0, 0.0));
+    if fill {
+        trade(exchange, venue, &order, touch);
+        (StatusCode::OK, result(&order, "FILLED", order.quantity))
+    } else {
+        let rsp = result(&order, "NEW", 0.0);
+        exchange.insert(order);
+        (StatusCode::OK, rsp)
+    }
+}
+
+pub(crate) fn cancel_order(
+    exchange: &mut Exchange,
+    venue: Venue,
+    params: &Params,
+) -> (StatusCode, Value) {
+    if let Some(e) = validate_signature(exchange, params) {
+        return e;
+    }
+
+    let orig = params.get("origClientOrderId").cloned().unwrap_or_default();
+    match exchange.remove(&orig) {
+        Some(order) => {
+            exchange.publish(venue, &report(&order, "CANCELED", 0.0, 0.0));
+            (StatusCode::OK, result(&order, "CANCELED", 0.0))
+        }
+        None => error(-2011, "Unknown order sent."),
+    }
+}
+
+// every item is answered with its order or its error
+fn batch_orders(exchange: &mut Exchange, params: &Params) -> (StatusCode, Value) {
+    if let Some(e) = validate_signature(exchange, params) {
+        return e;
+    }
+
+    let orders = params
+        .get("batchOrders")
+        .and_then(|orders| serde_json::from_str::<Vec<HashMap<String, String>>>(orders).ok());
+    let orders = match orders {
+        Some(orders) if !orders.is_empty() && orders.len() <= 5 => orders,
+        _ => return error(-1130, "Data sent for parameter 'batchOrders' is not valid."),
+    };
+
+    let items: Vec<_> = orders
+        .into_iter()
+        .map(|mut order| {
+            order.insert("timestamp".into(), params["timestamp"].clone());
+            order.insert("signature".into(), params["signature"].clone());
+            new_order(exchange, Venue::Usdt, &order).1
+        })
+        .collect();
+    (StatusCode::OK, json!(items))
+}
+
+fn batch_cancel(exchange: &mut Exchange, params: &Params) -> (StatusCode, Value) {
+    if let Some(e) = validate_signature(exchange, params) {
+        return e;
+    }
+
+    let origs = params
+        .get("origClientOrderIdList")
+        .and_then(|origs| serde_json::from_str::<Vec<String>>(origs).ok());
+    let origs = match origs {
+        Some(origs) if !origs.is_empty() && origs.len() <= 10 => origs,
+        _ => {
+            return error(
+                -1130,
+                "Data sent for parameter 'origClientOrderIdList' is not valid.",
+            )
+        }
+    };
+
+    let items: Vec<_> = origs
+        .into_iter()
+        .map(|orig| {
+            let mut cancel = params.clone();
+            cancel.insert("origClientOrderId".into(), orig);
+            cancel_order(exchange, Venue::Usdt, &cancel).1
+        })
+        .collect();
+    (StatusCode::OK, json!(items))
+}
+
+pub(crate) fn cancel_replace(
+    exchange: &mut Exchange,
+    venue: Venue,
+    params: &Params,
+) -> (StatusCode, Value) {
+    if let Some(e) = validate_signature(exchange, params) {
+        return e;
+    }
+
+    let orig = params
+        .get("cancelOrigClientOrderId")
+        .cloned()
+        .unwrap_or_default();
+    let canceled = match exchange.remove(&orig) {
+        Some(order) => {
+            exchange.publish(venue, &report(&order, "CANCELED", 0.0, 0.0));
+            result(&order, "CANCELED", 0.0)
+        }
+        None => {
+            return (
+                StatusCode::BAD_REQUEST,
+                json!({
+                    "code": -2022,
+                    "msg": "Order cancel-replace failed.",
+                    "data": {"cancelResult": "FAILURE", "newOrderResult": "NOT_ATTEMPTED"}
+                }),
+            )
+        }
+    };
+
+    match new_order(exchange, venue, params) {
+        (StatusCode::OK, placed) => (
+            StatusCode::OK,
+            json!({
+                "cancelResult": "SUCCESS",
+                "newOrderResult": "SUCCESS",
+                "cancelResponse": canceled,
+                "newOrderResponse": placed
+            }),
+        ),
+        (status, e) => (
+            status,
+            json!({
+                "code": -2021,
+                "msg": "Order cancel-replace partially failed.",
+                "data": {
+                    "cancelResult": "SUCCESS",
+                    "newOrderResult": "FAILURE",
+                    "cancelResponse": canceled,
+                    "newOrderResponse": e
+                }
+            }),
+        ),
+    }
+}
+
+// only price and quantity change, the client order id is kept
+pub(crate) fn modify_order(
+    exchange: &mut Exchange,
+    venue: Venue,
+    params: &Params,
+) -> (StatusCode, Value) {
+    if let Some(e) = validate_signature(exchange, params) {
+        return e;
+    }
+
+    exchange.count_order();
+    let orig = params.get("origClientOrderId").cloned().unwrap_or_default();
+    let mut order = match exchange.remove(&orig) {
+        Some(order) => order,
+        None => return error(-2013, "Order does not exist."),
+    };
+
+    let get = |key: &str| params.get(key).and_then(|v| v.parse::<f64>().ok());
+    match (get("price"), get("quantity")) {
+        (Some(price), Some(quantity)) if quantity > 0.0 => {
+            order.price = price;
+            order.quantity = quantity;
+        }
+        _ => {
+            exchange.insert(order);
+            return error(
+                -1102,
+                "Mandatory parameter 'price' or 'quantity' was not sent.",
+            );
+        }
+    }
+
+    let mut event = report(&order, "NEW", 0.0, 0.0);
+    event["o"]["x"] = json!("AMENDMENT");
+    exchange.publish(venue, &event);
+
+    let touch = match exchange.product(&order.symbol) {
+        Some(product) if order.side == "BUY" && order.price >= product.ask(0) => {
+            Some(product.ask(0))
+        }
+        Some(product) if order.side == "SELL" && order.price <= product.bid(0) => {
+            Some(product.bid(0))
+        }
+        _ => None,
+    };
+
+    match touch {
+        Some(touch) => {
+            trade(exchange, venue, &order, touch);
+            (StatusCode::OK, result(&order, "FILLED", order.quantity))
+        }
+        None => {
+            let rsp = result(&order, "NEW", 0.0);
+            exchange.insert(order);
+            (StatusCode::OK, rsp)
+        }
+    }
+}
+
+// the whole order trades at price, the account follows the order update
+fn trade(exchange: &mut Exchange, venue: Venue, order: &WorkingOrder, price: f64) {
+    exchange.publish(venue, &report(order, "FILLED", order.quantity, price));
+    if let Some(event) = exchange.account_update(order, price) {
+        exchange.publish(venue, &event);
+    }
+}
+
+// newOrderRespType=RESULT
+fn result(order: &WorkingOrder, status: &str, executed: f64) -> Value {
+    match order.venue {
+        Venue::Spot => json!({
+            "symbol": order.symbol,
+            "orderId": order.order_id,
+            "orderListId": -1,
+            "clientOrderId": order.client_order_id,
+            "transactTime": now(),
+            "price": order.price.to_string(),
+            "origQty": order.quantity.to_string(),
+            "executedQty": executed.to_string(),
+            "cummulativeQuoteQty": (executed * order.price).to_string(),
+            "status": status,
+            "timeInForce": order.tif,
+            "type": order.order_type,
+            "side": order.side,
+            "workingTime": now(),
+            "selfTradePreventionMode": "NONE"
+        }),
+        Venue::Usdt => json!({
+            "clientOrderId": order.client_order_id,
+            "cumQty": executed.to_string(),
+            "cumQuote": (executed * order.price).to_string(),
+            "executedQty": executed.to_string(),
+            "orderId": order.order_id,
+            "avgPrice": order.price.to_string(),
+            "origQty": order.quantity.to_string(),
+            "price": order.price.to_string(),
+            "reduceOnly": false,
+            "side": order.side,
+            "positionSide": "BOTH",
+            "status": status,
+            "stopPrice": "0",
+            "closePosition": false,
+            "symbol": order.symbol,
+            "timeInForce": order.tif,
+            "type": order.order_type,
+            "origType": order.order_type,
+            "updateTime": now(),
+            "workingType": "CONTRACT_PRICE",
+            "priceProtect": false
+        }),
+    }
+}
+
+// executionReport / ORDER_TRADE_UPDATE
+pub fn report(order: &WorkingOrder, status: &str, last_qty: f64, last_price: f64) -> Value {
+    let time = now();
+    let execution = match status {
+        "FILLED" | "PARTIALLY_FILLED" => "TRADE",
+        other => other,
+    };
+    let trade_id = if last_qty > 0.0 { order.order_id } else { -1 };
+
+    match order.venue {
+        Venue::Spot => {
+            // the cancel request carries its own client order id
+            let (c, orig) = match status {
+                "CANCELED" => (
+                    format!("cancel{}", order.order_id),
+                    order.client_order_id.clone(),
+                ),
+                _ => (order.client_order_id.clone(), String::new()),
+            };
+            json!({
+                "e": "executionReport",
+                "E": time,
+                "s": order.symbol,
+                "c": c,
+                "S": order.side,
+                "o": order.order_type,
+                "f": order.tif,
+                "q": order.quantity.to_string(),
+                "p": order.price.to_string(),
+                "P": "0",
+                "F": "0",
+                "g": -1,
+                "C": orig,
+                "x": execution,
+                "X": status,
+                "r": "NONE",
+                "i": order.order_id,
+                "l": last_qty.to_string(),
+                "z": last_qty.to_string(),
+                "L": last_price.to_string(),
+                "n": "0",
+                "N": null,
+                "T": time,
+                "t": trade_id,
+                "w": status == "NEW",
+                "m": false,
+                "M": false,
+                "O": time,
+                "Z": (last_qty * last_price).to_string(),
+                "Y": (last_qty * last_price).to_string(),
+                "Q": "0"
+            })
+        }
+        Venue::Usdt => json!({
+            "e": "ORDER_TRADE_UPDATE",
+            "E": time,
+            "T": time,
+            "o": {
+                "s": order.symbol,
+                "c": order.client_order_id,
+                "S": order.side,
+                "o": order.order_type,
+                "f": order.tif,
+                "q": order.quantity.to_string(),
+                "p": order.price.to_string(),
+                "ap": last_price.to_string(),
+                "sp": "0",
+                "x": execution,
+                "X": status,
+                "i": order.order_id,
+                "l": last_qty.to_string(),
+                "z": last_qty.to_string(),
+                "L": last_price.to_string(),
+                "N": "USDT",
+                "n": "0",
+                "T": time,
+                "t": trade_id,
+                "b": "0",
+                "a": "0",
+                "m": false,
+                "R": false,
+                "wt": "CONTRACT_PRICE",
+                "ot": order.order_type,
+                "ps": "BOTH",
+                "cp": false,
+                "rp": "0",
+                "pP": false,
+                "si": 0,
+                "ss": 0
+            }
+        }),
+    }
+}
@@ -0,0 +1,338 @@
+use crate::{now, Exchange, Product, SharedExchange, Venue};
+use futures_util::{SinkExt, StreamExt};
+use log::*;
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio::sync::mpsc::unbounded_channel;
+use tokio::time::{interval, Duration};
+use xcrypto::tungstenite::handshake::server::{Request, Response};
+use xcrypto::tungstenite::Message;
+use xcrypto::WebSocketStream;
+
+pub async fn serve(
+    listener: TcpListener,
+    venue: Venue,
+    exchange: SharedExchange,
+    shutdown: broadcast::Sender<()>,
+) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                info!("{:?} stream connect {}", venue, peer);
+                let exchange = exchange.clone();
+                let shutdown = shutdown.subscribe();
+                tokio::spawn(async move {
+                    if let Err(e) = handle(stream, venue, exchange, shutdown).await {
+                        error!("{}", e);
+                    }
+                    info!("{:?} stream disconnect {}", venue, peer);
+                });
+            }
+            Err(e) => error!("{}", e),
+        }
+    }
+}
+
+// the handshake callback signature is fixed by tungstenite
+#[allow(clippy::result_large_err)]
+async fn handle(
+    stream: TcpStream,
+    venue: Venue,
+    exchange: SharedExchange,
+    shutdown: broadcast::Receiver<()>,
+) -> anyhow::Result<()> {
+    let mut path = String::new();
+    let ws = xcrypto::accept_hdr_async(stream, |req: &Request, rsp: Response| {
+        path = req.uri().path().to_string();
+        Ok(rsp)
+    })
+    .await?;
+
+    if path == "/ws-api/v3" || path == "/ws-fapi/v1" {
+        return crate::wsapi::session(ws, venue, exchange, shutdown).await;
+    }
+
+    match path.strip_prefix("/ws/") {
+        Some(key) if exchange.lock().unwrap().is_listen_key(key) => {
+            user_data(ws, key, exchange, shutdown).await
+        }
+        _ => market_data(ws, venue, exchange, shutdown).await,
+    }
+}
+
+async fn user_data(
+    ws: WebSocketStream<TcpStream>,
+    key: &str,
+    exchange: SharedExchange,
+    mut shutdown: broadcast::Receiver<()>,
+) -> anyhow::Result<()> {
+    let (tx, mut rx) = unbounded_channel();
+    exchange.lock().unwrap().listen(key, tx);
+    let (mut write, mut read) = ws.split();
+
+    loop {
+        tokio::select! {
+            event = rx.recv() => match event {
+                Some(event) => write.send(event).await?,
+                None => break,
+            },
+            msg = read.next() => match msg {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return Ok(()),
+                _ => (),
+            },
+            _ = shutdown.recv() => break,
+        }
+    }
+
+    write.close().await?;
+    Ok(())
+}
+
+async fn market_data(
+    ws: WebSocketStream<TcpStream>,
+    venue: Venue,
+    exchange: SharedExchange,
+    mut shutdown: broadcast::Receiver<()>,
+) -> anyhow::Result<()> {
+    let (mut write, mut read) = ws.split();
+    let mut combined = false;
+    let mut streams = BTreeSet::new();
+    let mut timer = interval(Duration::from_millis(100));
+
+    loop {
+        tokio::select! {
+            msg = read.next() => match msg {
+                Some(Ok(Message::Text(text))) => {
+                    let rsp = handle_request(&text, &mut combined, &mut streams, &exchange);
+                    write.send(Message::Text(rsp.to_string())).await?;
+                }
+                Some(Ok(Message::Ping(ping))) => write.send(Message::Pong(ping)).await?,
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return Ok(()),
+                _ => (),
+            },
+            _ = timer.tick() => {
+                for stream in streams.iter() {
+                    let data = {
+                        let mut exchange = exchange.lock().unwrap();
+                        match stream.split_once('@') {
+                            Some((symbol, "depth@100ms")) => diff(venue, &mut exchange, symbol),
+                            // only btcusdt is liquidated on the whole market stream
+                            Some(("!forceOrder", "arr")) => exchange
+                                .product("btcusdt")
+                                .and_then(|p| data(venue, p, "btcusdt@forceOrder")),
+                            Some((symbol, _)) => exchange
+                                .product(symbol)
+                                .and_then(|p| data(venue, p, stream)),
+                            None => None,
+                        }
+                    };
+
+                    if let Some(data) = data {
+                        let msg = match combined {
+                            true => json!({"stream": stream, "data": data}),
+                            false => data,
+                        };
+                        write.send(Message::Text(msg.to_string())).await?;
+                    }
+                }
+            }
+            _ = shutdown.recv() => break,
+        }
+    }
+
+    write.close().await?;
+    Ok(())
+}
+
+fn handle_request(
+    text: &str,
+    combined: &mut bool,
+    streams: &mut BTreeSet<String>,
+    exchange: &SharedExchange,
+) -> Value {
+    let req: Value = match serde_json::from_str(text) {
+        Ok(req) => req,
+        Err(_) => return json!({"code": 3, "msg": "Invalid JSON"}),
+    };
+    let id = req["id"].clone();
+    let params = req["params"].as_array().cloned().unwrap_or_default();
+
+    match req["method"].as_str().unwrap_or_default() {
+        "SET_PROPERTY" => {
+            if params.first().and_then(|p| p.as_str()) == Some("combined") {
+                *combined = params.get(1).and_then(|p| p.as_bool()).unwrap_or(false);
+            }
+            json!({"result": null, "id": id})
+        }
+        "SUBSCRIBE" => {
+            let exchange = exchange.lock().unwrap();
+            for stream in params.iter().filter_map(|p| p.as_str()) {
+                match stream.split_once('@') {
+                    Some((symbol, _)) if exchange.product(symbol).is_some() => {
+                        streams.insert(stream.to_string());
+                    }
+                    Some(("!forceOrder", "arr")) => {
+                        streams.insert(stream.to_string());
+                    }
+                    _ => {
+                        return json!({
+                            "error": {"code": 2, "msg": format!("Invalid request: unknown stream {}", stream)},
+                            "id": id
+                        })
+                    }
+                }
+            }
+            json!({"result": null, "id": id})
+        }
+        "UNSUBSCRIBE" => {
+            for stream in params.iter().filter_map(|p| p.as_str()) {
+                streams.remove(stream);
+            }
+            json!({"result": null, "id": id})
+        }
+        "LIST_SUBSCRIPTIONS" => json!({"result": streams, "id": id}),
+        _ => json!({"error": {"code": 2, "msg": "Invalid request: unknown method"}, "id": id}),
+    }
+}
+
+fn data(venue: Venue, product: &Product, stream: &str) -> Option<Value> {
+    let time = now();
+    let (_, kind) = stream.split_once('@')?;
+    let levels = |side: fn(&Product, usize) -> f64| -> Vec<[String; 2]> {
+        (0..20)
+            .map(|i| [side(product, i).to_string(), "1".to_string()])
+            .collect()
+    };
+
+    match kind.split('@').next()? {
+        "bookTicker" => Some(json!({
+            "u": time,
+            "E": time,
+            "s": product.symbol,
+            "b": product.bid(0).to_string(),
+            "B": "1",
+            "a": product.ask(0).to_string(),
+            "A": "1"
+        })),
+        "depth5" | "depth10" | "depth20" => match venue {
+            Venue::Spot => Some(json!({
+                "lastUpdateId": time,
+                "bids": levels(Product::bid),
+                "asks": levels(Product::ask)
+            })),
+            Venue::Usdt => Some(json!({
+                "e": "depthUpdate",
+                "E": time,
+                "T": time,
+                "s": product.symbol,
+                "U": time,
+                "u": time,
+                "pu": time - 1,
+                "b": levels(Product::bid),
+                "a": levels(Product::ask)
+            })),
+        },
+        "trade" | "aggTrade" => {
+            let id = if kind == "trade" { "t" } else { "a" };
+            let mut trade = json!({
+                "e": kind,
+                "E": time,
+                "s": product.symbol,
+                "p": product.price.to_string(),
+                "q": "1",
+                "T": time,
+                "m": time % 2 == 0
+            });
+            trade[id] = json!(time);
+            Some(trade)
+        }
+        "markPrice" => match venue {
+            Venue::Spot => None,
+            // funding settles every 8 hours
+            Venue::Usdt => Some(json!({
+                "e": "markPriceUpdate",
+                "E": time,
+                "s": product.symbol,
+                "p": product.price.to_string(),
+                "ap": product.price.to_string(),
+                "i": product.price.to_string(),
+                "P": product.price.to_string(),
+                "r": "0.0001",
+                "T": (time / 28_800_000 + 1) * 28_800_000
+            })),
+        },
+        "forceOrder" => match venue {
+            Venue::Spot => None,
+            Venue::Usdt => Some(json!({
+                "e": "forceOrder",
+                "E": time,
+                "o": {
+                    "s": product.symbol,
+                    "S": "SELL",
+                    "o": "LIMIT",
+                    "f": "IOC",
+                    "q": "1",
+                    "p": product.bid(0).to_string(),
+                    "ap": product.bid(0).to_string(),
+                    "X": "FILLED",
+                    "l": "1",
+                    "z": "1",
+                    "T": time
+                }
+            })),
+        },
+        kline => {
+            let interval = kline.strip_prefix("kline_")?;
+            let price = product.price.to_string();
+            Some(json!({
+                "e": "kline",
+                "E": time,
+                "s": product.symbol,
+                "k": {
+                    "t": time - 60000,
+                    "T": time,
+                    "s": product.symbol,
+                    "i": interval,
+                    "f": 0,
+                    "L": 0,
+                    "o": price,
+                    "c": price,
+                    "h": product.ask(0).to_string(),
+                    "l": product.bid(0).to_string(),
+                    "v": "1",
+                    "n": 1,
+                    "x": false,
+                    "q": price,
+                    "V": "0",
+                    "Q": "0",
+                    "B": "0"
+                }
+            }))
+        }
+    }
+}
+
+// two updates per event, the touch quantity keeps changing
+fn diff(venue: Venue, exchange: &mut Exchange, symbol: &str) -> Option<Value> {
+    let product = exchange.product(symbol)?.clone();
+    let (prev, last) = exchange.depth_update(symbol, 2);
+    let time = now();
+    let quantity = (last % 5 + 1).to_string();
+
+    let mut data = json!({
+        "e": "depthUpdate",
+        "E": time,
+        "s": product.symbol,
+        "U": prev + 1,
+        "u": last,
+        "b": [[product.bid(0).to_string(), quantity]],
+        "a": [[product.ask(0).to_string(), quantity]]
+    });
+    if venue == Venue::Usdt {
+        data["T"] = json!(time);
+        data["pu"] = json!(prev);
+    }
+    Some(data)
+}
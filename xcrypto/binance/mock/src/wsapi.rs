@@ -0,0 +1,117 @@
+use crate::rest::{cancel_order, cancel_replace, modify_order, new_order};
+use crate::{SharedExchange, Venue};
+use futures_util::{SinkExt, StreamExt};
+use hyper::StatusCode;
+use log::*;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use tokio::net::TcpStream;
+use tokio::sync::broadcast;
+use xcrypto::tungstenite::Message;
+use xcrypto::WebSocketStream;
+
+// websocket api, orders are taken once the connection is logged on
+pub async fn session(
+    ws: WebSocketStream<TcpStream>,
+    venue: Venue,
+    exchange: SharedExchange,
+    mut shutdown: broadcast::Receiver<()>,
+) -> anyhow::Result<()> {
+    let (mut write, mut read) = ws.split();
+    let mut logged_on = false;
+
+    loop {
+        tokio::select! {
+            msg = read.next() => match msg {
+                Some(Ok(Message::Text(text))) => {
+                    let logon = !logged_on;
+                    let rsp = handle_request(&text, venue, &mut logged_on, &exchange);
+                    if logon || !exchange.lock().unwrap().muted() {
+                        write.send(Message::Text(rsp.to_string())).await?;
+                    }
+                }
+                Some(Ok(Message::Ping(ping))) => write.send(Message::Pong(ping)).await?,
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return Ok(()),
+                _ => (),
+            },
+            _ = shutdown.recv() => break,
+        }
+    }
+
+    write.close().await?;
+    Ok(())
+}
+
+fn handle_request(
+    text: &str,
+    venue: Venue,
+    logged_on: &mut bool,
+    exchange: &SharedExchange,
+) -> Value {
+    let req: Value = match serde_json::from_str(text) {
+        Ok(req) => req,
+        Err(_) => {
+            return json!({"id": null, "status": 400, "error": {"code": -1000, "msg": "Invalid JSON"}})
+        }
+    };
+    let id = req["id"].clone();
+
+    // numbers are taken like the strings of a query
+    let mut params: HashMap<String, String> = req["params"]
+        .as_object()
+        .cloned()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(k, v)| match v {
+            Value::String(s) => (k, s),
+            v => (k, v.to_string()),
+        })
+        .collect();
+    info!("{} {:?}", req["method"], params);
+
+    let method = req["method"].as_str().unwrap_or_default();
+    if method == "session.logon" {
+        if !params.contains_key("apiKey") || !params.contains_key("signature") {
+            return json!({
+                "id": id,
+                "status": 400,
+                "error": {"code": -1102, "msg": "Mandatory parameter 'signature' was not sent."}
+            });
+        }
+        *logged_on = true;
+        return json!({"id": id, "status": 200, "result": {"apiKey": params["apiKey"]}});
+    }
+
+    // the session signs every request
+    if *logged_on {
+        params.insert("signature".into(), "session".into());
+    }
+
+    let (status, body, rate_limits) = {
+        let mut exchange = exchange.lock().unwrap();
+        exchange.count_request();
+        let (status, body) = match (venue, method) {
+            (_, "order.place") => new_order(&mut exchange, venue, &params),
+            (_, "order.cancel") => cancel_order(&mut exchange, venue, &params),
+            (Venue::Spot, "order.cancelReplace") => cancel_replace(&mut exchange, venue, &params),
+            (Venue::Usdt, "order.modify") => modify_order(&mut exchange, venue, &params),
+            _ => (
+                StatusCode::BAD_REQUEST,
+                json!({"code": -1000, "msg": "Unsupported method."}),
+            ),
+        };
+        (status, body, exchange.rate_limits(true))
+    };
+
+    match status {
+        StatusCode::OK => {
+            json!({"id": id, "status": 200, "result": body, "rateLimits": rate_limits})
+        }
+        status => json!({
+            "id": id,
+            "status": status.as_u16(),
+            "error": body,
+            "rateLimits": rate_limits
+        }),
+    }
+}
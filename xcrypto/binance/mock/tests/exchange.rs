@@ -0,0 +1,1469 @@
+use binance::chat::{Event, OrderUpdate};
+use binance::*;
+use mock::{MockExchange, Venue};
+use openssl::pkey::PKey;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use tokio::time::{timeout, Duration};
+use xcrypto::chat::{Error, Order, TradeReq};
+use xcrypto::ratelimit::ExchangeLimit;
+use xcrypto::rest::{HttpConfig, Rest, SignMethod};
+use xcrypto::timesync;
+use xcrypto::tungstenite::Message;
+use xcrypto::ws::WebSocket;
+use xcrypto::wsapi::WsApi;
+
+// run in a scratch directory
+fn scratch() -> &'static PathBuf {
+    static DIR: OnceLock<PathBuf> = OnceLock::new();
+    DIR.get_or_init(|| {
+        let dir = std::env::temp_dir().join(format!("mock-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::env::set_current_dir(&dir).unwrap();
+        dir
+    })
+}
+
+// a freshly generated ed25519 key
+fn pem() -> &'static str {
+    static PEM: OnceLock<String> = OnceLock::new();
+    PEM.get_or_init(|| {
+        let key = PKey::generate_ed25519().unwrap();
+        let path = scratch().join("private_key.pem");
+        std::fs::write(&path, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+        path.to_string_lossy().to_string()
+    })
+}
+
+async fn rest(mock: &MockExchange) -> anyhow::Result<Arc<Rest>> {
+    Ok(Arc::new(Rest::new(
+        &mock.rest_url(),
+        "apikey",
+        pem(),
+        3000,
+    )?))
+}
+
+async fn add_order(
+    rest: &Rest,
+    path: &str,
+    symbol: &str,
+    price: &str,
+    side: &str,
+    id: u32,
+) -> anyhow::Result<reqwest::Response> {
+    rest.add_order(
+        path,
+        symbol.into(),
+        price.into(),
+        "0.001".into(),
+        side.into(),
+        "LIMIT".into(),
+        "GTC".into(),
+        1,
+        id,
+    )
+    .await
+}
+
+async fn next_event<T: ListenKey + serde::de::DeserializeOwned>(
+    account: &mut Account<T>,
+) -> anyhow::Result<Event> {
+    timeout(Duration::from_secs(5), async {
+        loop {
+            if let Some(Message::Text(s)) = account.process().await? {
+                return Ok(serde_json::from_str::<Event>(&s)?);
+            }
+        }
+    })
+    .await?
+}
+
+async fn next_message<F: Fn(&Value) -> bool>(
+    client: &mut WebSocket,
+    predicate: F,
+) -> anyhow::Result<Value> {
+    timeout(Duration::from_secs(5), async {
+        loop {
+            if let Some(Message::Text(s)) = client.recv().await? {
+                let value: Value = serde_json::from_str(&s)?;
+                if predicate(&value) {
+                    return Ok(value);
+                }
+            }
+        }
+    })
+    .await?
+}
+
+// a paper trading server on the mock with its own db, setup configures it before it starts
+async fn serve<F>(venue: Venue, setup: F) -> anyhow::Result<(MockExchange, String)>
+where
+    F: FnOnce(&mut Application, &mut Market),
+{
+    static DB: AtomicUsize = AtomicUsize::new(0);
+
+    let mock = MockExchange::start().await?;
+    // paper trading needs no key
+    let rest = Arc::new(Rest::public(&mock.rest_url())?);
+    let (stream, info, depth) = match venue {
+        Venue::Spot => (mock.stream_url(), "/api/v3/exchangeInfo", "/api/v3/depth"),
+        Venue::Usdt => (
+            mock.fstream_url(),
+            "/fapi/v1/exchangeInfo",
+            "/fapi/v1/depth",
+        ),
+    };
+    let mut market = Market::new(stream).await?;
+    market.book(rest.clone(), depth);
+    let db = scratch().join(format!("sim-{}.db", DB.fetch_add(1, Ordering::Relaxed)));
+    let trade = SimTrade::new(rest, info, &db.to_string_lossy(), market.feed()).await?;
+
+    let port = std::net::TcpListener::bind("127.0.0.1:0")?
+        .local_addr()?
+        .port();
+    let local = format!("ws://127.0.0.1:{}", port);
+    let mut app = Application::new(&local).await?;
+    setup(&mut app, &mut market);
+    tokio::spawn(app.keep_running(market, trade));
+    Ok((mock, local))
+}
+
+async fn login(local: &str, session_id: u16, trading: bool) -> anyhow::Result<WebSocket> {
+    let mut client = WebSocket::client(local).await?;
+    let login = json!({"id": 1, "method": "login", "params": {"session_id": session_id, "name": "mock", "trading": trading}});
+    client.send(Message::Text(login.to_string())).await?;
+    next_message(&mut client, |v| v["id"] == 1).await?;
+    Ok(client)
+}
+
+#[tokio::test]
+async fn test_spot_order() -> anyhow::Result<()> {
+    let mock = MockExchange::start().await?;
+    let rest = rest(&mock).await?;
+    let mut account =
+        Account::<SpotListenKey>::new(&mock.stream_url(), "/api/v3/userDataStream", rest.clone())
+            .await?;
+
+    // resting order
+    let rsp = add_order(&rest, "/api/v3/order", "BTCUSDT", "59000", "BUY", 1).await?;
+    assert!(rsp.status().is_success());
+    match next_event(&mut account).await? {
+        Event::ExecutionReport(order) => {
+            assert!(matches!(order.X, xcrypto::chat::State::NEW));
+            assert_eq!(order.c, (1u64 << 32 | 1).to_string());
+        }
+        e => panic!("unexpected {:?}", e),
+    }
+    assert_eq!(mock.working_orders().len(), 1);
+
+    // cancel
+    let rsp = rest
+        .cancel("/api/v3/order", "BTCUSDT".into(), 1u64 << 32 | 1)
+        .await?;
+    assert!(rsp.status().is_success());
+    match next_event(&mut account).await? {
+        Event::ExecutionReport(order) => {
+            assert!(matches!(order.X, xcrypto::chat::State::CANCELED));
+            assert_eq!(order.C, (1u64 << 32 | 1).to_string());
+        }
+        e => panic!("unexpected {:?}", e),
+    }
+    assert!(mock.working_orders().is_empty());
+
+    // unknown order
+    let rsp = rest
+        .cancel("/api/v3/order", "BTCUSDT".into(), 1u64 << 32 | 1)
+        .await?;
+    assert_eq!(rsp.json::<Error>().await?.code, -2011);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_reject() -> anyhow::Result<()> {
+    let mock = MockExchange::start().await?;
+    let rest = rest(&mock).await?;
+
+    let rsp = add_order(&rest, "/api/v3/order", "XXXUSDT", "1", "BUY", 1).await?;
+    assert_eq!(rsp.json::<Error>().await?.code, -1121);
+
+    mock.reject_orders(true);
+    let rsp = add_order(&rest, "/api/v3/order", "BTCUSDT", "59000", "BUY", 2).await?;
+    assert_eq!(rsp.json::<Error>().await?.code, -2010);
+
+    mock.reject_orders(false);
+    let rsp = add_order(&rest, "/api/v3/order", "BTCUSDT", "59000", "BUY", 3).await?;
+    assert!(rsp.json::<Error>().await.is_err());
+
+    // unsigned
+    let rsp = rest.post("/api/v3/order", &[], false).await?;
+    assert_eq!(rsp.json::<Error>().await?.code, -1102);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_time_sync() -> anyhow::Result<()> {
+    let mock = MockExchange::start().await?;
+    mock.skew_clock(10000);
+    let rest = rest(&mock).await?;
+
+    let rsp = add_order(&rest, "/api/v3/order", "BTCUSDT", "59000", "BUY", 1).await?;
+    assert_eq!(rsp.json::<Error>().await?.code, -1021);
+
+    // signed requests are stamped with the clock of the exchange once it is sampled
+    timesync::start(&rest, "/api/v3/time", Duration::from_secs(60)).await;
+    let estimate = rest.time_sync().lock().unwrap().estimate().unwrap();
+    assert!((estimate.offset - 10000).abs() <= estimate.rtt + 1);
+    let rsp = add_order(&rest, "/api/v3/order", "BTCUSDT", "59000", "BUY", 2).await?;
+    assert!(rsp.json::<Error>().await.is_err());
+    assert_eq!(mock.working_orders().len(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_usdt_fill() -> anyhow::Result<()> {
+    let mock = MockExchange::start().await?;
+    let rest = rest(&mock).await?;
+    let mut account =
+        Account::<UsdtListenKey>::new(&mock.fstream_url(), "/fapi/v1/listenKey", rest.clone())
+            .await?;
+
+    // marketable limit order fills at the best ask
+    let rsp = add_order(&rest, "/fapi/v1/order", "ETHUSDT", "3001", "BUY", 1).await?;
+    assert!(rsp.status().is_success());
+
+    let mut states = vec![];
+    for _ in 0..2 {
+        match next_event(&mut account).await? {
+            Event::OrderUpdate(OrderUpdate { o, .. }) => states.push(format!("{:?}", o.X)),
+            e => panic!("unexpected {:?}", e),
+        }
+    }
+    assert_eq!(states, ["NEW", "FILLED"]);
+    assert!(mock.working_orders().is_empty());
+
+    // the position follows the fill
+    let update = match next_event(&mut account).await? {
+        Event::AccountUpdate(update) => update,
+        e => panic!("unexpected {:?}", e),
+    };
+    assert_eq!(update.balances()[0].asset, "USDT");
+    let position = &update.positions()[0];
+    assert_eq!(position.symbol, "ethusdt");
+    assert_eq!(position.amount, 0.001);
+    assert_eq!(position.entry_price, 3000.01);
+    assert_eq!(position.margin_type, "cross");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_spot_fill() -> anyhow::Result<()> {
+    let mock = MockExchange::start().await?;
+    let rest = rest(&mock).await?;
+    let mut account =
+        Account::<SpotListenKey>::new(&mock.stream_url(), "/api/v3/userDataStream", rest.clone())
+            .await?;
+
+    let rsp = add_order(&rest, "/api/v3/order", "BTCUSDT", "60001", "BUY", 1).await?;
+    assert!(rsp.status().is_success());
+    for _ in 0..2 {
+        assert!(matches!(
+            next_event(&mut account).await?,
+            Event::ExecutionReport(_)
+        ));
+    }
+
+    // both assets of the symbol follow the fill
+    let update = match next_event(&mut account).await? {
+        Event::OutboundAccountPosition(update) => update,
+        e => panic!("unexpected {:?}", e),
+    };
+    let balances = update.balances();
+    assert_eq!(balances[0].asset, "BTC");
+    assert_eq!(balances[0].free, 1.001);
+    assert_eq!(balances[1].asset, "USDT");
+    assert_eq!(balances[1].free, 100000.0 - 0.001 * 60000.01);
+
+    // the account snapshot agrees with the stream
+    let rsp = rest.get("/api/v3/account", &[], true).await?;
+    let account: Value = rsp.json().await?;
+    let balances: Vec<SpotBalance> = serde_json::from_value(account["balances"].clone())?;
+    let btc = balances
+        .iter()
+        .find(|balance| balance.asset == "BTC")
+        .unwrap();
+    assert_eq!(btc.balance(0).wallet, 1.001);
+
+    let rsp = rest.get("/api/v3/account", &[], false).await?;
+    assert_eq!(rsp.json::<Error>().await?.code, -1102);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_reconcile() -> anyhow::Result<()> {
+    let mock = MockExchange::start().await?;
+    let rest = rest(&mock).await?;
+    let mut account =
+        Account::<UsdtListenKey>::new(&mock.fstream_url(), "/fapi/v1/listenKey", rest.clone())
+            .await?;
+
+    // the fill never reaches a session, as if the account stream was down
+    add_order(&rest, "/fapi/v1/order", "ETHUSDT", "3001", "BUY", 1).await?;
+    for _ in 0..3 {
+        next_event(&mut account).await?;
+    }
+
+    let rsp = rest.get("/fapi/v2/positionRisk", &[], true).await?;
+    let risks: Vec<PositionRisk> = rsp.json().await?;
+    assert_eq!(risks.len(), 3);
+    let exchange: Nets = risks
+        .iter()
+        .map(|risk| {
+            let amount = risk.positionAmt.parse().unwrap_or_default();
+            (risk.symbol.to_lowercase(), amount)
+        })
+        .collect();
+
+    let posdb = Arc::new(xcrypto::position::PositionDB::new("reconcile.db").await?);
+    let config: ReconcileConfig = serde_json::from_value(json!({"session_id": 9}))?;
+    assert_eq!(config.interval, 60);
+    let reconciler = Reconciler::new(config);
+    let mut sessions = HashMap::new();
+
+    let discrepancies = reconciler
+        .reconcile(&exchange, &posdb, &mut sessions, |_| true)
+        .await?;
+    assert_eq!(discrepancies.len(), 1);
+    assert_eq!(discrepancies[0].symbol, "ethusdt");
+    assert_eq!(discrepancies[0].diff(), 0.001);
+
+    // the reserved session holds the missed fill from now on
+    assert_eq!(sessions[&9].positions()["ethusdt"].net, 0.001);
+    let discrepancies = reconciler
+        .reconcile(&exchange, &posdb, &mut sessions, |_| true)
+        .await?;
+    assert!(discrepancies.is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_accounts() -> anyhow::Result<()> {
+    let mock = MockExchange::start().await?;
+    let url = mock.fstream_url();
+    let account = |rest| Account::<UsdtListenKey>::new(&url, "/fapi/v1/listenKey", rest);
+
+    let mut accounts = Accounts::new(account(rest(&mock).await?).await?);
+    assert_eq!(
+        accounts.add("sub", account(rest(&mock).await?).await?, &[3, 5])?,
+        1
+    );
+    assert_eq!(accounts.count(), 2);
+    assert_eq!(accounts.index(3), 1);
+    assert_eq!(accounts.index(1), 0);
+    assert_eq!(accounts.name(accounts.index(5)), "sub");
+    assert!(accounts
+        .add("sub", account(rest(&mock).await?).await?, &[7])
+        .is_err());
+    assert!(accounts
+        .add("other", account(rest(&mock).await?).await?, &[5])
+        .is_err());
+
+    // the reserved session has to trade on the account it reconciles
+    let config: ReconcileConfig = serde_json::from_value(json!({"session_id": 3}))?;
+    assert!(accounts.check_reconcile(1, &config).is_ok());
+    assert!(accounts.check_reconcile(0, &config).is_err());
+
+    // the mock pushes user data to every listen key
+    add_order(
+        &accounts.rest(3),
+        "/fapi/v1/order",
+        "ETHUSDT",
+        "2999",
+        "BUY",
+        1,
+    )
+    .await?;
+    let (_, msg) = timeout(Duration::from_secs(5), accounts.process()).await?;
+    match msg? {
+        Some(Message::Text(s)) => assert!(matches!(
+            serde_json::from_str::<Event>(&s)?,
+            Event::OrderUpdate(_)
+        )),
+        msg => panic!("unexpected {:?}", msg),
+    }
+    assert!(!accounts.disconnected());
+    let (reconnected, errors) = accounts.reconnect().await;
+    assert!(reconnected.is_empty() && errors.is_empty());
+
+    // an account on another exchange goes down alone
+    let other = MockExchange::start().await?;
+    let down = Account::<UsdtListenKey>::new(
+        &other.fstream_url(),
+        "/fapi/v1/listenKey",
+        rest(&other).await?,
+    )
+    .await?;
+    accounts.add("down", down, &[9])?;
+    let third = MockExchange::start().await?;
+    let back = Account::<UsdtListenKey>::new(
+        &third.fstream_url(),
+        "/fapi/v1/listenKey",
+        rest(&third).await?,
+    )
+    .await?;
+    accounts.add("back", back, &[10])?;
+    // a stream down answers right away, the later one goes first and the loop yields for
+    // the other to be read
+    for (mock, session_id) in [(&third, 10), (&other, 9)] {
+        mock.disconnect();
+        timeout(Duration::from_secs(5), async {
+            while !accounts.session_disconnected(session_id) {
+                let _ = accounts.process().await;
+                tokio::task::yield_now().await;
+            }
+        })
+        .await?;
+    }
+    assert!(accounts.disconnected());
+    assert!(!accounts.session_disconnected(1));
+    assert!(!accounts.session_disconnected(3));
+
+    // an account failing to reconnect doesn't keep the next one down
+    let rest = accounts.get(2).rest();
+    rest.rate_limits().lock().unwrap().ban(rest.now()? + 60000);
+    tokio::time::sleep(Duration::from_secs(10)).await;
+    let (reconnected, errors) = accounts.reconnect().await;
+    assert_eq!(reconnected, [3]);
+    assert!(join_errors(errors)
+        .unwrap_err()
+        .to_string()
+        .contains("account down"));
+    assert!(accounts.session_disconnected(9));
+    assert!(!accounts.session_disconnected(10));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_journal() -> anyhow::Result<()> {
+    let mock = MockExchange::start().await?;
+    let rest = rest(&mock).await?;
+    let mut account =
+        Account::<UsdtListenKey>::new(&mock.fstream_url(), "/fapi/v1/listenKey", rest.clone())
+            .await?;
+
+    let posdb = Arc::new(xcrypto::position::PositionDB::new("journal.db").await?);
+    let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut session = Session::new(4, posdb.clone(), tx).await?;
+
+    add_order(&rest, "/fapi/v1/order", "ETHUSDT", "3001", "BUY", 1).await?;
+    for _ in 0..2 {
+        match next_event(&mut account).await? {
+            Event::OrderUpdate(order) => session.on_order(&order)?,
+            e => panic!("unexpected {:?}", e),
+        }
+    }
+
+    // fills are written in the background
+    let mut req: TradeReq = serde_json::from_value(json!({"session_id": 4}))?;
+    let trades = timeout(Duration::from_secs(5), async {
+        loop {
+            match posdb.get_trades(&req).await {
+                Ok(trades) if trades.is_empty() => tokio::task::yield_now().await,
+                trades => return trades,
+            }
+        }
+    })
+    .await??;
+    assert_eq!(trades.len(), 1);
+    assert_eq!(trades[0].symbol, "ethusdt");
+    assert_eq!(trades[0].price, 3000.01);
+    assert_eq!(trades[0].quantity, 0.001);
+    assert!(!trades[0].making);
+
+    // the journal survives a restart next to the positions
+    let posdb = xcrypto::position::PositionDB::new("journal.db").await?;
+    assert_eq!(posdb.get_positions(4).unwrap()["ethusdt"].net, 0.001);
+    req.symbol = Some("ETHUSDT".into());
+    req.start = Some(trades[0].trade_time);
+    assert_eq!(posdb.get_trades(&req).await?.len(), 1);
+    req.start = Some(trades[0].trade_time + 1);
+    assert!(posdb.get_trades(&req).await?.is_empty());
+    req.start = None;
+    req.symbol = Some("btcusdt".into());
+    assert!(posdb.get_trades(&req).await?.is_empty());
+    Ok(())
+}
+
+fn resting(symbol: &str, price: f64) -> xcrypto::chat::Order {
+    use xcrypto::chat::{OrderType, Side, State, Tif};
+    Order::new(
+        1,
+        symbol.into(),
+        Side::BUY,
+        State::NEW,
+        OrderType::LIMIT,
+        Tif::GTC,
+        0.001,
+        price,
+    )
+}
+
+#[tokio::test]
+async fn test_spot_amend() -> anyhow::Result<()> {
+    let mock = MockExchange::start().await?;
+    let rest = rest(&mock).await?;
+    let mut account =
+        Account::<SpotListenKey>::new(&mock.stream_url(), "/api/v3/userDataStream", rest.clone())
+            .await?;
+
+    add_order(&rest, "/api/v3/order", "BTCUSDT", "59000", "BUY", 1).await?;
+    next_event(&mut account).await?;
+
+    let order = resting("btcusdt", 59000.0);
+    let path = "/api/v3/order/cancelReplace";
+    let rsp = rest
+        .cancel_replace(path, &order, 1, "59500".into(), "0.002".into())
+        .await?;
+    assert!(rsp.status().is_success());
+
+    // the replacing order reuses the client order id
+    let mut states = vec![];
+    for _ in 0..2 {
+        match next_event(&mut account).await? {
+            Event::ExecutionReport(report) => {
+                let order: Order = report.into();
+                assert_eq!(order.internal_id, 1);
+                states.push((format!("{:?}", order.state), order.price));
+            }
+            e => panic!("unexpected {:?}", e),
+        }
+    }
+    assert_eq!(
+        states,
+        [
+            ("CANCELED".to_string(), 59000.0),
+            ("NEW".to_string(), 59500.0)
+        ]
+    );
+    assert_eq!(mock.working_orders()[0].quantity, 0.002);
+
+    // the replacing order is rejected after the cancel
+    let rsp = rest
+        .cancel_replace(path, &order, 1, "59500".into(), "0".into())
+        .await?;
+    let rsp: Value = rsp.json().await?;
+    assert_eq!(rsp["code"], -2021);
+    assert_eq!(rsp["data"]["cancelResult"], "SUCCESS");
+    assert!(mock.working_orders().is_empty());
+
+    let rsp = rest
+        .cancel_replace(path, &order, 1, "59500".into(), "0.001".into())
+        .await?;
+    assert_eq!(rsp.json::<Error>().await?.code, -2022);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_usdt_amend() -> anyhow::Result<()> {
+    let mock = MockExchange::start().await?;
+    let rest = rest(&mock).await?;
+    let mut account =
+        Account::<UsdtListenKey>::new(&mock.fstream_url(), "/fapi/v1/listenKey", rest.clone())
+            .await?;
+
+    add_order(&rest, "/fapi/v1/order", "ETHUSDT", "2900", "BUY", 1).await?;
+    next_event(&mut account).await?;
+
+    let order = resting("ethusdt", 2900.0);
+    let rsp = rest
+        .modify("/fapi/v1/order", &order, 1, "2950".into(), "0.002".into())
+        .await?;
+    assert!(rsp.status().is_success());
+    match next_event(&mut account).await? {
+        Event::OrderUpdate(update) => {
+            let order: Order = update.into();
+            assert!(matches!(order.state, xcrypto::chat::State::NEW));
+            assert_eq!(order.price, 2950.0);
+            assert_eq!(order.quantity, 0.002);
+        }
+        e => panic!("unexpected {:?}", e),
+    }
+
+    // amended through the touch
+    rest.modify("/fapi/v1/order", &order, 1, "3001".into(), "0.002".into())
+        .await?;
+    let mut states = vec![];
+    for _ in 0..2 {
+        match next_event(&mut account).await? {
+            Event::OrderUpdate(OrderUpdate { o, .. }) => states.push(format!("{:?}", o.X)),
+            e => panic!("unexpected {:?}", e),
+        }
+    }
+    assert_eq!(states, ["NEW", "FILLED"]);
+    assert!(mock.working_orders().is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_ws_api() -> anyhow::Result<()> {
+    let mock = MockExchange::start().await?;
+    let rest = rest(&mock).await?;
+    let mut account =
+        Account::<SpotListenKey>::new(&mock.stream_url(), "/api/v3/userDataStream", rest.clone())
+            .await?;
+    let wsapi = WsApi::new(&mock.ws_api_url(), rest.clone());
+
+    // requests wait for the logon
+    let place = |id: u32| {
+        wsapi.add_order(
+            "BTCUSDT".into(),
+            "59000".into(),
+            "0.001".into(),
+            "BUY".into(),
+            "LIMIT".into(),
+            "GTC".into(),
+            1,
+            id,
+        )
+    };
+    let rsp = place(1).await?;
+    assert_eq!(rsp["status"], "NEW");
+    assert_eq!(rsp["clientOrderId"], (1u64 << 32 | 1).to_string());
+    assert!(matches!(
+        next_event(&mut account).await?,
+        Event::ExecutionReport(_)
+    ));
+
+    let order = resting("btcusdt", 59000.0);
+    let rsp = wsapi
+        .cancel_replace(&order, 1, "59500".into(), "0.002".into())
+        .await?;
+    assert_eq!(rsp["cancelResult"], "SUCCESS");
+    assert_eq!(mock.working_orders()[0].price, 59500.0);
+
+    wsapi.cancel("BTCUSDT".into(), 1u64 << 32 | 1).await?;
+    assert!(mock.working_orders().is_empty());
+    let rsp = wsapi.cancel("BTCUSDT".into(), 1u64 << 32 | 1).await?;
+    assert_eq!(serde_json::from_value::<Error>(rsp)?.code, -2011);
+
+    mock.reject_orders(true);
+    let rsp = place(2).await?;
+    assert_eq!(serde_json::from_value::<Error>(rsp)?.code, -2010);
+    mock.reject_orders(false);
+
+    // requests fail until the session is logged on again
+    mock.disconnect();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(place(3).await.is_err());
+    tokio::time::sleep(Duration::from_secs(11)).await;
+    assert_eq!(place(4).await?["status"], "NEW");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_ws_api_timeout() -> anyhow::Result<()> {
+    let mock = MockExchange::start().await?;
+    let rest = rest(&mock).await?;
+    let wsapi =
+        WsApi::new(&mock.ws_api_url(), rest.clone()).with_timeout(Duration::from_millis(500));
+    let place = |id: u32| {
+        wsapi.add_order(
+            "BTCUSDT".into(),
+            "59000".into(),
+            "0.001".into(),
+            "BUY".into(),
+            "LIMIT".into(),
+            "GTC".into(),
+            1,
+            id,
+        )
+    };
+    assert_eq!(place(1).await?["status"], "NEW");
+
+    // the connection stays up, the answer never comes
+    mock.mute_ws_api(true);
+    let e = place(2).await.unwrap_err();
+    assert!(e.to_string().contains("timed out"));
+    let stats = rest.latency().lock().unwrap().stats();
+    assert_eq!(
+        (stats[0].endpoint.as_str(), stats[0].count, stats[0].errors),
+        ("order.place", 1, 1)
+    );
+
+    mock.mute_ws_api(false);
+    assert_eq!(place(3).await?["status"], "NEW");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_rate_limits() -> anyhow::Result<()> {
+    let mock = MockExchange::start().await?;
+    mock.order_limit(4);
+    let rest = rest(&mock).await?;
+
+    let rsp = rest.get("/api/v3/exchangeInfo", &[], false).await?;
+    let info: Value = rsp.json().await?;
+    let limits: Vec<ExchangeLimit> = serde_json::from_value(info["rateLimits"].clone())?;
+    rest.rate_limits().lock().unwrap().set_limits(&limits);
+
+    let usage = |rest: &Rest| {
+        let now = mock::now();
+        rest.rate_limits()
+            .lock()
+            .unwrap()
+            .usage(now)
+            .into_iter()
+            .map(|usage| (usage.rate_limit_type, usage.used, usage.limit))
+            .collect::<Vec<_>>()
+    };
+
+    // the headers of rest responses
+    add_order(&rest, "/api/v3/order", "BTCUSDT", "59000", "BUY", 1).await?;
+    add_order(&rest, "/api/v3/order", "BTCUSDT", "59000", "BUY", 2).await?;
+    assert_eq!(
+        usage(&rest),
+        [("ORDERS".into(), 2, 4), ("REQUEST_WEIGHT".into(), 3, 6000)]
+    );
+
+    // the rateLimits of websocket api responses
+    let wsapi = WsApi::new(&mock.ws_api_url(), rest.clone());
+    let rsp = wsapi
+        .add_order(
+            "BTCUSDT".into(),
+            "59000".into(),
+            "0.001".into(),
+            "BUY".into(),
+            "LIMIT".into(),
+            "GTC".into(),
+            1,
+            3,
+        )
+        .await?;
+    assert_eq!(rsp["status"], "NEW");
+    assert_eq!(
+        usage(&rest),
+        [("ORDERS".into(), 3, 4), ("REQUEST_WEIGHT".into(), 4, 6000)]
+    );
+
+    // nothing is sent while banned
+    rest.rate_limits().lock().unwrap().ban(mock::now() + 60000);
+    assert!(rest.get("/api/v3/exchangeInfo", &[], false).await.is_err());
+    assert!(wsapi
+        .cancel("BTCUSDT".into(), 1u64 << 32 | 3)
+        .await
+        .is_err());
+    assert_eq!(mock.working_orders().len(), 3);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_ip_limits() -> anyhow::Result<()> {
+    let mock = MockExchange::start().await?;
+    let client = HttpConfig::default().client()?;
+    let rest = || -> anyhow::Result<Arc<Rest>> {
+        Ok(Arc::new(
+            Rest::new(&mock.rest_url(), "apikey", pem(), 3000)?.with_client(client.clone()),
+        ))
+    };
+    let (first, second) = (rest()?, rest()?);
+    let other = Arc::new(
+        Rest::new(&mock.rest_url(), "apikey", pem(), 3000)?
+            .with_client(HttpConfig::default().client()?),
+    );
+
+    let rsp = first.get("/api/v3/exchangeInfo", &[], false).await?;
+    let info: Value = rsp.json().await?;
+    let limits: Vec<ExchangeLimit> = serde_json::from_value(info["rateLimits"].clone())?;
+    for rest in [&first, &second, &other] {
+        rest.rate_limits().lock().unwrap().set_limits(&limits);
+    }
+    let usage = |rest: &Rest| {
+        rest.rate_limits()
+            .lock()
+            .unwrap()
+            .usage(mock::now())
+            .into_iter()
+            .map(|usage| (usage.rate_limit_type, usage.used))
+            .collect::<Vec<_>>()
+    };
+
+    // the weight is the ip's, the orders the account's
+    add_order(&first, "/api/v3/order", "BTCUSDT", "59000", "BUY", 1).await?;
+    assert_eq!(
+        usage(&second),
+        [("ORDERS".into(), 0), ("REQUEST_WEIGHT".into(), 2)]
+    );
+    assert_eq!(
+        usage(&other),
+        [("ORDERS".into(), 0), ("REQUEST_WEIGHT".into(), 0)]
+    );
+
+    // a ban of one account stops every other one sending from the ip
+    first.rate_limits().lock().unwrap().ban(mock::now() + 60000);
+    assert!(second
+        .get("/api/v3/exchangeInfo", &[], false)
+        .await
+        .is_err());
+    assert!(other.get("/api/v3/exchangeInfo", &[], false).await.is_ok());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_latency() -> anyhow::Result<()> {
+    let mock = MockExchange::start().await?;
+    let client = HttpConfig {
+        http2: true,
+        ..Default::default()
+    }
+    .client()?;
+    let rest = Arc::new(Rest::new(&mock.rest_url(), "apikey", pem(), 3000)?.with_client(client));
+
+    add_order(&rest, "/api/v3/order", "BTCUSDT", "59000", "BUY", 1).await?;
+    add_order(&rest, "/api/v3/order", "BTCUSDT", "59000", "BUY", 2).await?;
+    let wsapi = WsApi::new(&mock.ws_api_url(), rest.clone());
+    wsapi.cancel("BTCUSDT".into(), 1u64 << 32 | 1).await?;
+    mock.disconnect();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(wsapi
+        .cancel("BTCUSDT".into(), 1u64 << 32 | 2)
+        .await
+        .is_err());
+
+    let stats = rest.latency().lock().unwrap().stats();
+    let counts: Vec<_> = stats
+        .iter()
+        .map(|stats| (stats.endpoint.as_str(), stats.count, stats.errors))
+        .collect();
+    assert_eq!(
+        counts,
+        [("POST /api/v3/order", 2, 0), ("order.cancel", 1, 1)]
+    );
+    let order = &stats[0];
+    assert!(order.min <= order.p50 && order.p50 <= order.max);
+    assert_eq!(order.buckets.iter().sum::<u64>(), 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_usdt_ws_api() -> anyhow::Result<()> {
+    let mock = MockExchange::start().await?;
+    let rest = rest(&mock).await?;
+    let wsapi = WsApi::new(&mock.fws_api_url(), rest.clone());
+
+    let rsp = wsapi
+        .add_order(
+            "ETHUSDT".into(),
+            "2900".into(),
+            "0.001".into(),
+            "BUY".into(),
+            "LIMIT".into(),
+            "GTC".into(),
+            1,
+            1,
+        )
+        .await?;
+    assert_eq!(rsp["status"], "NEW");
+
+    let order = resting("ethusdt", 2900.0);
+    let rsp = wsapi
+        .modify(&order, 1, "2950".into(), "0.002".into())
+        .await?;
+    assert_eq!(rsp["price"], "2950");
+    assert_eq!(mock.working_orders()[0].quantity, 0.002);
+
+    // the cancel replace of spot isn't served by the futures api
+    let rsp = wsapi
+        .cancel_replace(&order, 1, "2950".into(), "0.001".into())
+        .await?;
+    assert_eq!(serde_json::from_value::<Error>(rsp)?.code, -1000);
+
+    // hmac keys can't log on, every request carries its signature
+    let secret = std::env::temp_dir().join(format!("mock-{}-secret", std::process::id()));
+    std::fs::write(&secret, "secret")?;
+    let hmac = Rest::with_sign(
+        &mock.rest_url(),
+        "apikey",
+        secret.to_str().unwrap(),
+        SignMethod::Hmac,
+        3000,
+    )?;
+    let wsapi = WsApi::new(&mock.fws_api_url(), Arc::new(hmac));
+    let rsp = wsapi.cancel("ETHUSDT".into(), 1u64 << 32 | 1).await?;
+    assert_eq!(rsp["status"], "CANCELED");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_usdt_batch() -> anyhow::Result<()> {
+    let mock = MockExchange::start().await?;
+    let rest = rest(&mock).await?;
+
+    let params = |symbol: &str, price: &str, id: u64| {
+        xcrypto::rest::order_params(
+            symbol.into(),
+            price.into(),
+            "0.002".into(),
+            "BUY".into(),
+            "LIMIT".into(),
+            "GTC".into(),
+            id,
+        )
+    };
+    let rsp = rest
+        .batch_orders(
+            "/fapi/v1/batchOrders",
+            &[
+                params("ETHUSDT", "2900", 1),
+                params("XXXUSDT", "2900", 2),
+                params("ETHUSDT", "2800", 3),
+            ],
+        )
+        .await?;
+    let items: Vec<Value> = rsp.json().await?;
+    assert_eq!(items[0]["status"], "NEW");
+    assert_eq!(items[1]["code"], -1121);
+    assert_eq!(items[2]["clientOrderId"], "3");
+    assert_eq!(mock.working_orders().len(), 2);
+
+    let rsp = rest
+        .batch_cancel("/fapi/v1/batchOrders", "ETHUSDT".into(), &[1, 2, 3])
+        .await?;
+    let items: Vec<Value> = rsp.json().await?;
+    assert_eq!(items[0]["status"], "CANCELED");
+    assert_eq!(items[1]["code"], -2011);
+    assert_eq!(items[2]["status"], "CANCELED");
+    assert!(mock.working_orders().is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_disconnect() -> anyhow::Result<()> {
+    let mock = MockExchange::start().await?;
+    let rest = rest(&mock).await?;
+    let mut market = Market::new(mock.stream_url()).await?;
+    let mut account =
+        Account::<SpotListenKey>::new(&mock.stream_url(), "/api/v3/userDataStream", rest.clone())
+            .await?;
+
+    mock.disconnect();
+    timeout(Duration::from_secs(5), async {
+        while !market.process().await? {}
+        while !account.disconnected() {
+            account.process().await?;
+        }
+        anyhow::Ok(())
+    })
+    .await??;
+
+    // the account retries every 10 seconds
+    tokio::time::sleep(Duration::from_secs(10)).await;
+    account.reconnect().await?;
+    assert!(!account.disconnected());
+
+    add_order(&rest, "/api/v3/order", "BTCUSDT", "59000", "BUY", 1).await?;
+    assert!(matches!(
+        next_event(&mut account).await?,
+        Event::ExecutionReport(_)
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_handler_process() -> anyhow::Result<()> {
+    let (_mock, local) = serve(Venue::Spot, |_, _| ()).await?;
+
+    let mut client = login(&local, 1, true).await?;
+
+    let subscribe = json!({"id": 2, "method": "subscribe", "params": ["btcusdt@bbo"]});
+    client.send(Message::Text(subscribe.to_string())).await?;
+    let depth = next_message(&mut client, |v| v["stream"] == "btcusdt@bbo").await?;
+    assert_eq!(depth["asks"][0]["price"], 60000.01);
+
+    let order = json!({"id": 3, "method": "order", "params": {
+        "id": 1,
+        "symbol": "btcusdt",
+        "price": 60001.0,
+        "quantity": 0.001,
+        "side": "BUY",
+        "order_type": "LIMIT",
+        "tif": "GTC",
+        "session_id": 1
+    }});
+    client.send(Message::Text(order.to_string())).await?;
+    let order = next_message(&mut client, |v| v["state"] == "FILLED").await?;
+    assert_eq!(order["internal_id"], 1);
+    assert_eq!(order["trade_price"], 60000.01);
+
+    let get_orders = json!({"id": 4, "method": "get_orders", "params": {"session_id": 1}});
+    client.send(Message::Text(get_orders.to_string())).await?;
+    let rsp = next_message(&mut client, |v| v["id"] == 4).await?;
+    assert_eq!(rsp["result"]["open"], json!([]));
+    assert_eq!(rsp["result"]["finished"][0]["internal_id"], 1);
+    assert_eq!(rsp["result"]["finished"][0]["state"], "FILLED");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_risk() -> anyhow::Result<()> {
+    let config: RiskConfig = serde_json::from_value(json!({
+        "max_notional": 1000.0,
+        "max_position": 0.01,
+        "max_open_orders": 1,
+        "price_band": 0.01
+    }))?;
+    let (_mock, local) = serve(Venue::Spot, |app, market| {
+        app.risk(Risk::new(config, market.bbo()))
+    })
+    .await?;
+
+    let mut client = login(&local, 2, true).await?;
+
+    let subscribe = json!({"id": 2, "method": "subscribe", "params": ["btcusdt@bbo"]});
+    client.send(Message::Text(subscribe.to_string())).await?;
+    next_message(&mut client, |v| v["stream"] == "btcusdt@bbo").await?;
+
+    // no bbo of an unsubscribed symbol, notional, position, price band, then a resting order
+    // filling up the open orders
+    let cases = [
+        ("ethusdt", 3000.0, 0.001, Some(-20006)),
+        ("btcusdt", 60001.0, 0.02, Some(-20001)),
+        ("btcusdt", 30000.0, 0.02, Some(-20002)),
+        ("btcusdt", 50000.0, 0.001, Some(-20004)),
+        ("btcusdt", 59900.0, 0.001, None),
+        ("btcusdt", 59900.0, 0.001, Some(-20003)),
+    ];
+    for (id, (symbol, price, quantity, code)) in cases.into_iter().enumerate() {
+        let id = id as i64 + 3;
+        let order = json!({"id": id, "method": "order", "params": {
+            "id": id,
+            "symbol": symbol,
+            "price": price,
+            "quantity": quantity,
+            "side": "BUY",
+            "order_type": "LIMIT",
+            "tif": "GTC",
+            "session_id": 2
+        }});
+        client.send(Message::Text(order.to_string())).await?;
+
+        match code {
+            Some(code) => {
+                let rsp = next_message(&mut client, |v| v["id"] == id).await?;
+                let e: Error = serde_json::from_value(rsp["result"].clone())?;
+                assert_eq!(e.code, code);
+                let order = next_message(&mut client, |v| v["internal_id"] == id).await?;
+                assert_eq!(order["state"], "REJECTED");
+            }
+            None => {
+                let order = next_message(&mut client, |v| v["internal_id"] == id).await?;
+                assert_eq!(order["state"], "NEW");
+            }
+        }
+    }
+
+    // the server never saw it, say placed before a restart
+    let amend = json!({"id": 9, "method": "amend", "params": {
+        "symbol": "btcusdt",
+        "session_id": 2,
+        "order_id": 99,
+        "price": 59900.0,
+        "quantity": 0.001
+    }});
+    client.send(Message::Text(amend.to_string())).await?;
+    let rsp = next_message(&mut client, |v| v["id"] == 9).await?;
+    let e: Error = serde_json::from_value(rsp["result"].clone())?;
+    assert_eq!(e.code, xcrypto::error::UNKNOWN_ORDER);
+    Ok(())
+}
+
+// fills since startup and resting orders count against max_position
+#[tokio::test]
+async fn test_risk_position() -> anyhow::Result<()> {
+    let config: RiskConfig = serde_json::from_value(json!({"max_position": 0.003}))?;
+    let (_mock, local) = serve(Venue::Spot, |app, market| {
+        app.risk(Risk::new(config, market.bbo()))
+    })
+    .await?;
+    let mut client = login(&local, 11, true).await?;
+
+    let subscribe = json!({"id": 2, "method": "subscribe", "params": ["btcusdt@bbo"]});
+    client.send(Message::Text(subscribe.to_string())).await?;
+    next_message(&mut client, |v| v["stream"] == "btcusdt@bbo").await?;
+
+    // filled, resting, over the limit with the resting one, reducing
+    let cases = [
+        (60001.0, 0.002, "BUY", "FILLED"),
+        (59000.0, 0.001, "BUY", "NEW"),
+        (59000.0, 0.001, "BUY", "REJECTED"),
+        (61000.0, 0.001, "SELL", "NEW"),
+    ];
+    for (id, (price, quantity, side, state)) in cases.into_iter().enumerate() {
+        let id = id as i64 + 3;
+        let order = json!({"id": id, "method": "order", "params": {
+            "id": id,
+            "symbol": "btcusdt",
+            "price": price,
+            "quantity": quantity,
+            "side": side,
+            "order_type": "LIMIT",
+            "tif": "GTC",
+            "session_id": 11
+        }});
+        client.send(Message::Text(order.to_string())).await?;
+        let order = next_message(&mut client, |v| {
+            v["internal_id"] == id && v["state"] == state
+        })
+        .await?;
+        assert_eq!(order["quantity"], quantity);
+    }
+
+    let get =
+        json!({"id": 7, "method": "get_positions", "params": {"session_id": 11, "symbols": []}});
+    client.send(Message::Text(get.to_string())).await?;
+    let rsp = next_message(&mut client, |v| v["id"] == 7).await?;
+    assert_eq!(rsp["result"]["positions"][0]["net"], 0.002);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_cancel_on_disconnect() -> anyhow::Result<()> {
+    let (_mock, local) = serve(Venue::Spot, |app, _| {
+        app.cancel_on_disconnect(3, Duration::ZERO);
+        app.cancel_on_disconnect(4, Duration::from_secs(60));
+    })
+    .await?;
+
+    let get_orders = |session_id: u16| json!({"id": 3, "method": "get_orders", "params": {"session_id": session_id}});
+
+    for session_id in [3, 4] {
+        let mut client = login(&local, session_id, true).await?;
+
+        // rests far below the mock bbo
+        let order = json!({"id": 2, "method": "order", "params": {
+            "id": 1,
+            "symbol": "btcusdt",
+            "price": 50000.0,
+            "quantity": 0.001,
+            "side": "BUY",
+            "order_type": "LIMIT",
+            "tif": "GTC",
+            "session_id": session_id
+        }});
+        client.send(Message::Text(order.to_string())).await?;
+        next_message(&mut client, |v| v["state"] == "NEW").await?;
+        client.close(None).await?;
+    }
+
+    // wait for the server to notice both disconnects
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    for (session_id, open, finished) in [(3, 0, 1), (4, 1, 0)] {
+        let mut client = login(&local, session_id, true).await?;
+
+        client
+            .send(Message::Text(get_orders(session_id).to_string()))
+            .await?;
+        let rsp = next_message(&mut client, |v| v["id"] == 3).await?;
+        let result = &rsp["result"];
+        assert_eq!(result["open"].as_array().map(Vec::len), Some(open));
+        assert_eq!(result["finished"].as_array().map(Vec::len), Some(finished));
+        if finished > 0 {
+            assert_eq!(result["finished"][0]["state"], "CANCELED");
+        }
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_handler_amend() -> anyhow::Result<()> {
+    let (_mock, local) = serve(Venue::Spot, |_, _| ()).await?;
+
+    let mut client = login(&local, 5, true).await?;
+
+    let subscribe = json!({"id": 2, "method": "subscribe", "params": ["btcusdt@bbo"]});
+    client.send(Message::Text(subscribe.to_string())).await?;
+    next_message(&mut client, |v| v["stream"] == "btcusdt@bbo").await?;
+
+    let order = json!({"id": 3, "method": "order", "params": {
+        "id": 1,
+        "symbol": "btcusdt",
+        "price": 50000.0,
+        "quantity": 0.001,
+        "side": "BUY",
+        "order_type": "LIMIT",
+        "tif": "GTC",
+        "session_id": 5
+    }});
+    client.send(Message::Text(order.to_string())).await?;
+    next_message(&mut client, |v| v["state"] == "NEW").await?;
+
+    let amend = |id: i64, price: f64| {
+        json!({"id": id, "method": "amend", "params": {
+            "symbol": "btcusdt",
+            "session_id": 5,
+            "order_id": 1,
+            "price": price,
+            "quantity": 0.002
+        }})
+    };
+    client
+        .send(Message::Text(amend(4, 55000.0).to_string()))
+        .await?;
+    let order = next_message(&mut client, |v| v["state"] == "NEW").await?;
+    assert_eq!(order["price"], 55000.0);
+    assert_eq!(order["quantity"], 0.002);
+
+    client
+        .send(Message::Text(amend(5, 60001.0).to_string()))
+        .await?;
+    let order = next_message(&mut client, |v| v["state"] == "FILLED").await?;
+    assert_eq!(order["internal_id"], 1);
+    assert_eq!(order["trade_price"], 60000.01);
+
+    // done, the amend is answered even without risk checks
+    client
+        .send(Message::Text(amend(6, 55000.0).to_string()))
+        .await?;
+    let rsp = next_message(&mut client, |v| v["id"] == 6).await?;
+    assert_eq!(rsp["result"]["code"], xcrypto::error::UNKNOWN_ORDER);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_handler_batch() -> anyhow::Result<()> {
+    let (_mock, local) = serve(Venue::Spot, |app, market| {
+        app.risk(Risk::new(
+            RiskConfig {
+                max_notional: Some(1000.0),
+                ..Default::default()
+            },
+            market.bbo(),
+        ));
+    })
+    .await?;
+
+    let mut client = login(&local, 6, true).await?;
+
+    let subscribe = json!({"id": 2, "method": "subscribe", "params": ["btcusdt@bbo"]});
+    client.send(Message::Text(subscribe.to_string())).await?;
+    next_message(&mut client, |v| v["stream"] == "btcusdt@bbo").await?;
+
+    let order = |id: u32, symbol: &str, quantity: f64| {
+        json!({
+            "id": id,
+            "symbol": symbol,
+            "price": 50000.0,
+            "quantity": quantity,
+            "side": "BUY",
+            "order_type": "LIMIT",
+            "tif": "GTC",
+            "session_id": 6
+        })
+    };
+    let batch = json!({"id": 3, "method": "batch_order", "params": [
+        order(1, "btcusdt", 0.001),
+        order(2, "xxxusdt", 0.001),
+        order(3, "btcusdt", 1.0),
+        order(4, "btcusdt", 0.002),
+    ]});
+    client.send(Message::Text(batch.to_string())).await?;
+    let rsp = next_message(&mut client, |v| v["id"] == 3).await?;
+    let results = rsp["result"]["results"].as_array().unwrap();
+    let codes: Vec<_> = results
+        .iter()
+        .map(|r| r["code"].as_i64().unwrap())
+        .collect();
+    let ids: Vec<_> = results
+        .iter()
+        .map(|r| r["order_id"].as_i64().unwrap())
+        .collect();
+    assert_eq!(ids, [1, 2, 3, 4]);
+    assert_eq!(
+        codes,
+        [
+            0,
+            xcrypto::error::INVALID_SYMBOL as i64,
+            xcrypto::error::MAX_NOTIONAL as i64,
+            0
+        ]
+    );
+
+    let get = json!({"id": 4, "method": "get_orders", "params": {"session_id": 6}});
+    client.send(Message::Text(get.to_string())).await?;
+    let rsp = next_message(&mut client, |v| v["id"] == 4).await?;
+    assert_eq!(rsp["result"]["open"].as_array().unwrap().len(), 2);
+
+    let cancel =
+        |order_id: u32| json!({"symbol": "btcusdt", "session_id": 6, "order_id": order_id});
+    let batch =
+        json!({"id": 5, "method": "batch_cancel", "params": [cancel(1), cancel(3), cancel(4)]});
+    client.send(Message::Text(batch.to_string())).await?;
+    let rsp = next_message(&mut client, |v| v["id"] == 5).await?;
+    let codes: Vec<_> = rsp["result"]["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| r["code"].as_i64().unwrap())
+        .collect();
+    assert_eq!(codes, [0, xcrypto::error::UNDEF_ERROR as i64, 0]);
+
+    client.send(Message::Text(get.to_string())).await?;
+    let rsp = next_message(&mut client, |v| v["id"] == 4).await?;
+    assert!(rsp["result"]["open"].as_array().unwrap().is_empty());
+    Ok(())
+}
+
+// the orders taken earlier in a batch count against the limits of the later ones
+#[tokio::test]
+async fn test_batch_risk() -> anyhow::Result<()> {
+    let config: RiskConfig = serde_json::from_value(json!({
+        "max_position": 0.0025,
+        "max_open_orders": 3
+    }))?;
+    let (_mock, local) = serve(Venue::Spot, |app, market| {
+        app.risk(Risk::new(config, market.bbo()))
+    })
+    .await?;
+    let mut client = login(&local, 12, true).await?;
+
+    // a ladder resting away from the mock bbo
+    let order = |id: u32, side: &str, price: f64| {
+        json!({
+            "id": id,
+            "symbol": "btcusdt",
+            "price": price,
+            "quantity": 0.001,
+            "side": side,
+            "order_type": "LIMIT",
+            "tif": "GTC",
+            "session_id": 12
+        })
+    };
+    let batch = json!({"id": 2, "method": "batch_order", "params": [
+        order(1, "BUY", 59000.0),
+        order(2, "BUY", 58900.0),
+        order(3, "BUY", 58800.0),
+        order(4, "SELL", 61000.0),
+        order(5, "SELL", 61100.0),
+    ]});
+    client.send(Message::Text(batch.to_string())).await?;
+    let rsp = next_message(&mut client, |v| v["id"] == 2).await?;
+    let codes: Vec<_> = rsp["result"]["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| r["code"].as_i64().unwrap())
+        .collect();
+    assert_eq!(
+        codes,
+        [
+            0,
+            0,
+            xcrypto::error::MAX_POSITION as i64,
+            0,
+            xcrypto::error::MAX_OPEN_ORDERS as i64
+        ]
+    );
+
+    let get = json!({"id": 3, "method": "get_orders", "params": {"session_id": 12}});
+    client.send(Message::Text(get.to_string())).await?;
+    let rsp = next_message(&mut client, |v| v["id"] == 3).await?;
+    assert_eq!(rsp["result"]["open"].as_array().unwrap().len(), 3);
+    Ok(())
+}
+
+// subscribe a book through the handler and lose some updates of it
+async fn check_book(venue: Venue) -> anyhow::Result<()> {
+    let (mock, local) = serve(venue, |_, _| ()).await?;
+    let mut client = login(&local, 7, false).await?;
+
+    let subscribe = json!({"id": 2, "method": "subscribe", "params": ["btcusdt@book"]});
+    client.send(Message::Text(subscribe.to_string())).await?;
+    let book = next_message(&mut client, |v| v["stream"] == "btcusdt@book").await?;
+    let bids = book["bids"].as_array().unwrap();
+    assert_eq!(bids.len(), 100);
+    assert_eq!(bids[0]["price"], 59999.99);
+    assert_eq!(bids[1]["price"], 59999.98);
+    assert_eq!(book["asks"][0]["price"], 60000.01);
+    assert_eq!(mock.snapshots(), 1);
+
+    mock.skip_depth_updates(10);
+    timeout(Duration::from_secs(5), async {
+        while mock.snapshots() < 2 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await?;
+    let book = next_message(&mut client, |v| v["stream"] == "btcusdt@book").await?;
+    assert_eq!(book["bids"].as_array().unwrap().len(), 100);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_spot_book() -> anyhow::Result<()> {
+    check_book(Venue::Spot).await
+}
+
+#[tokio::test]
+async fn test_usdt_book() -> anyhow::Result<()> {
+    check_book(Venue::Usdt).await
+}
+
+#[tokio::test]
+async fn test_handler_trade() -> anyhow::Result<()> {
+    let (_mock, local) = serve(Venue::Spot, |_, _| ()).await?;
+
+    let mut client = login(&local, 8, false).await?;
+
+    let subscribe =
+        json!({"id": 2, "method": "subscribe", "params": ["btcusdt@trade", "ethusdt@aggTrade"]});
+    client.send(Message::Text(subscribe.to_string())).await?;
+    let trade = next_message(&mut client, |v| v["stream"] == "btcusdt@trade").await?;
+    assert_eq!(trade["symbol"], "btcusdt");
+    assert_eq!(trade["price"], 60000.0);
+    assert_eq!(trade["quantity"], 1.0);
+    assert!(trade["side"] == "BUY" || trade["side"] == "SELL");
+
+    let trade = next_message(&mut client, |v| v["stream"] == "ethusdt@aggTrade").await?;
+    assert_eq!(trade["price"], 3000.0);
+    assert!(trade["id"].as_i64().unwrap() > 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_handler_mark_price() -> anyhow::Result<()> {
+    let (_mock, local) = serve(Venue::Usdt, |_, _| ()).await?;
+
+    let mut client = login(&local, 9, false).await?;
+
+    let subscribe = json!({"id": 2, "method": "subscribe", "params": ["btcusdt@markPrice:1s"]});
+    client.send(Message::Text(subscribe.to_string())).await?;
+    let index = next_message(&mut client, |v| v["stream"] == "btcusdt@markPrice:1s").await?;
+    assert_eq!(index["symbol"], "btcusdt");
+    assert_eq!(index["mark_price"], 60000.0);
+    assert_eq!(index["last_funding_rate"], 0.0001);
+    assert!(index["next_funding_time"].as_i64().unwrap() > index["time"].as_i64().unwrap());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_handler_liquidation() -> anyhow::Result<()> {
+    let (_mock, local) = serve(Venue::Usdt, |_, _| ()).await?;
+
+    let mut client = login(&local, 10, false).await?;
+
+    let subscribe = json!({"id": 2, "method": "subscribe", "params": ["ethusdt@forceOrder", "!forceOrder@arr"]});
+    client.send(Message::Text(subscribe.to_string())).await?;
+    let liquidation = next_message(&mut client, |v| v["stream"] == "ethusdt@forceOrder").await?;
+    assert_eq!(liquidation["symbol"], "ethusdt");
+    assert_eq!(liquidation["side"], "SELL");
+    assert_eq!(liquidation["state"], "FILLED");
+    assert_eq!(liquidation["filled"], 1.0);
+
+    let liquidation = next_message(&mut client, |v| v["stream"] == "!forceOrder@arr").await?;
+    assert_eq!(liquidation["symbol"], "btcusdt");
+    Ok(())
+}
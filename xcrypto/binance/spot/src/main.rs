@@ -5,16 +5,62 @@ use clap::Parser;
 use log::{error, info};
 use logger::*;
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 use trade::SpotTrade;
-use xcrypto::rest::Rest;
+use xcrypto::rest::{HttpConfig, Rest, SignMethod};
+use xcrypto::timesync;
+use xcrypto::wsapi::WsApi;
 
 #[derive(Debug, Deserialize)]
 struct Config {
     margin: bool,
+    // not needed by sim, which only reads public market data
+    #[serde(default)]
     apikey: String,
+    #[serde(default)]
     pem: String,
+    // ed25519 by default, rsa or hmac with pem holding the secret key
+    #[serde(default)]
+    sign: SignMethod,
     local: String,
+    #[serde(default)]
+    sim: bool,
+    // positions and fills of the simulated sessions
+    #[serde(default = "default_sim_db")]
+    sim_db: String,
+    rest: Option<String>,
+    stream: Option<String>,
+    record: Option<String>,
+    risk: Option<RiskConfig>,
+    // fractions of the exchange limits to warn sessions at and to reject orders at
+    #[serde(default)]
+    rate_limit: RateLimitConfig,
+    reconcile: Option<ReconcileConfig>,
+    // orders are sent by the websocket api at this address instead of rest, e.g.
+    // wss://ws-api.binance.com:443/ws-api/v3
+    ws_api: Option<String>,
+    // sub accounts, the sessions they don't list trade with apikey and pem
+    #[serde(default)]
+    accounts: Vec<AccountConfig>,
+    // session_id -> grace period in milliseconds
+    #[serde(default)]
+    cancel_on_disconnect: HashMap<u16, u64>,
+    // timeouts, keep-alive and http/2 of the client every account sends with
+    #[serde(default)]
+    http: HttpConfig,
+    // seconds between samples of the server time signed requests are stamped with, 0 turns it off
+    #[serde(default = "default_time_sync")]
+    time_sync: u64,
+}
+
+fn default_time_sync() -> u64 {
+    60
+}
+
+fn default_sim_db() -> String {
+    "sim.db".into()
 }
 
 #[derive(Debug, Parser)]
@@ -48,32 +94,88 @@ async fn main() -> anyhow::Result<()> {
 
     let _logger = init(Some(format!("log/{}", filename)), args.level);
 
-    let app = Application::new(&config.local).await?;
-    let market = Market::new("wss://stream.binance.com:9443/ws".into()).await?;
+    let rest_url = config.rest.as_deref().unwrap_or("https://api.binance.com");
+    let stream_url = config
+        .stream
+        .as_deref()
+        .unwrap_or("wss://stream.binance.com:9443/ws");
 
-    let rest = Arc::new(Rest::new(
-        "https://api.binance.com",
-        &config.apikey,
-        &config.pem,
-        3000,
-    )?);
+    let mut app = Application::new(&config.local).await?;
+    let client = config.http.client()?;
+    let rest = if config.sim {
+        Rest::public(rest_url)?
+    } else {
+        Rest::with_sign(rest_url, &config.apikey, &config.pem, config.sign, 3000)?
+    };
+    let rest = Arc::new(rest.with_client(client.clone()));
+    let time_sync = Duration::from_secs(config.time_sync);
+    if config.time_sync > 0 {
+        timesync::start(&rest, "/api/v3/time", time_sync).await;
+    }
+    let mut market = Market::new(stream_url.into()).await?;
+    market.book(rest.clone(), "/api/v3/depth");
+    if let Some(dir) = &config.record {
+        market.record(Recorder::new(dir)?);
+    }
+    if let Some(risk) = config.risk {
+        app.risk(Risk::new(risk, market.bbo()));
+    }
+    app.rate_limit(config.rate_limit);
+    for (session_id, grace) in config.cancel_on_disconnect {
+        app.cancel_on_disconnect(session_id, Duration::from_millis(grace));
+    }
 
-    let account = if config.margin {
-        Account::<SpotListenKey>::new(
-            "wss://stream.binance.com:9443/ws",
-            "/sapi/v1/userDataStream",
+    if config.sim {
+        let trade = SimTrade::new(
             rest.clone(),
+            "/api/v3/exchangeInfo",
+            &config.sim_db,
+            market.feed(),
         )
-        .await?
+        .await?;
+
+        if let Err(e) = app.keep_running(market, trade).await {
+            error!("{}", e);
+        }
+        return Ok(());
+    }
+
+    let api = if config.margin {
+        "/sapi/v1/userDataStream"
     } else {
-        Account::<SpotListenKey>::new(
-            "wss://stream.binance.com:9443/ws",
-            "/api/v3/userDataStream",
-            rest.clone(),
-        )
-        .await?
+        "/api/v3/userDataStream"
     };
-    let trade = SpotTrade::new(rest.clone(), account, config.margin).await?;
+    let account = Account::<SpotListenKey>::new(stream_url, api, rest.clone()).await?;
+    let mut accounts = Accounts::new(account);
+    let mut reconciles: Vec<_> = config.reconcile.into_iter().map(|r| (0, r)).collect();
+    for sub in config.accounts {
+        let rest = Arc::new(
+            Rest::with_sign(rest_url, &sub.apikey, &sub.pem, sub.sign, 3000)?
+                .with_client(client.clone()),
+        );
+        if config.time_sync > 0 {
+            timesync::start(&rest, "/api/v3/time", time_sync).await;
+        }
+        let account = Account::new(stream_url, api, rest).await?;
+        let index = accounts.add(&sub.name, account, &sub.sessions)?;
+        reconciles.extend(sub.reconcile.map(|r| (index, r)));
+    }
+    if let Some(addr) = &config.ws_api {
+        // the websocket api has no margin orders
+        if config.margin {
+            anyhow::bail!("Margin orders can't be sent by the websocket api");
+        }
+        for index in 0..accounts.count() {
+            let wsapi = WsApi::new(addr, accounts.get(index).rest().clone())
+                .with_timeout(Duration::from_millis(config.http.timeout));
+            accounts.ws_api(index, wsapi);
+        }
+    }
+
+    let mut trade = SpotTrade::new(accounts, config.margin).await?;
+    for (index, reconcile) in reconciles {
+        trade.reconcile(index, reconcile)?;
+    }
 
     if let Err(e) = app.keep_running(market, trade).await {
         error!("{}", e);
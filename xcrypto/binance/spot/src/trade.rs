@@ -1,94 +1,217 @@
 use ::serde::Serialize;
 use binance::chat::*;
+use binance::Trade;
 use binance::*;
 use log::*;
 use native_json::Deserialize;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use tokio::sync::mpsc::UnboundedSender;
 use xcrypto::chat::*;
 use xcrypto::error::*;
 use xcrypto::parser::Parser;
 use xcrypto::position::PositionDB;
+use xcrypto::ratelimit::ExchangeLimit;
 use xcrypto::rest::Rest;
 use xcrypto::tungstenite::Message;
 
-async fn get_positions(rest: &Arc<Rest>) -> anyhow::Result<HashMap<String, BinanceProduct>> {
+// products and the rate limits of the exchange
+async fn get_positions(
+    rest: &Arc<Rest>,
+) -> anyhow::Result<(HashMap<String, BinanceProduct>, Vec<ExchangeLimit>)> {
     let rsp = rest.get("/api/v3/exchangeInfo", &[], false).await?;
     let results: serde_json::Value = serde_json::from_str(&rsp.text().await?)?;
 
-    let results = results.get("symbols").unwrap();
-    let results: Vec<BinanceProduct> = serde_json::from_value(results.to_owned())?;
+    let symbols = results.get("symbols").unwrap();
+    let symbols: Vec<BinanceProduct> = serde_json::from_value(symbols.to_owned())?;
     let mut products = HashMap::new();
 
-    for result in results {
-        products.insert(result.symbol.clone(), result);
+    for symbol in symbols {
+        products.insert(symbol.symbol.clone(), symbol);
     }
 
+    let limits = serde_json::from_value(results["rateLimits"].to_owned()).unwrap_or_default();
+
     info!("products {}", products.len());
-    Ok(products)
+    Ok((products, limits))
 }
 
-pub struct SpotTrade {
+// the free and locked balances of every asset held, user data only pushes changes
+async fn get_balances(rest: &Arc<Rest>, margin: bool) -> anyhow::Result<HashMap<String, Balance>> {
+    let (path, key, params) = if margin {
+        ("/sapi/v1/margin/account", "userAssets", vec![])
+    } else {
+        (
+            "/api/v3/account",
+            "balances",
+            vec![("omitZeroBalances".into(), "true".into())],
+        )
+    };
+    let rsp = rest.get(path, &params, true).await?;
+    let results: serde_json::Value = serde_json::from_str(&rsp.text().await?)?;
+
+    let results = match results.get(key) {
+        Some(results) => results,
+        None => anyhow::bail!("Failed to get balances {}", results),
+    };
+    let results: Vec<SpotBalance> = serde_json::from_value(results.to_owned())?;
+    let time = now();
+    let balances: HashMap<_, _> = results
+        .iter()
+        .map(|result| result.balance(time))
+        .filter(|balance| balance.wallet != 0.0)
+        .map(|balance| (balance.asset.clone(), balance))
+        .collect();
+
+    info!("balances {}", balances.len());
+    Ok(balances)
+}
+
+// symbol -> balance of its base asset, symbols are (symbol, base asset)
+async fn get_exchange_nets(
     rest: Arc<Rest>,
+    margin: bool,
+    symbols: Vec<(String, String)>,
+) -> anyhow::Result<Nets> {
+    let balances = get_balances(&rest, margin).await?;
+    Ok(symbols
+        .into_iter()
+        .map(|(symbol, base)| {
+            let net = balances.get(&base).map(|balance| balance.wallet);
+            (symbol, net.unwrap_or_default())
+        })
+        .collect())
+}
+
+pub struct SpotTrade {
     txs: HashMap<SocketAddr, UnboundedSender<Message>>,
-    account: Account<SpotListenKey>,
+    accounts: Accounts<SpotListenKey>,
     margin: bool,
     // addr -> session_id
     session_id: HashMap<SocketAddr, u16>,
     // session_id -> session
     session: HashMap<u16, Session>,
     posdb: Arc<PositionDB>,
+    oms: Oms,
+    // client order id of orders being canceled and replaced
+    amends: Arc<Mutex<HashSet<u64>>>,
     products: HashMap<String, BinanceProduct>,
+    // the rest of the fields are per account
+    // asset -> balance
+    balances: Vec<HashMap<String, Balance>>,
+    reconcilers: Vec<Option<Reconciler>>,
 }
 
 impl SpotTrade {
-    pub async fn new(
-        rest: Arc<Rest>,
-        account: Account<SpotListenKey>,
-        margin: bool,
-    ) -> anyhow::Result<Self> {
-        let products = get_positions(&rest).await?;
+    pub async fn new(accounts: Accounts<SpotListenKey>, margin: bool) -> anyhow::Result<Self> {
+        let (products, limits) = get_positions(accounts.get(0).rest()).await?;
+        accounts.set_rate_limits(&limits);
+        let mut balances = Vec::new();
+        for index in 0..accounts.count() {
+            balances.push(get_balances(accounts.get(index).rest(), margin).await?);
+        }
+        let reconcilers = balances.iter().map(|_| None).collect();
 
         Ok(Self {
-            rest,
             txs: HashMap::default(),
-            account,
+            accounts,
             margin,
             session_id: HashMap::default(),
             session: HashMap::default(),
             posdb: Arc::new(PositionDB::new("pos.db").await?),
+            oms: Oms::default(),
+            amends: Arc::default(),
             products,
+            balances,
+            reconcilers,
         })
     }
+
+    // compare the nets of the sessions of an account with the balances of the base assets
+    pub fn reconcile(&mut self, index: usize, config: ReconcileConfig) -> anyhow::Result<()> {
+        self.accounts.check_reconcile(index, &config)?;
+        self.reconcilers[index] = Some(Reconciler::new(config));
+        Ok(())
+    }
 }
 
 impl Trade for SpotTrade {
     fn disconnected(&self) -> bool {
-        self.account.disconnected()
+        self.accounts.disconnected()
+    }
+
+    fn session_disconnected(&self, session_id: u16) -> bool {
+        self.accounts.session_disconnected(session_id)
     }
 
     fn products(&self) -> &HashMap<String, BinanceProduct> {
         &self.products
     }
 
+    // live once the session logged in, what pos.db loaded before
     fn get_positions(&self, session_id: u16) -> Option<&HashMap<String, Position>> {
-        self.posdb.get_positions(session_id)
+        self.session
+            .get(&session_id)
+            .map(Session::positions)
+            .or_else(|| self.posdb.get_positions(session_id))
+    }
+
+    fn oms(&self) -> &Oms {
+        &self.oms
+    }
+
+    fn get_balances(&self, session_id: u16) -> Vec<Balance> {
+        let index = self.accounts.index(session_id);
+        self.balances[index].values().cloned().collect()
+    }
+
+    fn rest(&self, session_id: u16) -> Option<&Rest> {
+        let index = self.accounts.index(session_id);
+        Some(self.accounts.get(index).rest())
+    }
+
+    async fn get_trades(&self, req: &TradeReq) -> anyhow::Result<Vec<Fill>> {
+        self.posdb.get_trades(req).await
     }
 
     async fn get_products(&mut self) -> anyhow::Result<()> {
-        self.products = get_positions(&self.rest).await?;
+        let (products, limits) = get_positions(self.accounts.get(0).rest()).await?;
+        self.accounts.set_rate_limits(&limits);
+        self.products = products;
         Ok(())
     }
 
     async fn process(&mut self) -> anyhow::Result<bool> {
-        let msg = self.account.process().await?;
+        for (index, reconciler) in self.reconcilers.iter_mut().enumerate() {
+            if let Some(reconciler) = reconciler.as_mut().filter(|r| r.due()) {
+                let quote = &reconciler.config().quote;
+                let symbols: Vec<_> = self
+                    .products
+                    .values()
+                    .filter(|product| product.quoteAsset == *quote)
+                    .map(|product| (product.symbol.clone(), product.baseAsset.clone()))
+                    .collect();
+                let rest = self.accounts.get(index).rest().clone();
+                reconciler.spawn(get_exchange_nets(rest, self.margin, symbols));
+            }
+        }
 
-        if let Some(Message::Text(s)) = msg {
-            if let Event::ExecutionReport(order) = serde_json::from_str::<Event>(&s)? {
-                self.on_order(&order);
+        tokio::select! {
+            (index, msg) = self.accounts.process() => {
+                if let Some(Message::Text(s)) = msg? {
+                    match serde_json::from_str::<Event>(&s)? {
+                        Event::ExecutionReport(order) => self.on_order(&order),
+                        Event::OutboundAccountPosition(update) => self.on_account(index, &update),
+                        // deposits and withdrawals, the new balance follows in outboundAccountPosition
+                        Event::Balance(update) => info!("{:?}", update),
+                        _ => (),
+                    }
+                }
+            }
+            (index, Some(nets)) = reconciled(&mut self.reconcilers) => {
+                self.on_reconcile(index, nets).await?
             }
         }
 
@@ -98,7 +221,7 @@ impl Trade for SpotTrade {
     fn add_order(&mut self, addr: &SocketAddr, order: &BinanceOrder) -> anyhow::Result<()> {
         match self.txs.get_mut(addr) {
             Some(tx) => {
-                let rest = self.rest.clone();
+                let transport = self.accounts.transport(order.session_id);
                 let tx = tx.clone();
 
                 let symbol = order.symbol.clone();
@@ -109,6 +232,8 @@ impl Trade for SpotTrade {
                 let tif = order.tif.clone();
                 let session_id = order.session_id;
                 let id = order.id;
+                let order = order.clone();
+                let sent = self.oms.sent().clone();
 
                 let path = if self.margin {
                     "/sapi/v1/margin/order"
@@ -117,7 +242,7 @@ impl Trade for SpotTrade {
                 };
 
                 tokio::spawn(async move {
-                    match rest
+                    let rejected = match transport
                         .add_order(
                             path,
                             symbol.clone().to_uppercase(),
@@ -131,54 +256,17 @@ impl Trade for SpotTrade {
                         )
                         .await
                     {
-                        Ok(rsp) => {
-                            // exchange rej
-                            if let Ok(e) = rsp.json::<xcrypto::chat::Error>().await {
-                                error!("{:?}", e);
-                                let order = Order::new(
-                                    id,
-                                    symbol,
-                                    side,
-                                    State::REJECTED,
-                                    order_type,
-                                    tif,
-                                    quantity,
-                                    price,
-                                );
-
-                                match serde_json::to_string(&order) {
-                                    Ok(s) => {
-                                        if let Err(e) = tx.send(Message::Text(s)) {
-                                            error!("{}", e);
-                                        }
-                                    }
-                                    Err(e) => error!("{}", e),
-                                }
-                            }
-                        }
+                        // exchange rej
+                        Ok(rsp) => serde_json::from_value::<xcrypto::chat::Error>(rsp)
+                            .ok()
+                            .map(|e| anyhow::anyhow!("{:?}", e)),
                         // network error
-                        Err(e) => {
-                            error!("{:?}", e);
-                            let order = Order::new(
-                                id,
-                                symbol,
-                                side,
-                                State::REJECTED,
-                                order_type,
-                                tif,
-                                quantity,
-                                price,
-                            );
-
-                            match serde_json::to_string(&order) {
-                                Ok(s) => {
-                                    if let Err(e) = tx.send(Message::Text(s)) {
-                                        error!("{}", e);
-                                    }
-                                }
-                                Err(e) => error!("{}", e),
-                            }
-                        }
+                        Err(e) => Some(e),
+                    };
+
+                    if let Some(e) = rejected {
+                        error!("{:?}", e);
+                        sent.reject(&tx, &order);
                     }
                 });
             }
@@ -191,7 +279,7 @@ impl Trade for SpotTrade {
     fn cancel(&mut self, addr: &SocketAddr, cancel: &BinanceCancel) -> anyhow::Result<()> {
         match self.txs.get_mut(addr) {
             Some(_) => {
-                let rest = self.rest.clone();
+                let transport = self.accounts.transport(cancel.session_id);
 
                 let symbol = cancel.symbol.clone().to_uppercase();
                 let session_id = cancel.session_id;
@@ -205,7 +293,7 @@ impl Trade for SpotTrade {
                 };
 
                 tokio::spawn(async move {
-                    if let Err(e) = rest.cancel(path, symbol, orig).await {
+                    if let Err(e) = transport.cancel(path, symbol, orig).await {
                         error!("{}", e)
                     }
                 });
@@ -215,6 +303,221 @@ impl Trade for SpotTrade {
         Ok(())
     }
 
+    fn cancel_all(&mut self, session_id: u16) -> anyhow::Result<()> {
+        let path = if self.margin {
+            "/sapi/v1/margin/order"
+        } else {
+            "/api/v3/order"
+        };
+
+        for order in self.oms.get_orders(session_id).open {
+            let transport = self.accounts.transport(session_id);
+            let symbol = order.symbol.to_uppercase();
+            let orig = client_order_id(session_id, order.internal_id);
+
+            tokio::spawn(async move {
+                if let Err(e) = transport.cancel(path, symbol, orig).await {
+                    error!("{}", e)
+                }
+            });
+        }
+        Ok(())
+    }
+
+    fn amend(&mut self, addr: &SocketAddr, id: i64, amend: &BinanceAmend) -> anyhow::Result<()> {
+        let tx = match self.txs.get(addr) {
+            Some(tx) => tx.clone(),
+            None => {
+                warn!("Missing session {}, maybe a bug", addr);
+                return Ok(());
+            }
+        };
+
+        // binance has no cancel replace for cross margin
+        if self.margin {
+            let e = Error {
+                code: AMEND_UNSUPPORTED,
+                msg: "amend isn't supported by margin account".into(),
+            };
+            warn!("{:?} {:?}", e, amend);
+            send(&tx, &Response { id, result: e });
+            return Ok(());
+        }
+
+        let key = client_order_id(amend.session_id, amend.order_id);
+        let mut order = match self.oms.get(key) {
+            Some(order) => order.clone(),
+            None => {
+                let e = Error {
+                    code: UNKNOWN_ORDER,
+                    msg: format!("unknown order {} to amend", amend.order_id),
+                };
+                warn!("{:?}", e);
+                send(&tx, &Response { id, result: e });
+                return Ok(());
+            }
+        };
+        self.amends.lock().unwrap().insert(key);
+
+        let amends = self.amends.clone();
+        let transport = self.accounts.transport(amend.session_id);
+        let session_id = amend.session_id;
+        let price = amend.price.to_string();
+        let quantity = amend.quantity.to_string();
+
+        tokio::spawn(async move {
+            let e = match transport
+                .cancel_replace(
+                    "/api/v3/order/cancelReplace",
+                    &order,
+                    session_id,
+                    price,
+                    quantity,
+                )
+                .await
+            {
+                Ok(rsp) if rsp.get("code").is_some() => {
+                    error!("{}", rsp);
+                    // still there when the CANCELED update hasn't come yet, it gets through then
+                    let pending = amends.lock().unwrap().remove(&key);
+                    // canceled but not replaced, the order is gone
+                    if rsp["data"]["cancelResult"] == "SUCCESS" && !pending {
+                        order.state = State::CANCELED;
+                        send(&tx, &order);
+                    }
+                    Error {
+                        code: AMEND_FAILED,
+                        msg: format!("{} {}", rsp["code"], rsp["msg"]),
+                    }
+                }
+                Ok(_) => return,
+                Err(e) => {
+                    error!("{:?}", e);
+                    amends.lock().unwrap().remove(&key);
+                    Error {
+                        code: AMEND_FAILED,
+                        msg: e.to_string(),
+                    }
+                }
+            };
+            send(&tx, &Response { id, result: e });
+        });
+        Ok(())
+    }
+
+    fn batch_order(
+        &mut self,
+        addr: &SocketAddr,
+        mut batch: Batch,
+        orders: Vec<BinanceOrder>,
+    ) -> anyhow::Result<()> {
+        let tx = match self.txs.get(addr) {
+            Some(tx) => tx.clone(),
+            None => {
+                warn!("Missing session {}, maybe a bug", addr);
+                return Ok(());
+            }
+        };
+
+        let rest = self.rest_of(addr);
+        let sent = self.oms.sent().clone();
+        let path = if self.margin {
+            "/sapi/v1/margin/order"
+        } else {
+            "/api/v3/order"
+        };
+
+        // spot has no batch endpoint, the orders are sent in parallel
+        tokio::spawn(async move {
+            let rsps = futures::future::join_all(orders.iter().map(|order| {
+                rest.add_order(
+                    path,
+                    order.symbol.to_uppercase(),
+                    order.price.to_string(),
+                    order.quantity.to_string(),
+                    format!("{:?}", order.side),
+                    format!("{:?}", order.order_type),
+                    format!("{:?}", order.tif),
+                    order.session_id,
+                    order.id,
+                )
+            }))
+            .await;
+
+            for (order, rsp) in orders.iter().zip(rsps) {
+                match rsp {
+                    Ok(rsp) => {
+                        // exchange rej
+                        if let Ok(e) = rsp.json::<xcrypto::chat::Error>().await {
+                            batch.reject_order(&tx, &sent, order, e);
+                        }
+                    }
+                    // network error
+                    Err(e) => batch.reject_order(
+                        &tx,
+                        &sent,
+                        order,
+                        Error {
+                            code: UNDEF_ERROR,
+                            msg: e.to_string(),
+                        },
+                    ),
+                }
+            }
+            batch.reply(&tx);
+        });
+        Ok(())
+    }
+
+    fn batch_cancel(
+        &mut self,
+        addr: &SocketAddr,
+        mut batch: Batch,
+        cancels: Vec<BinanceCancel>,
+    ) -> anyhow::Result<()> {
+        let tx = match self.txs.get(addr) {
+            Some(tx) => tx.clone(),
+            None => {
+                warn!("Missing session {}, maybe a bug", addr);
+                return Ok(());
+            }
+        };
+
+        let rest = self.rest_of(addr);
+        let path = if self.margin {
+            "/sapi/v1/margin/order"
+        } else {
+            "/api/v3/order"
+        };
+
+        tokio::spawn(async move {
+            let rsps = futures::future::join_all(cancels.iter().map(|cancel| {
+                rest.cancel(
+                    path,
+                    cancel.symbol.to_uppercase(),
+                    client_order_id(cancel.session_id, cancel.order_id),
+                )
+            }))
+            .await;
+
+            for (cancel, rsp) in cancels.iter().zip(rsps) {
+                let e = match rsp {
+                    Ok(rsp) => rsp.json::<xcrypto::chat::Error>().await.ok(),
+                    Err(e) => Some(Error {
+                        code: UNDEF_ERROR,
+                        msg: e.to_string(),
+                    }),
+                };
+                if let Some(e) = e {
+                    error!("{:?} {:?}", cancel, e);
+                    batch.reject(cancel.order_id, e);
+                }
+            }
+            batch.reply(&tx);
+        });
+        Ok(())
+    }
+
     fn handle_close(&mut self, addr: &SocketAddr) -> anyhow::Result<()> {
         if let Some(_) = self.txs.remove(addr) {
             match self.session_id.remove(addr) {
@@ -267,7 +570,7 @@ impl Trade for SpotTrade {
         let mut params = Vec::new();
 
         for symbol in req.params.iter() {
-            let symbol = symbol.to_lowercase();
+            let symbol = normalize_stream(symbol);
             match symbol.split_once("@") {
                 Some((name, stream)) => {
                     if !self.products.contains_key(name) {
@@ -313,7 +616,7 @@ impl Trade for SpotTrade {
                 };
             }
             None => match stream {
-                "depth" | "bbo" => true,
+                "depth" | "bbo" | "book" | "trade" | "aggTrade" => true,
                 _ => false,
             },
         }
@@ -353,9 +656,25 @@ impl Trade for SpotTrade {
     }
 
     async fn reconncet(&mut self) -> anyhow::Result<()> {
-        self.account.reconnect().await?;
-
-        Ok(())
+        // changes pushed while disconnected are lost
+        let (reconnected, mut errors) = self.accounts.reconnect().await;
+        for index in reconnected {
+            let rest = self.accounts.get(index).rest();
+            match get_balances(rest, self.margin).await {
+                Ok(balances) => {
+                    for balance in balances.into_values() {
+                        self.broadcast(index, &balance);
+                        self.balances[index].insert(balance.asset.clone(), balance);
+                    }
+                }
+                Err(e) => {
+                    let name = self.accounts.name(index);
+                    error!("Failed to get balances of account {} {}", name, e);
+                    errors.push(e.context(format!("account {}", name)));
+                }
+            }
+        }
+        join_errors(errors)
     }
 }
 
@@ -371,6 +690,18 @@ impl SpotTrade {
         match client_order_id.parse::<u64>() {
             Ok(client_order_id) => {
                 let session_id = (client_order_id >> 32) as u16;
+                self.oms.on_order(client_order_id, order.clone().into());
+
+                match order.X {
+                    // the strategy only sees the replacing order
+                    State::CANCELED if self.amends.lock().unwrap().remove(&client_order_id) => {
+                        return
+                    }
+                    State::CANCELED | State::NEW | State::PARTIALLY_FILLED => (),
+                    _ => {
+                        self.amends.lock().unwrap().remove(&client_order_id);
+                    }
+                }
 
                 match self.session.get_mut(&session_id) {
                     Some(session) => {
@@ -384,4 +715,51 @@ impl SpotTrade {
             Err(_) => info!("Extrnal order:{:?} ", order),
         }
     }
+
+    async fn on_reconcile(
+        &mut self,
+        index: usize,
+        nets: anyhow::Result<Nets>,
+    ) -> anyhow::Result<()> {
+        let exchange = match nets {
+            Ok(nets) => nets,
+            Err(e) => {
+                error!("{}", e);
+                return Ok(());
+            }
+        };
+
+        if let Some(reconciler) = &self.reconcilers[index] {
+            let accounts = &self.accounts;
+            let owns = |session_id| accounts.index(session_id) == index;
+            reconciler
+                .reconcile(&exchange, &self.posdb, &mut self.session, owns)
+                .await?;
+        }
+        Ok(())
+    }
+
+    fn on_account(&mut self, index: usize, update: &OutboundAccountPosition) {
+        info!("{} {:?}", self.accounts.name(index), update);
+        for balance in update.balances() {
+            self.broadcast(index, &balance);
+            self.balances[index].insert(balance.asset.clone(), balance);
+        }
+    }
+
+    // the rest of the account the session logged in at addr trades on
+    fn rest_of(&self, addr: &SocketAddr) -> Arc<Rest> {
+        let session_id = self.session_id.get(addr).cloned().unwrap_or_default();
+        self.accounts.rest(session_id)
+    }
+
+    // every trading session of an account
+    fn broadcast<T: Serialize>(&self, index: usize, data: &T) {
+        for (addr, tx) in self.txs.iter() {
+            match self.session_id.get(addr) {
+                Some(session_id) if self.accounts.index(*session_id) == index => send(tx, data),
+                _ => (),
+            }
+        }
+    }
 }
@@ -1,11 +1,18 @@
-use crate::ListenKey;
+use crate::{ListenKey, ReconcileConfig};
+use futures::future::{select_all, FutureExt};
 use log::*;
 use native_json::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
 use std::time::Instant;
 use url::Url;
-use xcrypto::{rest::Rest, tungstenite::Message, ws::WebSocket};
+use xcrypto::chat::Order;
+use xcrypto::ratelimit::ExchangeLimit;
+use xcrypto::rest::{Rest, SignMethod};
+use xcrypto::{tungstenite::Message, ws::WebSocket, wsapi::WsApi};
 
 async fn fetch_listen_key<T: DeserializeOwned>(rest: &Arc<Rest>, api: &str) -> anyhow::Result<T> {
     let rsp = rest.post(api, &[], false).await?;
@@ -36,10 +43,10 @@ where
 {
     pub async fn new(addr: &str, api: &str, rest: Arc<Rest>) -> anyhow::Result<Self> {
         let listenkey: T = fetch_listen_key(&rest, api).await?;
-        let addr = format!("{}/{}", Url::parse(addr)?.as_str(), listenkey.key());
+        let url = format!("{}/{}", Url::parse(addr)?.as_str(), listenkey.key());
 
-        info!("Account Websocket: {:?}", addr);
-        let ws = WebSocket::client(addr.as_str()).await?;
+        info!("Account Websocket: {:?}", url);
+        let ws = WebSocket::client(url.as_str()).await?;
         Ok(Self {
             addr: addr.into(),
             api: api.into(),
@@ -55,6 +62,10 @@ where
         self.disconnected
     }
 
+    pub fn rest(&self) -> &Arc<Rest> {
+        &self.rest
+    }
+
     pub async fn process(&mut self) -> anyhow::Result<Option<Message>> {
         if self.time.elapsed() >= Duration::from_secs(30 * 60) {
             tokio::spawn(ping(self.rest.clone(), self.api.clone()));
@@ -107,3 +118,268 @@ where
         Ok(())
     }
 }
+
+// a sub account next to the one of apikey and pem, the sessions listed trade on it
+#[derive(Debug, Clone, Deserialize)]
+pub struct AccountConfig {
+    pub name: String,
+    pub apikey: String,
+    pub pem: String,
+    #[serde(default)]
+    pub sign: SignMethod,
+    pub sessions: Vec<u16>,
+    // compares the nets of its sessions with this account
+    pub reconcile: Option<ReconcileConfig>,
+}
+
+pub const DEFAULT_ACCOUNT: &str = "default";
+
+// every account of a trade server, the first one takes the sessions no other account lists
+pub struct Accounts<T: ListenKey + DeserializeOwned> {
+    accounts: Vec<(String, Account<T>)>,
+    // session_id -> index of its account
+    routes: HashMap<u16, usize>,
+    // index -> websocket api sending the orders of the account instead of rest
+    wsapis: HashMap<usize, Arc<WsApi>>,
+}
+
+impl<T> Accounts<T>
+where
+    T: ListenKey + DeserializeOwned + Send,
+{
+    pub fn new(default: Account<T>) -> Self {
+        Self {
+            accounts: vec![(DEFAULT_ACCOUNT.into(), default)],
+            routes: HashMap::default(),
+            wsapis: HashMap::default(),
+        }
+    }
+
+    // the index of the new account
+    pub fn add(
+        &mut self,
+        name: &str,
+        account: Account<T>,
+        sessions: &[u16],
+    ) -> anyhow::Result<usize> {
+        if self.accounts.iter().any(|(other, _)| other == name) {
+            anyhow::bail!("Duplicate account {}", name);
+        }
+
+        let index = self.accounts.len();
+        for session_id in sessions {
+            if let Some(other) = self.routes.insert(*session_id, index) {
+                anyhow::bail!(
+                    "Session {} is listed by {} and {}",
+                    session_id,
+                    self.accounts[other].0,
+                    name
+                );
+            }
+        }
+        info!("Account {} trades sessions {:?}", name, sessions);
+        self.accounts.push((name.into(), account));
+        Ok(index)
+    }
+
+    // never 0, the default account is always there
+    pub fn count(&self) -> usize {
+        self.accounts.len()
+    }
+
+    pub fn index(&self, session_id: u16) -> usize {
+        self.routes.get(&session_id).cloned().unwrap_or_default()
+    }
+
+    pub fn name(&self, index: usize) -> &str {
+        &self.accounts[index].0
+    }
+
+    pub fn rest(&self, session_id: u16) -> Arc<Rest> {
+        self.accounts[self.index(session_id)].1.rest().clone()
+    }
+
+    pub fn ws_api(&mut self, index: usize, wsapi: WsApi) {
+        info!("Account {} sends orders by websocket api", self.name(index));
+        self.wsapis.insert(index, Arc::new(wsapi));
+    }
+
+    pub fn transport(&self, session_id: u16) -> Transport {
+        let index = self.index(session_id);
+        match self.wsapis.get(&index) {
+            Some(wsapi) => Transport::WsApi(wsapi.clone()),
+            None => Transport::Rest(self.accounts[index].1.rest().clone()),
+        }
+    }
+
+    // the limits of exchangeInfo, every account counts its own usage
+    pub fn set_rate_limits(&self, limits: &[ExchangeLimit]) {
+        for (_, account) in &self.accounts {
+            account
+                .rest()
+                .rate_limits()
+                .lock()
+                .unwrap()
+                .set_limits(limits);
+        }
+    }
+
+    pub fn get(&self, index: usize) -> &Account<T> {
+        &self.accounts[index].1
+    }
+
+    // differences are booked into the reserved session, it has to trade on the account compared
+    pub fn check_reconcile(&self, index: usize, config: &ReconcileConfig) -> anyhow::Result<()> {
+        match config.session_id {
+            Some(session_id) if self.index(session_id) != index => anyhow::bail!(
+                "Reserved session {} doesn't trade on {}",
+                session_id,
+                self.name(index)
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    // any stream down, it is reconnected
+    pub fn disconnected(&self) -> bool {
+        self.accounts
+            .iter()
+            .any(|(_, account)| account.disconnected())
+    }
+
+    // the stream of the account the session trades on is down, its requests wait until it is back
+    pub fn session_disconnected(&self, session_id: u16) -> bool {
+        self.accounts[self.index(session_id)].1.disconnected()
+    }
+
+    // the next user data of any account with the index of the account
+    pub async fn process(&mut self) -> (usize, anyhow::Result<Option<Message>>) {
+        let streams = self
+            .accounts
+            .iter_mut()
+            .map(|(_, account)| account.process().boxed());
+        let (msg, index, _) = select_all(streams).await;
+        (index, msg)
+    }
+
+    // the indexes of the accounts that came back, one failing doesn't hold back the others
+    pub async fn reconnect(&mut self) -> (Vec<usize>, Vec<anyhow::Error>) {
+        let mut reconnected = Vec::new();
+        let mut errors = Vec::new();
+        for (index, (name, account)) in self.accounts.iter_mut().enumerate() {
+            if !account.disconnected() {
+                continue;
+            }
+            if let Err(e) = account.reconnect().await {
+                error!("Account {} failed to reconnect {}", name, e);
+                errors.push(e.context(format!("account {}", name)));
+                continue;
+            }
+            if !account.disconnected() {
+                info!("Account {} is back", name);
+                reconnected.push(index);
+            }
+        }
+        (reconnected, errors)
+    }
+}
+
+// every error of the accounts in one
+pub fn join_errors(errors: Vec<anyhow::Error>) -> anyhow::Result<()> {
+    if errors.is_empty() {
+        return Ok(());
+    }
+    let errors: Vec<_> = errors.iter().map(|e| format!("{:#}", e)).collect();
+    anyhow::bail!("{}", errors.join("; "))
+}
+
+// where the orders of an account go, both answer with the json body of the rest endpoint
+#[derive(Clone)]
+pub enum Transport {
+    Rest(Arc<Rest>),
+    WsApi(Arc<WsApi>),
+}
+
+impl Transport {
+    // the arguments of Rest::add_order
+    #[allow(clippy::too_many_arguments)]
+    pub async fn add_order(
+        &self,
+        path: &str,
+        symbol: String,
+        price: String,
+        quantity: String,
+        side: String,
+        order_type: String,
+        tif: String,
+        session_id: u16,
+        id: u32,
+    ) -> anyhow::Result<Value> {
+        match self {
+            Self::Rest(rest) => {
+                let rsp = rest
+                    .add_order(
+                        path, symbol, price, quantity, side, order_type, tif, session_id, id,
+                    )
+                    .await?;
+                Ok(rsp.json().await?)
+            }
+            Self::WsApi(wsapi) => {
+                wsapi
+                    .add_order(
+                        symbol, price, quantity, side, order_type, tif, session_id, id,
+                    )
+                    .await
+            }
+        }
+    }
+
+    pub async fn cancel(&self, path: &str, symbol: String, orig: u64) -> anyhow::Result<Value> {
+        match self {
+            Self::Rest(rest) => Ok(rest.cancel(path, symbol, orig).await?.json().await?),
+            Self::WsApi(wsapi) => wsapi.cancel(symbol, orig).await,
+        }
+    }
+
+    pub async fn cancel_replace(
+        &self,
+        path: &str,
+        order: &Order,
+        session_id: u16,
+        price: String,
+        quantity: String,
+    ) -> anyhow::Result<Value> {
+        match self {
+            Self::Rest(rest) => {
+                let rsp = rest
+                    .cancel_replace(path, order, session_id, price, quantity)
+                    .await?;
+                Ok(rsp.json().await?)
+            }
+            Self::WsApi(wsapi) => {
+                wsapi
+                    .cancel_replace(order, session_id, price, quantity)
+                    .await
+            }
+        }
+    }
+
+    pub async fn modify(
+        &self,
+        path: &str,
+        order: &Order,
+        session_id: u16,
+        price: String,
+        quantity: String,
+    ) -> anyhow::Result<Value> {
+        match self {
+            Self::Rest(rest) => {
+                let rsp = rest
+                    .modify(path, order, session_id, price, quantity)
+                    .await?;
+                Ok(rsp.json().await?)
+            }
+            Self::WsApi(wsapi) => wsapi.modify(order, session_id, price, quantity).await,
+        }
+    }
+}
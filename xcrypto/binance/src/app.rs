@@ -1,22 +1,47 @@
 use super::handler::Handler;
 use crate::market::Market;
-use crate::Trade;
+use crate::{RateLimitConfig, Risk, Throttle, Trade};
 
 use log::*;
+use std::collections::HashMap;
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 use tokio::sync::oneshot;
+use tokio::time::Duration;
 use xcrypto::tungstenite::Message;
 use xcrypto::ws::{Connection, TcpStreamReceiver, TcpStreamSender, WebSocket};
 
 pub struct Application {
     listener: WebSocket,
+    risk: Option<Risk>,
+    rate_limit: RateLimitConfig,
+    cancel_on_disconnect: HashMap<u16, Duration>,
 }
 
 impl Application {
     pub async fn new(local: &str) -> anyhow::Result<Self> {
         info!("-------------------- Start --------------------");
         let listener = WebSocket::server(local).await?;
-        Ok(Self { listener })
+        Ok(Self {
+            listener,
+            risk: None,
+            rate_limit: RateLimitConfig::default(),
+            cancel_on_disconnect: HashMap::default(),
+        })
+    }
+
+    // check every order before it reaches the exchange
+    pub fn risk(&mut self, risk: Risk) {
+        self.risk = Some(risk);
+    }
+
+    // how close to the exchange limits orders are still sent
+    pub fn rate_limit(&mut self, config: RateLimitConfig) {
+        self.rate_limit = config;
+    }
+
+    // cancel the open orders of a trading session once it has been gone for the grace period
+    pub fn cancel_on_disconnect(&mut self, session_id: u16, grace: Duration) {
+        self.cancel_on_disconnect.insert(session_id, grace);
     }
 
     async fn accept_connect(
@@ -47,15 +72,23 @@ impl Application {
     }
 
     pub async fn keep_running<T: Trade + Send + 'static>(
-        self,
+        mut self,
         mut market: Market,
         mut trade: T,
     ) -> anyhow::Result<()> {
         let (tx, rx) = unbounded_channel();
         let (stop_tx, stop_rx) = oneshot::channel();
+        let risk = self.risk.take();
+        let throttle = Throttle::new(self.rate_limit.clone());
+        let policy = std::mem::take(&mut self.cancel_on_disconnect);
 
         tokio::spawn(async move {
             let mut handler = Handler::new();
+            if let Some(risk) = risk {
+                handler.risk(risk);
+            }
+            handler.throttle(throttle);
+            handler.cancel_on_disconnect(policy);
 
             if let Err(e) = handler.process(rx, &mut market, &mut trade).await {
                 error!("{}", e);
@@ -0,0 +1,58 @@
+use crate::{send, BinanceOrder, Sent};
+use log::*;
+use tokio::sync::mpsc::UnboundedSender;
+use xcrypto::chat::*;
+use xcrypto::tungstenite::Message;
+
+// per order results of a batch request, replied once every order is answered
+#[derive(Debug)]
+pub struct Batch {
+    id: i64,
+    results: Vec<OrderResult>,
+}
+
+impl Batch {
+    // every order is accepted until rejected
+    pub fn new(id: i64, order_ids: impl Iterator<Item = u32>) -> Self {
+        let results = order_ids
+            .map(|order_id| OrderResult {
+                order_id,
+                code: 0,
+                msg: String::new(),
+            })
+            .collect();
+        Self { id, results }
+    }
+
+    pub fn reject(&mut self, order_id: u32, e: Error) {
+        if let Some(result) = self.results.iter_mut().find(|r| r.order_id == order_id) {
+            result.code = e.code;
+            result.msg = e.msg;
+        }
+    }
+
+    // an order failed to place is also reported to the strategy as REJECTED
+    pub fn reject_order(
+        &mut self,
+        tx: &UnboundedSender<Message>,
+        sent: &Sent,
+        order: &BinanceOrder,
+        e: Error,
+    ) {
+        error!("{:?} {:?}", order, e);
+        sent.reject(tx, order);
+        self.reject(order.id, e);
+    }
+
+    pub fn reply(self, tx: &UnboundedSender<Message>) {
+        send(
+            tx,
+            &Response {
+                id: self.id,
+                result: BatchRsp {
+                    results: self.results,
+                },
+            },
+        );
+    }
+}
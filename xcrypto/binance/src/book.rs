@@ -0,0 +1,253 @@
+use crate::{now, BinanceQuote, BinanceSnapshot};
+use log::*;
+use std::collections::BTreeMap;
+use tokio::time::{Duration, Instant};
+use xcrypto::chat::Depth;
+
+// least interval between two snapshot requests of a symbol
+const RESYNC: Duration = Duration::from_secs(1);
+// updates kept waiting for a snapshot, 100 seconds of a 100ms stream
+const PENDING: usize = 1000;
+
+// one update of a diff depth stream, quantities are absolute and 0 removes the level
+#[derive(Debug)]
+pub struct DiffDepth {
+    pub time: i64,
+    pub first: u64,
+    pub last: u64,
+    // future only, the last update id of the previous event
+    pub prev: Option<u64>,
+    pub bids: Vec<BinanceQuote>,
+    pub asks: Vec<BinanceQuote>,
+}
+
+// the full book of a symbol, a rest snapshot followed by every diff depth update
+pub struct LocalBook {
+    symbol: String,
+    // price bits -> quantity, the bits of positive prices keep their order
+    bids: BTreeMap<u64, f64>,
+    asks: BTreeMap<u64, f64>,
+    // last applied update id, None until synced
+    last: Option<u64>,
+    // no update has followed the snapshot yet
+    fresh: bool,
+    // updates waiting for the next snapshot
+    pending: Vec<DiffDepth>,
+    requested: Option<Instant>,
+}
+
+impl LocalBook {
+    pub fn new(symbol: &str) -> Self {
+        Self {
+            symbol: symbol.to_lowercase(),
+            bids: BTreeMap::default(),
+            asks: BTreeMap::default(),
+            last: None,
+            fresh: false,
+            pending: Vec::default(),
+            requested: None,
+        }
+    }
+
+    pub fn synced(&self) -> bool {
+        self.last.is_some()
+    }
+
+    // true if the book changed
+    pub fn update(&mut self, diff: DiffDepth) -> bool {
+        let last = match self.last {
+            Some(last) => last,
+            None => {
+                self.buffer(diff);
+                return false;
+            }
+        };
+
+        // re-applying an update is harmless, the snapshot may sit inside the first one
+        let stale = match self.fresh {
+            true => diff.last < last,
+            false => diff.last <= last,
+        };
+        if stale {
+            return false;
+        }
+
+        let continuous = match (self.fresh, diff.prev) {
+            (true, _) => diff.first <= last + 1,
+            (false, Some(prev)) => prev == last,
+            (false, None) => diff.first == last + 1,
+        };
+        if !continuous {
+            warn!(
+                "Gap of {} book, last {}, update {}-{}",
+                self.symbol, last, diff.first, diff.last
+            );
+            self.last = None;
+            self.buffer(diff);
+            return false;
+        }
+
+        apply(&mut self.bids, &diff.bids);
+        apply(&mut self.asks, &diff.asks);
+        self.last = Some(diff.last);
+        self.fresh = false;
+        true
+    }
+
+    // a snapshot failing or late, e.g. while banned, starts over with a new one instead of
+    // piling up updates
+    fn buffer(&mut self, diff: DiffDepth) {
+        if self.pending.len() >= PENDING {
+            warn!(
+                "{} updates of {} book without a snapshot, requesting another",
+                self.pending.len(),
+                self.symbol
+            );
+            self.pending.clear();
+            self.requested = None;
+        }
+        self.pending.push(diff);
+    }
+
+    // the first update decides which snapshot is recent enough
+    pub fn need_snapshot(&self) -> bool {
+        self.last.is_none()
+            && !self.pending.is_empty()
+            && self.requested.is_none_or(|time| time.elapsed() >= RESYNC)
+    }
+
+    pub fn request(&mut self) {
+        self.requested = Some(Instant::now());
+    }
+
+    // true if the book is synced after replaying the pending updates
+    pub fn on_snapshot(&mut self, snapshot: BinanceSnapshot) -> bool {
+        info!("Snapshot {} of {}", snapshot.lastUpdateId, self.symbol);
+        self.bids.clear();
+        self.asks.clear();
+        apply(&mut self.bids, &snapshot.bids);
+        apply(&mut self.asks, &snapshot.asks);
+        self.last = Some(snapshot.lastUpdateId);
+        self.fresh = true;
+
+        for diff in std::mem::take(&mut self.pending) {
+            self.update(diff);
+        }
+        self.synced()
+    }
+
+    pub fn depth(&self, time: Option<i64>) -> Depth<BinanceQuote> {
+        let quote = |(price, quantity): (&u64, &f64)| BinanceQuote {
+            price: f64::from_bits(*price),
+            quantity: *quantity,
+        };
+
+        Depth {
+            time: time.unwrap_or(now()),
+            symbol: self.symbol.clone(),
+            stream: format!("{}@book", self.symbol),
+            bids: self.bids.iter().rev().map(quote).collect(),
+            asks: self.asks.iter().map(quote).collect(),
+        }
+    }
+}
+
+fn apply(levels: &mut BTreeMap<u64, f64>, quotes: &[BinanceQuote]) {
+    for quote in quotes {
+        if quote.quantity == 0.0 {
+            levels.remove(&quote.price.to_bits());
+        } else {
+            levels.insert(quote.price.to_bits(), quote.quantity);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quote(price: f64, quantity: f64) -> BinanceQuote {
+        BinanceQuote { price, quantity }
+    }
+
+    fn diff(first: u64, last: u64, prev: Option<u64>, bid: f64) -> DiffDepth {
+        DiffDepth {
+            time: 0,
+            first,
+            last,
+            prev,
+            bids: vec![quote(99.0, bid)],
+            asks: vec![],
+        }
+    }
+
+    fn snapshot(id: u64) -> BinanceSnapshot {
+        BinanceSnapshot {
+            lastUpdateId: id,
+            bids: vec![quote(99.0, 1.0), quote(98.0, 1.0)],
+            asks: vec![quote(101.0, 1.0)],
+        }
+    }
+
+    #[test]
+    fn test_spot_book() {
+        let mut book = LocalBook::new("BTCUSDT");
+        assert!(!book.need_snapshot());
+        assert!(!book.update(diff(5, 8, None, 2.0)));
+        assert!(!book.update(diff(9, 12, None, 3.0)));
+        assert!(book.need_snapshot());
+
+        // the snapshot lands inside the second update
+        book.request();
+        assert!(book.on_snapshot(snapshot(10)));
+        let depth = book.depth(None);
+        assert_eq!(depth.stream, "btcusdt@book");
+        assert_eq!(depth.bids[0].price, 99.0);
+        assert_eq!(depth.bids[0].quantity, 3.0);
+        assert_eq!(depth.bids[1].price, 98.0);
+
+        assert!(book.update(diff(13, 13, None, 0.0)));
+        assert_eq!(book.depth(None).bids[0].price, 98.0);
+        assert!(!book.update(diff(13, 13, None, 5.0)));
+
+        // a missing update drops the book until the next snapshot
+        assert!(!book.update(diff(15, 16, None, 4.0)));
+        assert!(!book.synced());
+        assert!(!book.need_snapshot());
+        assert!(!book.on_snapshot(snapshot(2)));
+        assert!(!book.synced());
+    }
+
+    #[test]
+    fn test_future_book() {
+        let mut book = LocalBook::new("ethusdt");
+        book.update(diff(5, 8, Some(4), 2.0));
+        assert!(book.on_snapshot(snapshot(8)));
+        assert_eq!(book.depth(None).bids[0].quantity, 2.0);
+
+        // future updates chain on pu and may skip ids
+        assert!(book.update(diff(11, 12, Some(8), 3.0)));
+        assert!(!book.update(diff(15, 16, Some(13), 4.0)));
+        assert!(!book.synced());
+    }
+
+    #[test]
+    fn test_pending() {
+        let mut book = LocalBook::new("btcusdt");
+        book.update(diff(1, 1, None, 1.0));
+        book.request();
+        for id in 2..=PENDING as u64 {
+            book.update(diff(id, id, None, 1.0));
+        }
+        assert_eq!(book.pending.len(), PENDING);
+        assert!(!book.need_snapshot());
+
+        // the snapshot never came, only the latest update is kept for the next one
+        let last = PENDING as u64 + 1;
+        book.update(diff(last, last, None, 2.0));
+        assert_eq!(book.pending.len(), 1);
+        assert!(book.need_snapshot());
+        assert!(book.on_snapshot(snapshot(last - 1)));
+        assert_eq!(book.depth(None).bids[0].quantity, 2.0);
+    }
+}
@@ -8,15 +8,26 @@ use serde::{
 use std::fmt::Debug;
 use xcrypto::chat::*;
 
-use crate::{ListenKey, OrderTrait};
+use crate::{DiffDepth, ListenKey, OrderTrait};
 
-fn now() -> i64 {
+pub fn now() -> i64 {
     std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
         .unwrap_or_default()
         .as_millis() as i64
 }
 
+// liquidations of every usdt future symbol
+pub const ALL_FORCE_ORDERS: &str = "!forceOrder@arr";
+
+// symbols are case insensitive, stream kinds like aggTrade are not
+pub fn normalize_stream(stream: &str) -> String {
+    match stream.split_once("@") {
+        Some((symbol, kind)) => format!("{}@{}", symbol.to_lowercase(), kind),
+        None => stream.to_lowercase(),
+    }
+}
+
 json! {BinanceBookTicker {
     stream: String,
     data: {
@@ -34,6 +45,11 @@ impl BinanceBookTicker {
     pub fn stream(&self) -> &String {
         &self.stream
     }
+
+    // spot bookTicker carries no event time
+    pub fn time(&self) -> Option<i64> {
+        self.data.E
+    }
 }
 
 impl From<BinanceBookTicker> for Depth<BinanceQuote> {
@@ -79,8 +95,8 @@ json! {BinanceKline {
 
 #[derive(Debug, Clone, Serialize)]
 pub struct BinanceQuote {
-    price: f64,
-    quantity: f64,
+    pub price: f64,
+    pub quantity: f64,
 }
 
 impl<'de> Deserialize<'de> for BinanceQuote {
@@ -133,6 +149,10 @@ impl BinanceSpotDepth {
     pub fn stream(&self) -> &String {
         &self.stream
     }
+
+    pub fn time(&self) -> Option<i64> {
+        None
+    }
 }
 
 impl From<BinanceSpotDepth> for Depth<BinanceQuote> {
@@ -159,11 +179,15 @@ impl From<BinanceSpotDepth> for Depth<BinanceQuote> {
     }
 }
 
+// also the layout of the spot and future diff depth streams
 json! {BinanceFutureDepth {
     stream:String,
     data: {
             E:i64,
             s:String,
+            U: Option<u64>,
+            u: Option<u64>,
+            pu: Option<u64>,
             b: Vec<BinanceQuote>,
             a: Vec<BinanceQuote>,
         }
@@ -174,6 +198,29 @@ impl BinanceFutureDepth {
     pub fn stream(&self) -> &String {
         &self.stream
     }
+
+    pub fn time(&self) -> Option<i64> {
+        Some(self.data.E)
+    }
+
+    // None for a partial depth without update ids
+    pub fn diff(self) -> Option<DiffDepth> {
+        Some(DiffDepth {
+            time: self.data.E,
+            first: self.data.U?,
+            last: self.data.u?,
+            prev: self.data.pu,
+            bids: self.data.b,
+            asks: self.data.a,
+        })
+    }
+}
+
+json! {BinanceSnapshot {
+    lastUpdateId: u64,
+    bids: Vec<BinanceQuote>,
+    asks: Vec<BinanceQuote>,
+    }
 }
 
 impl From<BinanceFutureDepth> for Depth<BinanceQuote> {
@@ -203,6 +250,10 @@ impl BinanceKline {
     pub fn stream(&self) -> &String {
         &self.stream
     }
+
+    pub fn time(&self) -> Option<i64> {
+        Some(self.data.E)
+    }
 }
 
 impl From<BinanceKline> for Kline {
@@ -221,6 +272,155 @@ impl From<BinanceKline> for Kline {
     }
 }
 
+// t of a trade, a of an aggregated trade
+json! {BinanceTrade {
+    stream: String,
+    data: {
+        E: i64,
+        s: String,
+        t: Option<i64>,
+        a: Option<i64>,
+        p: String,
+        q: String,
+        T: i64,
+        m: bool,
+        }
+    }
+}
+
+impl BinanceTrade {
+    pub fn stream(&self) -> &String {
+        &self.stream
+    }
+
+    pub fn time(&self) -> Option<i64> {
+        Some(self.data.E)
+    }
+}
+
+impl From<BinanceTrade> for Trade {
+    fn from(value: BinanceTrade) -> Self {
+        let symbol = value.data.s.to_lowercase();
+        let kind = value
+            .stream
+            .split_once("@")
+            .map(|(_, kind)| kind)
+            .unwrap_or("trade");
+
+        Trade {
+            time: value.data.T,
+            stream: format!("{}@{}", symbol, kind),
+            symbol,
+            id: value.data.t.or(value.data.a).unwrap_or_default(),
+            price: value.data.p.parse().unwrap_or_default(),
+            quantity: value.data.q.parse().unwrap_or_default(),
+            // the buyer is the maker, so the seller took liquidity
+            side: if value.data.m { Side::SELL } else { Side::BUY },
+        }
+    }
+}
+
+// usdt future only, P is the estimated settle price and r the funding rate
+json! {BinanceMarkPrice {
+    stream: String,
+    data: {
+        E: i64,
+        s: String,
+        p: String,
+        i: String,
+        P: String,
+        r: String,
+        T: i64,
+        }
+    }
+}
+
+impl BinanceMarkPrice {
+    pub fn stream(&self) -> &String {
+        &self.stream
+    }
+
+    pub fn time(&self) -> Option<i64> {
+        Some(self.data.E)
+    }
+}
+
+impl From<BinanceMarkPrice> for PremiumIndex {
+    fn from(value: BinanceMarkPrice) -> Self {
+        let symbol = value.data.s.to_lowercase();
+        let kind = value
+            .stream
+            .split_once("@")
+            .map(|(_, kind)| kind.replace("@", ":"))
+            .unwrap_or("markPrice".into());
+
+        PremiumIndex {
+            time: value.data.E,
+            stream: format!("{}@{}", symbol, kind),
+            symbol,
+            mark_price: value.data.p.parse().unwrap_or_default(),
+            index_price: value.data.i.parse().unwrap_or_default(),
+            estimated_settle_price: value.data.P.parse().unwrap_or_default(),
+            last_funding_rate: value.data.r.parse().unwrap_or_default(),
+            next_funding_time: value.data.T,
+        }
+    }
+}
+
+// z is the accumulated filled quantity
+#[derive(Debug, Deserialize, Clone)]
+pub struct ForceOrderData {
+    #[serde(deserialize_with = "deserialize_symbol")]
+    pub s: String,
+    pub S: Side,
+    pub p: String,
+    pub ap: String,
+    pub X: State,
+    pub q: String,
+    pub z: String,
+    pub T: i64,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ForceOrderEvent {
+    pub E: i64,
+    pub o: ForceOrderData,
+}
+
+// usdt future only
+#[derive(Debug, Deserialize, Clone)]
+pub struct BinanceForceOrder {
+    pub stream: String,
+    pub data: ForceOrderEvent,
+}
+
+impl BinanceForceOrder {
+    pub fn stream(&self) -> &String {
+        &self.stream
+    }
+
+    pub fn time(&self) -> Option<i64> {
+        Some(self.data.E)
+    }
+}
+
+impl From<BinanceForceOrder> for Liquidation {
+    fn from(value: BinanceForceOrder) -> Self {
+        let order = value.data.o;
+        Liquidation {
+            time: order.T,
+            symbol: order.s,
+            stream: value.stream,
+            side: order.S,
+            state: order.X,
+            price: order.p.parse().unwrap_or_default(),
+            avg_price: order.ap.parse().unwrap_or_default(),
+            quantity: order.q.parse().unwrap_or_default(),
+            filled: order.z.parse().unwrap_or_default(),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Clone)]
 #[serde(tag = "filterType")]
 #[allow(non_camel_case_types, unused)]
@@ -320,6 +520,10 @@ pub struct BinanceProduct {
     pub symbol: String,
     pub status: ConctactStatus,
     #[serde(default)]
+    pub baseAsset: String,
+    #[serde(default)]
+    pub quoteAsset: String,
+    #[serde(default)]
     pub deliveryDate: Option<u64>,
     #[serde(default)]
     pub onboardDate: Option<u64>,
@@ -396,7 +600,7 @@ json! {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct BinanceOrder {
     pub id: u32,
     pub symbol: String,
@@ -408,6 +612,21 @@ pub struct BinanceOrder {
     pub session_id: u16,
 }
 
+impl BinanceOrder {
+    pub fn rejected(&self) -> Order {
+        Order::new(
+            self.id,
+            self.symbol.clone(),
+            self.side,
+            State::REJECTED,
+            self.order_type.clone(),
+            self.tif.clone(),
+            self.quantity,
+            self.price,
+        )
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct BinanceCancel {
     pub symbol: String,
@@ -415,6 +634,15 @@ pub struct BinanceCancel {
     pub order_id: u32,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct BinanceAmend {
+    pub symbol: String,
+    pub session_id: u16,
+    pub order_id: u32,
+    pub price: f64,
+    pub quantity: f64,
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(untagged)]
 pub enum MarketStream {
@@ -422,6 +650,9 @@ pub enum MarketStream {
     SpotDepth(BinanceSpotDepth),
     FutureDepth(BinanceFutureDepth),
     Kline(BinanceKline),
+    Trade(BinanceTrade),
+    MarkPrice(BinanceMarkPrice),
+    ForceOrder(BinanceForceOrder),
 }
 
 // spot
@@ -454,6 +685,15 @@ json! {
     }
 }
 
+impl OutboundAccountPosition {
+    pub fn balances(&self) -> Vec<Balance> {
+        self.B
+            .iter()
+            .map(|position| spot_balance(self.E, &position.a, &position.f, &position.l))
+            .collect()
+    }
+}
+
 json! {
     BalanceUpdate {
         e: String,
@@ -464,6 +704,44 @@ json! {
     }
 }
 
+// an asset of /api/v3/account or /sapi/v1/margin/account
+json! {
+    SpotBalance {
+        asset: String,
+        free: String,
+        locked: String
+    }
+}
+
+impl SpotBalance {
+    pub fn balance(&self, time: i64) -> Balance {
+        spot_balance(time, &self.asset, &self.free, &self.locked)
+    }
+}
+
+fn spot_balance(time: i64, asset: &str, free: &str, locked: &str) -> Balance {
+    let free = free.parse().unwrap_or_default();
+    let locked = locked.parse().unwrap_or_default();
+    Balance {
+        time,
+        asset: asset.into(),
+        wallet: free + locked,
+        free,
+        locked,
+        cross_wallet: 0.0,
+        change: 0.0,
+    }
+}
+
+// one entry per symbol and position side of /fapi/v2/positionRisk
+json! {
+    PositionRisk {
+        symbol: String,
+        positionAmt: String,
+        positionSide: String
+    }
+}
+
 json! {
     SpotExpired {
         e: String,
@@ -562,6 +840,9 @@ impl OrderTrait for ExecutionReport {
     fn trd_vol(&self) -> anyhow::Result<f64> {
         Ok(self.l.parse::<f64>()?)
     }
+    fn trd_prc(&self) -> anyhow::Result<f64> {
+        Ok(self.L.parse::<f64>()?)
+    }
 }
 
 impl From<ExecutionReport> for Order {
@@ -670,11 +951,48 @@ json! {
         a: {
             m: String,
             B: [Asset],
-            P: [Position]
+            P: [UsdtPosition]
         }
     }
 }
 
+impl AccountUpdate {
+    pub fn balances(&self) -> Vec<Balance> {
+        self.a
+            .B
+            .iter()
+            .map(|asset| Balance {
+                time: self.E,
+                asset: asset.a.clone(),
+                wallet: asset.wb.parse().unwrap_or_default(),
+                free: 0.0,
+                locked: 0.0,
+                cross_wallet: asset.cw.parse().unwrap_or_default(),
+                change: asset.bc.parse().unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    pub fn positions(&self) -> Vec<ExchangePosition> {
+        self.a
+            .P
+            .iter()
+            .map(|position| ExchangePosition {
+                time: self.E,
+                symbol: position.s.to_lowercase(),
+                position_side: position.ps.clone(),
+                amount: position.pa.parse().unwrap_or_default(),
+                entry_price: position.ep.parse().unwrap_or_default(),
+                break_even_price: position.bep.parse().unwrap_or_default(),
+                unrealized_pnl: position.up.parse().unwrap_or_default(),
+                realized_pnl: position.cr.parse().unwrap_or_default(),
+                margin_type: position.mt.to_lowercase(),
+                isolated_wallet: position.iw.parse().unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 
 pub struct OrderData {
@@ -725,6 +1043,9 @@ impl OrderTrait for OrderUpdate {
     fn trd_vol(&self) -> anyhow::Result<f64> {
         Ok(self.o.l.parse::<f64>()?)
     }
+    fn trd_prc(&self) -> anyhow::Result<f64> {
+        Ok(self.o.L.parse::<f64>()?)
+    }
 }
 
 impl From<OrderUpdate> for Order {
@@ -969,6 +1290,185 @@ mod tests {
         assert_eq!(kline.amount, 1.0000);
     }
 
+    #[test]
+    fn test_trade() {
+        let s = r#"{
+                            "stream": "bnbusdt@trade",
+                            "data":{
+                                "e": "trade",
+                                "E": 123456789,
+                                "s": "BNBUSDT",
+                                "t": 12345,
+                                "p": "0.001",
+                                "q": "100",
+                                "T": 123456785,
+                                "m": true,
+                                "M": true
+                            }
+                        }"#;
+        let trade = match serde_json::from_str::<MarketStream>(s).unwrap() {
+            MarketStream::Trade(trade) => Trade::from(trade),
+            stream => panic!("unexpected {:?}", stream),
+        };
+        assert_eq!(trade.time, 123456785);
+        assert_eq!(trade.stream, "bnbusdt@trade");
+        assert_eq!(trade.id, 12345);
+        assert_eq!(trade.price, 0.001);
+        assert!(matches!(trade.side, Side::SELL));
+
+        let s = r#"{
+                            "stream": "btcusdt@aggTrade",
+                            "data":{
+                                "e": "aggTrade",
+                                "E": 123456789,
+                                "s": "BTCUSDT",
+                                "a": 5933014,
+                                "p": "0.001",
+                                "q": "100",
+                                "f": 100,
+                                "l": 105,
+                                "T": 123456785,
+                                "m": false
+                            }
+                        }"#;
+        let trade: BinanceTrade = serde_json::from_str(s).unwrap();
+        let trade: Trade = trade.into();
+        assert_eq!(trade.stream, "btcusdt@aggTrade");
+        assert_eq!(trade.id, 5933014);
+        assert_eq!(trade.quantity, 100.0);
+        assert!(matches!(trade.side, Side::BUY));
+    }
+
+    #[test]
+    fn test_mark_price() {
+        let s = r#"{
+                            "stream": "btcusdt@markPrice@1s",
+                            "data":{
+                                "e": "markPriceUpdate",
+                                "E": 1562305380000,
+                                "s": "BTCUSDT",
+                                "p": "11794.15000000",
+                                "ap": "11794.15000000",
+                                "i": "11784.62659091",
+                                "P": "11784.25641265",
+                                "r": "0.00038167",
+                                "T": 1562306400000
+                            }
+                        }"#;
+        let index = match serde_json::from_str::<MarketStream>(s).unwrap() {
+            MarketStream::MarkPrice(index) => PremiumIndex::from(index),
+            stream => panic!("unexpected {:?}", stream),
+        };
+        assert_eq!(index.time, 1562305380000);
+        assert_eq!(index.stream, "btcusdt@markPrice:1s");
+        assert_eq!(index.mark_price, 11794.15);
+        assert_eq!(index.index_price, 11784.62659091);
+        assert_eq!(index.estimated_settle_price, 11784.25641265);
+        assert_eq!(index.last_funding_rate, 0.00038167);
+        assert_eq!(index.next_funding_time, 1562306400000);
+    }
+
+    #[test]
+    fn test_force_order() {
+        let s = r#"{
+                            "stream": "!forceOrder@arr",
+                            "data":{
+                                "e": "forceOrder",
+                                "E": 1568014460893,
+                                "o": {
+                                    "s": "BTCUSDT",
+                                    "S": "SELL",
+                                    "o": "LIMIT",
+                                    "f": "IOC",
+                                    "q": "0.014",
+                                    "p": "9910",
+                                    "ap": "9910",
+                                    "X": "FILLED",
+                                    "l": "0.014",
+                                    "z": "0.014",
+                                    "T": 1568014460893
+                                }
+                            }
+                        }"#;
+        let liquidation = match serde_json::from_str::<MarketStream>(s).unwrap() {
+            MarketStream::ForceOrder(order) => Liquidation::from(order),
+            stream => panic!("unexpected {:?}", stream),
+        };
+        assert_eq!(liquidation.symbol, "btcusdt");
+        assert_eq!(liquidation.stream, ALL_FORCE_ORDERS);
+        assert!(matches!(liquidation.side, Side::SELL));
+        assert!(matches!(liquidation.state, State::FILLED));
+        assert_eq!(liquidation.avg_price, 9910.0);
+        assert_eq!(liquidation.filled, 0.014);
+    }
+
+    #[test]
+    fn test_account_update() {
+        let s = r#"{
+                            "e": "ACCOUNT_UPDATE",
+                            "E": 1564745798939,
+                            "T": 1564745798938,
+                            "a": {
+                                "m": "ORDER",
+                                "B": [
+                                    {"a": "USDT", "wb": "122624.12345678", "cw": "100.12345678", "bc": "50.12345678"}
+                                ],
+                                "P": [
+                                    {"s": "BTCUSDT", "pa": "0", "ep": "0.00000", "bep": "0", "cr": "200",
+                                     "up": "0", "mt": "isolated", "iw": "0.00000000", "ps": "BOTH"},
+                                    {"s": "BTCUSDT", "pa": "20", "ep": "6563.66500", "bep": "6563.6", "cr": "0",
+                                     "up": "2850.21200", "mt": "isolated", "iw": "13200.70726908", "ps": "LONG"}
+                                ]
+                            }
+                        }"#;
+        let update = match serde_json::from_str::<Event>(s).unwrap() {
+            Event::AccountUpdate(update) => update,
+            event => panic!("unexpected {:?}", event),
+        };
+        let balances = update.balances();
+        assert_eq!(balances[0].asset, "USDT");
+        assert_eq!(balances[0].wallet, 122624.12345678);
+        assert_eq!(balances[0].change, 50.12345678);
+
+        let positions = update.positions();
+        assert_eq!(positions.len(), 2);
+        assert_eq!(positions[1].symbol, "btcusdt");
+        assert_eq!(positions[1].position_side, "LONG");
+        assert_eq!(positions[1].amount, 20.0);
+        assert_eq!(positions[1].entry_price, 6563.665);
+        assert_eq!(positions[1].unrealized_pnl, 2850.212);
+        assert_eq!(positions[0].realized_pnl, 200.0);
+        assert_eq!(positions[0].margin_type, "isolated");
+    }
+
+    #[test]
+    fn test_outbound_account_position() {
+        let s = r#"{
+                            "e": "outboundAccountPosition",
+                            "E": 1564034571105,
+                            "u": 1564034571073,
+                            "B": [
+                                {"a": "ETH", "f": "10000.000000", "l": "0.500000"}
+                            ]
+                        }"#;
+        let update = match serde_json::from_str::<Event>(s).unwrap() {
+            Event::OutboundAccountPosition(update) => update,
+            event => panic!("unexpected {:?}", event),
+        };
+        let balances = update.balances();
+        assert_eq!(balances[0].time, 1564034571105);
+        assert_eq!(balances[0].asset, "ETH");
+        assert_eq!(balances[0].free, 10000.0);
+        assert_eq!(balances[0].locked, 0.5);
+        assert_eq!(balances[0].wallet, 10000.5);
+
+        let s = r#"{"e": "balanceUpdate", "E": 1573200697110, "a": "BTC", "d": "100.00000000", "T": 1573200697068}"#;
+        assert!(matches!(
+            serde_json::from_str::<Event>(s).unwrap(),
+            Event::Balance(_)
+        ));
+    }
+
     #[test]
     fn test_product() {
         // spot
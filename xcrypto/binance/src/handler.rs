@@ -1,5 +1,7 @@
 use crate::market::Market;
-use crate::{BinanceCancel, BinanceOrder, Trade};
+use crate::{
+    client_order_id, Batch, BinanceAmend, BinanceCancel, BinanceOrder, Risk, Throttle, Trade,
+};
 use log::*;
 use std::collections::HashMap;
 use std::net::SocketAddr;
@@ -9,14 +11,26 @@ use tokio::signal::unix::{signal, SignalKind};
 use tokio::signal::windows::{ctrl_break, ctrl_c};
 
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
-use tokio::time::Duration;
-use xcrypto::chat::{Login, PositionReq, PositionRsp, Request};
+use tokio::time::{Duration, Instant};
+use xcrypto::chat::{
+    BalanceRsp, Error, LatencyRsp, Login, OrderReq, PositionReq, PositionRsp, Request, TradeReq,
+    TradeRsp,
+};
+use xcrypto::error::UNKNOWN_ORDER;
 use xcrypto::parser::Parser;
 use xcrypto::tungstenite::Message;
 use xcrypto::ws::Connection;
 
 pub struct Handler {
     channels: HashMap<SocketAddr, (UnboundedSender<Message>, UnboundedReceiver<Message>)>,
+    risk: Option<Risk>,
+    throttle: Throttle,
+    // addr -> session_id of trading logins
+    sessions: HashMap<SocketAddr, u16>,
+    // session_id -> grace period before its open orders are canceled on disconnect
+    cancel_on_disconnect: HashMap<u16, Duration>,
+    // session_id -> when its open orders are canceled
+    expires: HashMap<u16, Instant>,
     keep_running: bool,
 }
 
@@ -24,10 +38,27 @@ impl Handler {
     pub fn new() -> Self {
         Self {
             channels: HashMap::default(),
+            risk: None,
+            throttle: Throttle::default(),
+            sessions: HashMap::default(),
+            cancel_on_disconnect: HashMap::default(),
+            expires: HashMap::default(),
             keep_running: false,
         }
     }
 
+    pub fn risk(&mut self, risk: Risk) {
+        self.risk = Some(risk);
+    }
+
+    pub fn throttle(&mut self, throttle: Throttle) {
+        self.throttle = throttle;
+    }
+
+    pub fn cancel_on_disconnect(&mut self, policy: HashMap<u16, Duration>) {
+        self.cancel_on_disconnect = policy;
+    }
+
     fn on_message(&mut self, addr: &SocketAddr, msg: &Message) -> Option<Parser> {
         if let Some((tx, _)) = self.channels.get_mut(addr) {
             match &msg {
@@ -75,7 +106,14 @@ impl Handler {
             if params.trading {
                 match trade.handle_login(addr, &req, tx).await? {
                     Some(e) => market.reply(addr, req.id, e)?,
-                    None => market.handle_login(addr, &req)?,
+                    None => {
+                        // reconnected in time, keep the orders
+                        if self.expires.remove(&params.session_id).is_some() {
+                            info!("Session {} is back, keep its orders", params.session_id);
+                        }
+                        self.sessions.insert(*addr, params.session_id);
+                        market.handle_login(addr, &req)?
+                    }
                 }
             } else {
                 market.handle_login(addr, &req)?;
@@ -176,6 +214,92 @@ impl Handler {
         Ok(())
     }
 
+    fn handle_get_orders<T: Trade>(
+        &self,
+        addr: &SocketAddr,
+        parser: &Parser,
+        market: &mut Market,
+        trade: &mut T,
+    ) -> anyhow::Result<()> {
+        let req: Request<OrderReq> = parser.decode()?;
+        info!("{:?}", req);
+
+        let params = trade.oms().get_orders(req.params.session_id);
+        market.reply(addr, req.id, params)
+    }
+
+    fn handle_get_balances<T: Trade>(
+        &self,
+        addr: &SocketAddr,
+        parser: &Parser,
+        market: &mut Market,
+        trade: &mut T,
+    ) -> anyhow::Result<()> {
+        let req = parser.decode::<Request<Vec<String>>>()?;
+        info!("{:?}", req);
+
+        // only trading logins have an account
+        let balances = match self.sessions.get(addr) {
+            Some(session_id) => trade
+                .get_balances(*session_id)
+                .into_iter()
+                .filter(|balance| req.params.is_empty() || req.params.contains(&balance.asset))
+                .collect(),
+            None => Vec::new(),
+        };
+        market.reply(addr, req.id, BalanceRsp { balances })
+    }
+
+    fn handle_get_latency<T: Trade>(
+        &self,
+        addr: &SocketAddr,
+        parser: &Parser,
+        market: &mut Market,
+        trade: &mut T,
+    ) -> anyhow::Result<()> {
+        let req = parser.decode::<Request<Vec<String>>>()?;
+        info!("{:?}", req);
+
+        // the account of a trading login, the simulator has no exchange to time
+        let rest = self
+            .sessions
+            .get(addr)
+            .and_then(|session_id| trade.rest(*session_id));
+        let rsp = match rest {
+            Some(rest) => LatencyRsp {
+                clock: rest.time_sync().lock().unwrap().estimate(),
+                endpoints: rest
+                    .latency()
+                    .lock()
+                    .unwrap()
+                    .stats()
+                    .into_iter()
+                    .filter(|stats| req.params.is_empty() || req.params.contains(&stats.endpoint))
+                    .collect(),
+            },
+            None => LatencyRsp {
+                clock: None,
+                endpoints: Vec::new(),
+            },
+        };
+        market.reply(addr, req.id, rsp)
+    }
+
+    async fn handle_get_trades<T: Trade>(
+        &self,
+        addr: &SocketAddr,
+        parser: &Parser,
+        market: &mut Market,
+        trade: &mut T,
+    ) -> anyhow::Result<()> {
+        let req: Request<TradeReq> = parser.decode()?;
+        info!("{:?}", req);
+
+        let trades = trade.get_trades(&req.params).await?;
+        let session_id = req.params.session_id;
+        market.reply(addr, req.id, TradeRsp { session_id, trades })
+    }
+
     #[allow(unused)]
     async fn handle_order<T: Trade>(
         &mut self,
@@ -187,7 +311,22 @@ impl Handler {
         let req = parser.decode::<Request<BinanceOrder>>()?;
         info!("{:?}", req);
 
-        trade.add_order(addr, &req.params)
+        let order = &req.params;
+        let reject = self
+            .risk
+            .as_ref()
+            .and_then(|risk| risk.check(order, trade))
+            .or_else(|| self.throttle.check(order.session_id, 1, trade));
+        if let Some(e) = reject {
+            warn!("Risk reject {:?}", e);
+            market.reply(addr, req.id, e)?;
+            market.notify(addr, &order.rejected())?;
+            return Ok(());
+        }
+        trade.oms().sent().insert(order);
+
+        self.warn_rate_limits(addr, order.session_id, market, trade)?;
+        trade.add_order(addr, order)
     }
 
     #[allow(unused)]
@@ -204,6 +343,125 @@ impl Handler {
         trade.cancel(addr, &req.params)
     }
 
+    async fn handle_amend<T: Trade>(
+        &mut self,
+        addr: &SocketAddr,
+        parser: &Parser,
+        market: &mut Market,
+        trade: &mut T,
+    ) -> anyhow::Result<()> {
+        let req = parser.decode::<Request<BinanceAmend>>()?;
+        info!("{:?}", req);
+
+        let amend = &req.params;
+        let key = client_order_id(amend.session_id, amend.order_id);
+        // placed before a restart or already done, there is nothing to amend
+        let order = match trade.oms().get(key) {
+            Some(order) => order,
+            None => {
+                let e = Error {
+                    code: UNKNOWN_ORDER,
+                    msg: format!("unknown order {} to amend", amend.order_id),
+                };
+                warn!("Amend reject {:?}", e);
+                return market.reply(addr, req.id, e);
+            }
+        };
+        if let Some(risk) = self.risk.as_ref() {
+            let order = BinanceOrder {
+                id: amend.order_id,
+                symbol: order.symbol.clone(),
+                price: amend.price,
+                quantity: amend.quantity,
+                side: order.side,
+                order_type: order.order_type.clone(),
+                tif: order.tif.clone(),
+                session_id: amend.session_id,
+            };
+
+            // the order keeps working with its current price and quantity
+            if let Some(e) = risk.check_amend(&order, trade) {
+                warn!("Risk reject {:?}", e);
+                return market.reply(addr, req.id, e);
+            }
+        }
+
+        if let Some(e) = self.throttle.check(amend.session_id, 1, trade) {
+            warn!("Risk reject {:?}", e);
+            return market.reply(addr, req.id, e);
+        }
+
+        self.warn_rate_limits(addr, amend.session_id, market, trade)?;
+        trade.amend(addr, req.id, amend)
+    }
+
+    async fn handle_batch_order<T: Trade>(
+        &mut self,
+        addr: &SocketAddr,
+        parser: &Parser,
+        market: &mut Market,
+        trade: &mut T,
+    ) -> anyhow::Result<()> {
+        let req = parser.decode::<Request<Vec<BinanceOrder>>>()?;
+        info!("{:?}", req);
+
+        let mut batch = Batch::new(req.id, req.params.iter().map(|order| order.id));
+        let mut orders = Vec::new();
+        for order in req.params {
+            // the orders already taken count against the risk and rate limits of the rest
+            let sending = orders.len() as u64 + 1;
+            match self
+                .risk
+                .as_ref()
+                .and_then(|risk| risk.check(&order, trade))
+                .or_else(|| self.throttle.check(order.session_id, sending, trade))
+            {
+                Some(e) => {
+                    warn!("Risk reject {:?}", e);
+                    market.notify(addr, &order.rejected())?;
+                    batch.reject(order.id, e);
+                }
+                None => {
+                    trade.oms().sent().insert(&order);
+                    orders.push(order);
+                }
+            }
+        }
+
+        if let Some(order) = orders.first() {
+            self.warn_rate_limits(addr, order.session_id, market, trade)?;
+        }
+        trade.batch_order(addr, batch, orders)
+    }
+
+    // tell the session once a limit of its account is getting close
+    fn warn_rate_limits<T: Trade>(
+        &mut self,
+        addr: &SocketAddr,
+        session_id: u16,
+        market: &mut Market,
+        trade: &T,
+    ) -> anyhow::Result<()> {
+        for usage in self.throttle.warnings(session_id, trade) {
+            warn!("Rate limit {:?} of session {}", usage, session_id);
+            market.notify(addr, &usage)?;
+        }
+        Ok(())
+    }
+
+    async fn handle_batch_cancel<T: Trade>(
+        &mut self,
+        addr: &SocketAddr,
+        parser: &Parser,
+        trade: &mut T,
+    ) -> anyhow::Result<()> {
+        let req = parser.decode::<Request<Vec<BinanceCancel>>>()?;
+        info!("{:?}", req);
+
+        let batch = Batch::new(req.id, req.params.iter().map(|cancel| cancel.order_id));
+        trade.batch_cancel(addr, batch, req.params)
+    }
+
     async fn handle_request<T: Trade>(
         &mut self,
         addr: &SocketAddr,
@@ -215,8 +473,11 @@ impl Handler {
             return market.handle_disconnect(addr, &parser);
         }
 
-        if trade.disconnected() {
-            return trade.handle_disconnect(addr, &parser);
+        // only the trading logins on an account whose stream is down wait for it
+        if let Some(session_id) = self.sessions.get(addr) {
+            if trade.session_disconnected(*session_id) {
+                return trade.handle_disconnect(addr, &parser);
+            }
         }
 
         if let Some(val) = parser.get("method") {
@@ -226,8 +487,18 @@ impl Handler {
                     "subscribe" => self.handle_subscribe(addr, &parser, market, trade).await?,
                     "get_products" => self.handle_get_products(addr, &parser, market, trade)?,
                     "get_positions" => self.handle_get_positions(addr, &parser, market, trade)?,
+                    "get_orders" => self.handle_get_orders(addr, &parser, market, trade)?,
+                    "get_balances" => self.handle_get_balances(addr, &parser, market, trade)?,
+                    "get_trades" => self.handle_get_trades(addr, &parser, market, trade).await?,
+                    "get_latency" => self.handle_get_latency(addr, &parser, market, trade)?,
                     "order" => self.handle_order(addr, &parser, market, trade).await?,
                     "cancel" => self.handle_cancel(addr, &parser, market, trade).await?,
+                    "amend" => self.handle_amend(addr, &parser, market, trade).await?,
+                    "batch_order" => {
+                        self.handle_batch_order(addr, &parser, market, trade)
+                            .await?
+                    }
+                    "batch_cancel" => self.handle_batch_cancel(addr, &parser, trade).await?,
                     _ => (),
                 }
             }
@@ -320,6 +591,8 @@ impl Handler {
                     }
                 }
             }
+
+            self.expire(trade);
         }
 
         Ok(())
@@ -335,9 +608,38 @@ impl Handler {
         market.handle_close(addr).await?;
         trade.handle_close(addr)?;
 
+        if let Some(session_id) = self.sessions.remove(addr) {
+            if let Some(grace) = self.cancel_on_disconnect.get(&session_id) {
+                info!(
+                    "Session {} disconnected, cancel its orders in {:?}",
+                    session_id, grace
+                );
+                self.expires.insert(session_id, Instant::now() + *grace);
+                self.expire(trade);
+            }
+        }
+
         Ok(())
     }
 
+    fn expire<T: Trade>(&mut self, trade: &mut T) {
+        let now = Instant::now();
+        let expired: Vec<_> = self
+            .expires
+            .iter()
+            .filter(|(_, deadline)| **deadline <= now)
+            .map(|(session_id, _)| *session_id)
+            .collect();
+
+        for session_id in expired {
+            self.expires.remove(&session_id);
+            info!("Cancel open orders of session {}", session_id);
+            if let Err(e) = trade.cancel_all(session_id) {
+                error!("{}", e);
+            }
+        }
+    }
+
     pub fn stop(&mut self) {
         info!("Handler stop process");
         self.keep_running = false;
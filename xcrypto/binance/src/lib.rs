@@ -1,20 +1,37 @@
 pub mod account;
 pub mod app;
+pub mod batch;
+pub mod book;
 pub mod chat;
 pub mod handler;
 pub mod market;
+pub mod oms;
+pub mod reconcile;
+pub mod recorder;
+pub mod risk;
 pub mod session;
+pub mod sim;
 pub mod subscriber;
+pub mod throttle;
 
 pub use account::*;
 pub use app::*;
+pub use batch::*;
+pub use book::*;
 pub use chat::*;
 pub use handler::*;
 pub use market::*;
+pub use oms::*;
+pub use reconcile::*;
+pub use recorder::*;
+pub use risk::*;
 pub use session::*;
+pub use sim::*;
 use std::future::Future;
 pub use subscriber::*;
+pub use throttle::*;
 
+use log::*;
 use serde::Serialize;
 use std::collections::HashMap;
 use std::fmt::Debug;
@@ -22,16 +39,41 @@ use std::net::SocketAddr;
 use tokio::sync::mpsc::UnboundedSender;
 use xcrypto::chat::*;
 use xcrypto::parser::Parser;
+use xcrypto::rest::Rest;
 use xcrypto::tungstenite::Message;
 
 pub trait Trade {
+    // any connection to the exchange is down
     fn disconnected(&self) -> bool;
+    // the connection the session trades through is down
+    fn session_disconnected(&self, session_id: u16) -> bool;
     fn products(&self) -> &HashMap<String, BinanceProduct>;
     fn get_positions(&self, session_id: u16) -> Option<&HashMap<String, Position>>;
+    fn oms(&self) -> &Oms;
+    // the balances of the account the session trades on
+    fn get_balances(&self, session_id: u16) -> Vec<Balance>;
+    // the rest of the account the session trades on, none when orders never reach the exchange
+    fn rest(&self, session_id: u16) -> Option<&Rest>;
+    fn get_trades(&self, req: &TradeReq) -> impl Future<Output = anyhow::Result<Vec<Fill>>> + Send;
     fn get_products(&mut self) -> impl Future<Output = anyhow::Result<()>> + Send;
     fn process(&mut self) -> impl Future<Output = anyhow::Result<bool>> + Send;
     fn add_order(&mut self, addr: &SocketAddr, order: &BinanceOrder) -> anyhow::Result<()>;
     fn cancel(&mut self, addr: &SocketAddr, cancel: &BinanceCancel) -> anyhow::Result<()>;
+    fn cancel_all(&mut self, session_id: u16) -> anyhow::Result<()>;
+    // id is the request, a failed amend is answered with an Error to it
+    fn amend(&mut self, addr: &SocketAddr, id: i64, amend: &BinanceAmend) -> anyhow::Result<()>;
+    fn batch_order(
+        &mut self,
+        addr: &SocketAddr,
+        batch: Batch,
+        orders: Vec<BinanceOrder>,
+    ) -> anyhow::Result<()>;
+    fn batch_cancel(
+        &mut self,
+        addr: &SocketAddr,
+        batch: Batch,
+        cancels: Vec<BinanceCancel>,
+    ) -> anyhow::Result<()>;
     fn handle_close(&mut self, addr: &SocketAddr) -> anyhow::Result<()>;
     fn handle_login(
         &mut self,
@@ -51,9 +93,22 @@ pub trait Trade {
     fn reconncet(&mut self) -> impl Future<Output = anyhow::Result<()>> + Send;
 }
 
+// serialized to a strategy, a strategy gone is only logged
+pub fn send<T: Serialize>(tx: &UnboundedSender<Message>, data: &T) {
+    match serde_json::to_string(data) {
+        Ok(s) => {
+            if let Err(e) = tx.send(Message::Text(s)) {
+                error!("{}", e);
+            }
+        }
+        Err(e) => error!("{}", e),
+    }
+}
+
 pub trait OrderTrait {
     fn symbol(&self) -> &str;
     fn trd_vol(&self) -> anyhow::Result<f64>;
+    fn trd_prc(&self) -> anyhow::Result<f64>;
     fn commission(&self) -> f64;
     fn net(&self) -> anyhow::Result<f64>;
     fn side(&self) -> Side;
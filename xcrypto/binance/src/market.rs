@@ -1,22 +1,41 @@
 use crate::chat::Event;
-use crate::{BinanceQuote, MarketStream, Subscriber, Trade};
+use crate::{
+    now, BinanceQuote, BinanceSnapshot, DiffDepth, LocalBook, MarketStream, Recorder, Subscriber,
+    Trade,
+};
 use log::*;
 use serde::{Deserialize, Serialize};
 use std::fmt::format;
 use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
 use std::{collections::HashMap, fmt::Debug};
-use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 use tokio::time::{Duration, Instant};
 use xcrypto::parser::Parser;
+use xcrypto::rest::Rest;
 use xcrypto::ws::WebSocket;
 use xcrypto::{chat::*, error::*, tungstenite::Message};
 
+// diff depth stream and its snapshot
+type Snapshot = (String, anyhow::Result<BinanceSnapshot>);
+
+// symbol -> latest (bid, ask), overwritten by every bbo/depth and emptied while the stream is down
+pub type Bbo = Arc<Mutex<HashMap<String, (f64, f64)>>>;
+
 pub struct Market {
     addr: String,
     txs: HashMap<SocketAddr, UnboundedSender<Message>>,
     subscribers: HashMap<SocketAddr, Subscriber>,
     symbols: HashMap<String, u16>,
     requests: HashMap<i64, SocketAddr>,
+    feeds: Vec<UnboundedSender<Depth<BinanceQuote>>>,
+    bbo: Bbo,
+    recorder: Option<Recorder>,
+    // rest and path of depth snapshots, books are available once set
+    snapshot: Option<(Arc<Rest>, String)>,
+    // diff depth stream -> local book
+    books: HashMap<String, LocalBook>,
+    snapshots: (UnboundedSender<Snapshot>, UnboundedReceiver<Snapshot>),
     ws: WebSocket,
     disconnected: bool,
     id: i64,
@@ -35,6 +54,12 @@ impl Market {
             subscribers: HashMap::default(),
             symbols: HashMap::default(),
             requests: HashMap::default(),
+            feeds: Vec::default(),
+            bbo: Bbo::default(),
+            recorder: None,
+            snapshot: None,
+            books: HashMap::default(),
+            snapshots: unbounded_channel(),
             ws,
             disconnected: false,
             id: 1,
@@ -46,6 +71,44 @@ impl Market {
         self.disconnected
     }
 
+    // every bbo/depth forwarded to subscribers is also published to the feed
+    pub fn feed(&mut self) -> UnboundedReceiver<Depth<BinanceQuote>> {
+        let (tx, rx) = unbounded_channel();
+        self.feeds.push(tx);
+        rx
+    }
+
+    // the latest prices only, for readers that don't need every update
+    pub fn bbo(&self) -> Bbo {
+        self.bbo.clone()
+    }
+
+    pub fn record(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+    }
+
+    pub fn book(&mut self, rest: Arc<Rest>, path: &str) {
+        self.snapshot = Some((rest, path.into()));
+    }
+
+    fn publish(&mut self, depth: &Depth<BinanceQuote>) {
+        if let (Some(bid), Some(ask)) = (depth.bids.first(), depth.asks.first()) {
+            self.bbo
+                .lock()
+                .unwrap()
+                .insert(depth.symbol.clone(), (bid.price, ask.price));
+        }
+        self.feeds.retain(|tx| tx.send(depth.clone()).is_ok());
+    }
+
+    fn persist<T: Serialize>(&self, symbol: &str, time: Option<i64>, local: i64, data: &T) {
+        if let Some(recorder) = &self.recorder {
+            if let Err(e) = recorder.record(symbol, time, local, data) {
+                error!("{}", e);
+            }
+        }
+    }
+
     async fn subscribe(&mut self, symbols: Vec<String>) -> anyhow::Result<()> {
         let req: Request<Vec<String>> = Request {
             id: self.id,
@@ -101,6 +164,18 @@ impl Market {
         Ok(())
     }
 
+    pub fn notify<T: Serialize + Debug>(
+        &mut self,
+        addr: &SocketAddr,
+        data: &T,
+    ) -> anyhow::Result<()> {
+        if let Some(tx) = self.txs.get_mut(addr) {
+            debug!("{:?}", data);
+            tx.send(Message::Text(serde_json::to_string(data)?))?;
+        }
+        Ok(())
+    }
+
     pub async fn reconncet<T: Trade>(&mut self, trade: &mut T) -> anyhow::Result<()> {
         if !self.disconnected {
             return Ok(());
@@ -145,10 +220,18 @@ impl Market {
     }
 
     pub async fn process(&mut self) -> anyhow::Result<bool> {
-        match self.ws.recv().await? {
+        let msg = tokio::select! {
+            msg = self.ws.recv() => msg?,
+            Some((stream, snapshot)) = self.snapshots.1.recv() => {
+                self.handle_snapshot(stream, snapshot);
+                return Ok(self.disconnected);
+            }
+        };
+
+        match msg {
             Some(inner) => match &inner {
                 Message::Text(s) => match serde_json::from_str::<Event>(s) {
-                    Ok(e) => self.handle_event(e),
+                    Ok(e) => self.handle_event(e, now()),
                     Err(e) => error!("{} {}", e, inner),
                 },
                 Message::Ping(ping) => {
@@ -160,7 +243,8 @@ impl Market {
             None => {
                 if !self.disconnected {
                     error!("market disconnected");
-                    self.disconnected = true
+                    self.disconnected = true;
+                    self.bbo.lock().unwrap().clear();
                 }
             }
         }
@@ -184,6 +268,7 @@ impl Market {
                             if *cnt == 0 {
                                 if let Some(_) = self.symbols.remove(symbol) {
                                     info!("Unsubscribe {}", symbol);
+                                    self.books.remove(symbol);
                                     unsubscribe.push(symbol.replace(":", "_"));
                                 }
                             }
@@ -220,44 +305,156 @@ impl Market {
         }
     }
 
-    fn handle_stream(&mut self, stream: MarketStream) -> anyhow::Result<()> {
-        let s = match &stream {
-            MarketStream::BookTicker(book) => book.stream().clone(),
-            MarketStream::Kline(kline) => kline.stream().clone(),
-            MarketStream::SpotDepth(depth) => depth.stream().clone(),
-            MarketStream::FutureDepth(depth) => depth.stream().clone(),
+    fn handle_stream(&mut self, stream: MarketStream, local: i64) -> anyhow::Result<()> {
+        let (s, time) = match &stream {
+            MarketStream::BookTicker(book) => (book.stream().clone(), book.time()),
+            MarketStream::Kline(kline) => (kline.stream().clone(), kline.time()),
+            MarketStream::SpotDepth(depth) => (depth.stream().clone(), depth.time()),
+            MarketStream::FutureDepth(depth) => (depth.stream().clone(), depth.time()),
+            MarketStream::Trade(trade) => (trade.stream().clone(), trade.time()),
+            MarketStream::MarkPrice(index) => (index.stream().clone(), index.time()),
+            MarketStream::ForceOrder(order) => (order.stream().clone(), order.time()),
         };
 
         let data = match stream {
             MarketStream::BookTicker(book) => {
                 let depth: Depth<BinanceQuote> = book.into();
+                self.publish(&depth);
+                self.persist(&depth.symbol, time, local, &depth);
                 serde_json::to_string(&depth)?
             }
             MarketStream::Kline(kline) => {
                 let kline: Kline = kline.into();
+                self.persist(&kline.symbol, time, local, &kline);
                 serde_json::to_string(&kline)?
             }
+            MarketStream::Trade(trade) => {
+                let trade: xcrypto::chat::Trade = trade.into();
+                self.persist(&trade.symbol, time, local, &trade);
+                serde_json::to_string(&trade)?
+            }
+            MarketStream::MarkPrice(index) => {
+                let index: PremiumIndex = index.into();
+                self.persist(&index.symbol, time, local, &index);
+                serde_json::to_string(&index)?
+            }
+            MarketStream::ForceOrder(order) => {
+                let liquidation: Liquidation = order.into();
+                self.persist(&liquidation.symbol, time, local, &liquidation);
+                serde_json::to_string(&liquidation)?
+            }
             MarketStream::SpotDepth(depth) => {
                 let depth: Depth<BinanceQuote> = depth.into();
+                self.publish(&depth);
+                self.persist(&depth.symbol, time, local, &depth);
                 serde_json::to_string(&depth)?
             }
+            MarketStream::FutureDepth(depth) if self.books.contains_key(&s) => {
+                return self.handle_diff(&s, depth.diff());
+            }
             MarketStream::FutureDepth(depth) => {
                 let depth: Depth<BinanceQuote> = depth.into();
+                self.publish(&depth);
+                self.persist(&depth.symbol, time, local, &depth);
                 serde_json::to_string(&depth)?
             }
         };
 
+        self.forward(&s, &data);
+        Ok(())
+    }
+
+    fn forward(&mut self, stream: &String, data: &String) {
         for subscriber in self.subscribers.values_mut() {
-            if subscriber.is_subscribed(&s) {
-                if let Err(e) = subscriber.forward(&data) {
+            if subscriber.is_subscribed(stream) {
+                if let Err(e) = subscriber.forward(data) {
                     error!("{}", e);
                 }
             }
         }
+    }
 
+    fn handle_diff(&mut self, stream: &String, diff: Option<DiffDepth>) -> anyhow::Result<()> {
+        let book = match (self.books.get_mut(stream), diff) {
+            (Some(book), Some(diff)) => {
+                let time = diff.time;
+                if book.update(diff) {
+                    Some(book.depth(Some(time)))
+                } else {
+                    if book.need_snapshot() {
+                        book.request();
+                        self.request_snapshot(stream);
+                    }
+                    None
+                }
+            }
+            (_, None) => {
+                warn!("Missing update ids of {}", stream);
+                None
+            }
+            _ => None,
+        };
+
+        if let Some(depth) = book {
+            self.publish(&depth);
+            self.persist(&depth.symbol, Some(depth.time), now(), &depth);
+            self.forward(stream, &serde_json::to_string(&depth)?);
+        }
         Ok(())
     }
 
+    fn request_snapshot(&self, stream: &str) {
+        let (rest, path) = match &self.snapshot {
+            Some((rest, path)) => (rest.clone(), path.clone()),
+            None => return,
+        };
+        let symbol = match stream.split_once("@") {
+            Some((symbol, _)) => symbol.to_uppercase(),
+            None => return,
+        };
+
+        let tx = self.snapshots.0.clone();
+        let stream = stream.to_string();
+        tokio::spawn(async move {
+            let params = [
+                ("symbol".to_string(), symbol),
+                ("limit".to_string(), "1000".to_string()),
+            ];
+            let snapshot = async {
+                let rsp = rest.get(&path, &params, false).await?;
+                anyhow::Ok(rsp.json::<BinanceSnapshot>().await?)
+            }
+            .await;
+            let _ = tx.send((stream, snapshot));
+        });
+    }
+
+    fn handle_snapshot(&mut self, stream: String, snapshot: anyhow::Result<BinanceSnapshot>) {
+        let depth = match (self.books.get_mut(&stream), snapshot) {
+            (Some(book), Ok(snapshot)) => {
+                if book.on_snapshot(snapshot) {
+                    Some(book.depth(None))
+                } else {
+                    None
+                }
+            }
+            (_, Err(e)) => {
+                error!("Snapshot of {} failed {}", stream, e);
+                None
+            }
+            _ => None,
+        };
+
+        if let Some(depth) = depth {
+            self.publish(&depth);
+            self.persist(&depth.symbol, None, depth.time, &depth);
+            match serde_json::to_string(&depth) {
+                Ok(data) => self.forward(&stream, &data),
+                Err(e) => error!("{}", e),
+            }
+        }
+    }
+
     pub fn handle_connect(&mut self, addr: &SocketAddr, tx: &UnboundedSender<Message>) {
         self.txs.insert(addr.clone(), tx.clone());
     }
@@ -289,6 +486,17 @@ impl Market {
             );
         }
 
+        if self.snapshot.is_none() && req.params.iter().any(|s| s.ends_with("@book")) {
+            return self.reply(
+                addr,
+                req.id,
+                Error {
+                    code: INVALID_STREAM,
+                    msg: "book isn't enabled".into(),
+                },
+            );
+        }
+
         if let Some(subscriber) = self.subscribers.get_mut(addr) {
             let mut symbols = Vec::new();
             for symbol in req.params.iter() {
@@ -300,8 +508,12 @@ impl Market {
                     symbol.replace(":", "_")
                 } else if symbol.contains("bbo") {
                     symbol.replace("bbo", "bookTicker")
+                } else if symbol.ends_with("@book") {
+                    symbol.replace("@book", "@depth@100ms")
                 } else if symbol.contains("depth") {
                     symbol.replace("depth", "depth20").replace(":", "@")
+                } else if symbol.contains("markPrice") {
+                    symbol.replace(":", "@")
                 } else {
                     symbol.clone()
                 };
@@ -310,6 +522,9 @@ impl Market {
                     Some(cnt) => *cnt += 1,
                     None => {
                         self.symbols.insert(symbol.clone(), 1);
+                        if let Some((name, _)) = symbol.split_once("@depth@") {
+                            self.books.insert(symbol.clone(), LocalBook::new(name));
+                        }
                     }
                 }
 
@@ -325,13 +540,13 @@ impl Market {
         Ok(())
     }
 
-    fn handle_event(&mut self, event: Event) {
+    fn handle_event(&mut self, event: Event, local: i64) {
         debug!("{:?}", event);
         match event {
             Event::Success(suc) => self.handle_success(suc),
             Event::Error(e) => self.handle_error(e),
             Event::Stream(stream) => {
-                if let Err(e) = self.handle_stream(stream) {
+                if let Err(e) = self.handle_stream(stream, local) {
                     error!("{}", e)
                 }
             }
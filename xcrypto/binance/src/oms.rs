@@ -0,0 +1,212 @@
+use crate::{send, BinanceOrder};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::UnboundedSender;
+use xcrypto::chat::*;
+use xcrypto::tungstenite::Message;
+
+// finished orders kept per session
+const FINISHED: usize = 100;
+
+pub fn client_order_id(session_id: u16, id: u32) -> u64 {
+    u64::from(session_id) << 32 | u64::from(id)
+}
+
+// orders taken by the handler the exchange hasn't acknowledged or rejected yet, shared with the
+// tasks sending them
+#[derive(Debug, Default, Clone)]
+pub struct Sent(Arc<Mutex<HashMap<u64, BinanceOrder>>>);
+
+impl Sent {
+    pub fn insert(&self, order: &BinanceOrder) {
+        let key = client_order_id(order.session_id, order.id);
+        self.0.lock().unwrap().insert(key, order.clone());
+    }
+
+    pub fn remove(&self, client_order_id: u64) {
+        self.0.lock().unwrap().remove(&client_order_id);
+    }
+
+    // it never got to the exchange or was refused, the strategy gets it REJECTED
+    pub fn reject(&self, tx: &UnboundedSender<Message>, order: &BinanceOrder) {
+        self.remove(client_order_id(order.session_id, order.id));
+        send(tx, &order.rejected());
+    }
+
+    fn orders(&self, session_id: u16) -> Vec<BinanceOrder> {
+        self.0
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|order| order.session_id == session_id)
+            .cloned()
+            .collect()
+    }
+}
+
+// orders routed to the exchange, following every execution report
+#[derive(Default)]
+pub struct Oms {
+    // client order id -> working order
+    open: HashMap<u64, Order>,
+    // session_id -> recently finished orders, oldest first
+    finished: HashMap<u16, VecDeque<Order>>,
+    sent: Sent,
+}
+
+impl Oms {
+    pub fn sent(&self) -> &Sent {
+        &self.sent
+    }
+
+    pub fn on_order(&mut self, client_order_id: u64, order: Order) {
+        let session_id = (client_order_id >> 32) as u16;
+        self.sent.remove(client_order_id);
+        match order.state {
+            State::NEW | State::PARTIALLY_FILLED => {
+                self.open.insert(client_order_id, order);
+            }
+            _ => {
+                self.open.remove(&client_order_id);
+                let finished = self.finished.entry(session_id).or_default();
+                if finished.len() >= FINISHED {
+                    finished.pop_front();
+                }
+                finished.push_back(order);
+            }
+        }
+    }
+
+    pub fn get(&self, client_order_id: u64) -> Option<&Order> {
+        self.open.get(&client_order_id)
+    }
+
+    // the orders sent and not acknowledged yet count too
+    pub fn open_orders(&self, session_id: u16) -> usize {
+        let open = self
+            .open
+            .keys()
+            .filter(|id| (*id >> 32) as u16 == session_id)
+            .count();
+        open + self.sent.orders(session_id).len()
+    }
+
+    // what the open and sent orders of a session may still fill on one side of a symbol
+    pub fn open_quantity(&self, session_id: u16, symbol: &str, side: Side) -> f64 {
+        let open: f64 = self
+            .open
+            .iter()
+            .filter(|(id, _)| (*id >> 32) as u16 == session_id)
+            .map(|(_, order)| order)
+            .filter(|order| order.side == side && order.symbol.eq_ignore_ascii_case(symbol))
+            .map(|order| order.quantity - order.acc)
+            .sum();
+        let sent: f64 = self
+            .sent
+            .orders(session_id)
+            .iter()
+            .filter(|order| order.side == side && order.symbol.eq_ignore_ascii_case(symbol))
+            .map(|order| order.quantity)
+            .sum();
+        open + sent
+    }
+
+    pub fn get_orders(&self, session_id: u16) -> OrderRsp {
+        let mut open: Vec<_> = self
+            .open
+            .iter()
+            .filter(|(id, _)| (*id >> 32) as u16 == session_id)
+            .map(|(_, order)| order.clone())
+            .collect();
+        open.sort_by_key(|order| order.internal_id);
+
+        let finished = self
+            .finished
+            .get(&session_id)
+            .map(|orders| orders.iter().cloned().collect())
+            .unwrap_or_default();
+
+        OrderRsp {
+            session_id,
+            open,
+            finished,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(id: u32, state: State) -> Order {
+        Order::new(
+            id,
+            "btcusdt".into(),
+            Side::BUY,
+            state,
+            OrderType::LIMIT,
+            Tif::GTC,
+            1.0,
+            100.0,
+        )
+    }
+
+    #[test]
+    fn test_get_orders() {
+        let mut oms = Oms::default();
+        oms.on_order(client_order_id(3, 2), order(2, State::NEW));
+        oms.on_order(client_order_id(3, 1), order(1, State::NEW));
+        oms.on_order(client_order_id(4, 1), order(1, State::NEW));
+        oms.on_order(client_order_id(3, 1), order(1, State::PARTIALLY_FILLED));
+        assert_eq!(oms.open_orders(3), 2);
+        assert_eq!(oms.open_quantity(3, "BTCUSDT", Side::BUY), 2.0);
+        assert_eq!(oms.open_quantity(3, "btcusdt", Side::SELL), 0.0);
+
+        oms.on_order(client_order_id(3, 2), order(2, State::CANCELED));
+        let rsp = oms.get_orders(3);
+        assert_eq!(rsp.open.len(), 1);
+        assert!(matches!(rsp.open[0].state, State::PARTIALLY_FILLED));
+        assert_eq!(rsp.finished.len(), 1);
+        assert_eq!(rsp.finished[0].internal_id, 2);
+        assert_eq!(oms.open_orders(4), 1);
+
+        for id in 0..FINISHED as u32 + 10 {
+            oms.on_order(client_order_id(5, id), order(id, State::FILLED));
+        }
+        let rsp = oms.get_orders(5);
+        assert_eq!(rsp.finished.len(), FINISHED);
+        assert_eq!(rsp.finished[0].internal_id, 10);
+    }
+
+    #[test]
+    fn test_sent() {
+        let mut oms = Oms::default();
+        let sent = BinanceOrder {
+            id: 1,
+            symbol: "btcusdt".into(),
+            price: 100.0,
+            quantity: 2.0,
+            side: Side::BUY,
+            order_type: OrderType::LIMIT,
+            tif: Tif::GTC,
+            session_id: 3,
+        };
+        oms.sent().insert(&sent);
+        oms.sent().insert(&BinanceOrder {
+            id: 2,
+            ..sent.clone()
+        });
+        assert_eq!(oms.open_orders(3), 2);
+        assert_eq!(oms.open_quantity(3, "btcusdt", Side::BUY), 4.0);
+
+        // acknowledged, it is counted once as open
+        oms.on_order(client_order_id(3, 1), order(1, State::NEW));
+        assert_eq!(oms.open_orders(3), 2);
+        assert_eq!(oms.open_quantity(3, "btcusdt", Side::BUY), 3.0);
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        oms.sent().reject(&tx, &BinanceOrder { id: 2, ..sent });
+        assert!(rx.try_recv().is_ok());
+        assert_eq!(oms.open_orders(3), 1);
+    }
+}
@@ -0,0 +1,228 @@
+use crate::Session;
+use futures::future::{select_all, FutureExt};
+use log::*;
+use serde::Deserialize;
+use std::collections::{hash_map::Entry, HashMap};
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::time::{Duration, Instant};
+use xcrypto::position::PositionDB;
+
+// symbol -> net
+pub type Nets = HashMap<String, f64>;
+
+fn default_interval() -> u64 {
+    60
+}
+
+fn default_tolerance() -> f64 {
+    1e-9
+}
+
+fn default_quote() -> String {
+    "USDT".into()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReconcileConfig {
+    // seconds between two checks, the first one runs at startup
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+    // smaller differences are rounding of the nets
+    #[serde(default = "default_tolerance")]
+    pub tolerance: f64,
+    // differences are booked into this session, reports only without it
+    pub session_id: Option<u16>,
+    // spot only, symbols quoted in it are compared with the balance of their base asset
+    #[serde(default = "default_quote")]
+    pub quote: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Discrepancy {
+    pub symbol: String,
+    pub local: f64,
+    pub exchange: f64,
+}
+
+impl Discrepancy {
+    // what the sessions miss
+    pub fn diff(&self) -> f64 {
+        self.exchange - self.local
+    }
+}
+
+// only the symbols the exchange reports are compared, a missing local net is 0
+pub fn compare(local: &Nets, exchange: &Nets, tolerance: f64) -> Vec<Discrepancy> {
+    let mut discrepancies: Vec<_> = exchange
+        .iter()
+        .map(|(symbol, exchange)| Discrepancy {
+            symbol: symbol.clone(),
+            local: local.get(symbol).cloned().unwrap_or_default(),
+            exchange: *exchange,
+        })
+        .filter(|discrepancy| discrepancy.diff().abs() > tolerance)
+        .collect();
+    discrepancies.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+    discrepancies
+}
+
+// the nets of the sessions owned by an account, sessions not logged in since startup keep what
+// pos.db loaded
+pub fn local_nets<F: Fn(u16) -> bool>(
+    posdb: &PositionDB,
+    sessions: &HashMap<u16, Session>,
+    owns: F,
+) -> Nets {
+    let mut nets = Nets::new();
+    let loaded = posdb
+        .all_positions()
+        .iter()
+        .filter(|(session_id, _)| owns(**session_id) && !sessions.contains_key(session_id))
+        .map(|(_, positions)| positions);
+    let live = sessions
+        .iter()
+        .filter(|(session_id, _)| owns(**session_id))
+        .map(|(_, session)| session.positions());
+
+    for positions in loaded.chain(live) {
+        for position in positions.values() {
+            *nets.entry(position.symbol.clone()).or_default() += position.net;
+        }
+    }
+    nets
+}
+
+pub struct Reconciler {
+    config: ReconcileConfig,
+    next: Instant,
+    // a request to the exchange is running
+    pending: bool,
+    results: (
+        UnboundedSender<anyhow::Result<Nets>>,
+        UnboundedReceiver<anyhow::Result<Nets>>,
+    ),
+}
+
+impl Reconciler {
+    pub fn new(config: ReconcileConfig) -> Self {
+        info!("{:?}", config);
+        Self {
+            config,
+            next: Instant::now(),
+            pending: false,
+            results: unbounded_channel(),
+        }
+    }
+
+    pub fn config(&self) -> &ReconcileConfig {
+        &self.config
+    }
+
+    pub fn due(&self) -> bool {
+        !self.pending && Instant::now() >= self.next
+    }
+
+    // fetch the exchange nets in the background
+    pub fn spawn<F>(&mut self, fetch: F)
+    where
+        F: Future<Output = anyhow::Result<Nets>> + Send + 'static,
+    {
+        self.pending = true;
+        self.next = Instant::now() + Duration::from_secs(self.config.interval);
+        let tx = self.results.0.clone();
+        tokio::spawn(async move {
+            let _ = tx.send(fetch.await);
+        });
+    }
+
+    pub async fn recv(&mut self) -> Option<anyhow::Result<Nets>> {
+        let result = self.results.1.recv().await;
+        self.pending = false;
+        result
+    }
+
+    // report every difference and book it into the reserved session, owns tells the sessions
+    // trading on the account compared
+    pub async fn reconcile<F: Fn(u16) -> bool>(
+        &self,
+        exchange: &Nets,
+        posdb: &Arc<PositionDB>,
+        sessions: &mut HashMap<u16, Session>,
+        owns: F,
+    ) -> anyhow::Result<Vec<Discrepancy>> {
+        let local = local_nets(posdb, sessions, owns);
+        let discrepancies = compare(&local, exchange, self.config.tolerance);
+        for discrepancy in discrepancies.iter() {
+            warn!(
+                "Position of {} differs by {}, sessions {}, exchange {}",
+                discrepancy.symbol,
+                discrepancy.diff(),
+                discrepancy.local,
+                discrepancy.exchange
+            );
+        }
+        info!(
+            "Reconciled {} symbols, {} differ",
+            exchange.len(),
+            discrepancies.len()
+        );
+
+        let session_id = match self.config.session_id {
+            Some(session_id) if !discrepancies.is_empty() => session_id,
+            _ => return Ok(discrepancies),
+        };
+        let session = match sessions.entry(session_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                entry.insert(Session::inactive(session_id, posdb.clone()).await?)
+            }
+        };
+        for discrepancy in discrepancies.iter() {
+            session.adjust(&discrepancy.symbol, discrepancy.diff())?;
+        }
+        Ok(discrepancies)
+    }
+}
+
+// the next result of any reconciler with its index, pending forever without one
+pub async fn reconciled(
+    reconcilers: &mut [Option<Reconciler>],
+) -> (usize, Option<anyhow::Result<Nets>>) {
+    let results: Vec<_> = reconcilers
+        .iter_mut()
+        .enumerate()
+        .filter_map(|(index, reconciler)| reconciler.as_mut().map(|r| (index, r)))
+        .map(|(index, reconciler)| reconciler.recv().map(move |nets| (index, nets)).boxed())
+        .collect();
+    if results.is_empty() {
+        return std::future::pending().await;
+    }
+    select_all(results).await.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nets(items: &[(&str, f64)]) -> Nets {
+        items.iter().map(|(s, net)| (s.to_string(), *net)).collect()
+    }
+
+    #[test]
+    fn test_compare() {
+        let local = nets(&[("btcusdt", 0.5), ("ethusdt", -1.0), ("bnbusdt", 2.0)]);
+        let exchange = nets(&[("btcusdt", 0.5), ("ethusdt", -0.4), ("dogeusdt", 100.0)]);
+
+        // bnbusdt isn't reported by the exchange
+        let discrepancies = compare(&local, &exchange, 1e-9);
+        assert_eq!(discrepancies.len(), 2);
+        assert_eq!(discrepancies[0].symbol, "dogeusdt");
+        assert_eq!(discrepancies[0].diff(), 100.0);
+        assert_eq!(discrepancies[1].symbol, "ethusdt");
+        assert!((discrepancies[1].diff() - 0.6).abs() < 1e-12);
+
+        assert!(compare(&local, &nets(&[("btcusdt", 0.5 + 1e-12)]), 1e-9).is_empty());
+    }
+}
@@ -0,0 +1,213 @@
+use chrono::{DateTime, NaiveDate};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::*;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+#[derive(Serialize)]
+struct Record<'a, T: Serialize> {
+    exchange: Option<i64>,
+    local: i64,
+    data: &'a T,
+}
+
+enum Action {
+    Write {
+        symbol: String,
+        local: i64,
+        line: String,
+    },
+    Exit,
+}
+
+type Target = GzEncoder<BufWriter<File>>;
+
+// writes every stream to <dir>/<symbol>/<symbol>_<yyyymmdd>.jsonl.gz, one file per utc day
+pub struct Recorder {
+    tx: Sender<Action>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Recorder {
+    pub fn new(dir: &str) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        info!("Record market data to {}", dir);
+
+        let (tx, rx) = channel();
+        let dir = PathBuf::from(dir);
+        let thread = std::thread::spawn(move || {
+            if let Err(e) = worker(&dir, rx) {
+                error!("{}", e);
+            }
+        });
+
+        Ok(Self {
+            tx,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn record<T: Serialize>(
+        &self,
+        symbol: &str,
+        exchange: Option<i64>,
+        local: i64,
+        data: &T,
+    ) -> anyhow::Result<()> {
+        let line = serde_json::to_string(&Record {
+            exchange,
+            local,
+            data,
+        })?;
+
+        self.tx.send(Action::Write {
+            symbol: symbol.to_lowercase(),
+            local,
+            line,
+        })?;
+        Ok(())
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            let _ = self.tx.send(Action::Exit);
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn open(dir: &Path, symbol: &str, date: &NaiveDate) -> std::io::Result<Target> {
+    let dir = dir.join(symbol);
+    std::fs::create_dir_all(&dir)?;
+
+    // appending starts a new gzip member, which concatenates cleanly on read
+    let path = dir.join(format!("{}_{}.jsonl.gz", symbol, date.format("%Y%m%d")));
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    Ok(GzEncoder::new(BufWriter::new(file), Compression::default()))
+}
+
+fn write(
+    dir: &Path,
+    targets: &mut HashMap<String, (NaiveDate, Target)>,
+    symbol: String,
+    local: i64,
+    line: &str,
+) -> std::io::Result<()> {
+    let date = DateTime::from_timestamp_millis(local)
+        .unwrap_or_default()
+        .date_naive();
+
+    let rotate = match targets.get(&symbol) {
+        Some((day, _)) => *day != date,
+        None => true,
+    };
+
+    if rotate {
+        if let Some((_, target)) = targets.remove(&symbol) {
+            target.finish()?.flush()?;
+        }
+        let target = open(dir, &symbol, &date)?;
+        targets.insert(symbol.clone(), (date, target));
+    }
+
+    if let Some((_, target)) = targets.get_mut(&symbol) {
+        target.write_all(line.as_bytes())?;
+        target.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+fn worker(dir: &Path, rx: Receiver<Action>) -> std::io::Result<()> {
+    let mut targets: HashMap<String, (NaiveDate, Target)> = HashMap::default();
+    let mut time = Instant::now();
+
+    loop {
+        match rx.recv_timeout(Duration::from_secs(1)) {
+            Ok(Action::Write {
+                symbol,
+                local,
+                line,
+            }) => {
+                if let Err(e) = write(dir, &mut targets, symbol, local, &line) {
+                    error!("{}", e);
+                }
+            }
+            Ok(Action::Exit) | Err(RecvTimeoutError::Disconnected) => break,
+            Err(RecvTimeoutError::Timeout) => (),
+        }
+
+        if time.elapsed() >= Duration::from_secs(1) {
+            time = Instant::now();
+            for (_, target) in targets.values_mut() {
+                if let Err(e) = target.flush() {
+                    error!("{}", e);
+                }
+            }
+        }
+    }
+
+    for (_, (_, target)) in targets {
+        target.finish()?.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::MultiGzDecoder;
+    use std::io::Read;
+
+    fn read(path: PathBuf) -> Vec<serde_json::Value> {
+        let mut buf = String::new();
+        MultiGzDecoder::new(File::open(path).unwrap())
+            .read_to_string(&mut buf)
+            .unwrap();
+        buf.lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_record() {
+        let dir = std::env::temp_dir().join(format!("recorder-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let day = 86400000;
+
+        // reopening the same day appends another gzip member
+        for _ in 0..2 {
+            let recorder = Recorder::new(dir.to_str().unwrap()).unwrap();
+            recorder
+                .record("BTCUSDT", Some(1), 2, &serde_json::json!({"a": 1}))
+                .unwrap();
+            recorder
+                .record("btcusdt", None, day + 2, &serde_json::json!({"a": 2}))
+                .unwrap();
+        }
+
+        let records = read(dir.join("btcusdt/btcusdt_19700101.jsonl.gz"));
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["exchange"], 1);
+        assert_eq!(records[0]["local"], 2);
+        assert_eq!(records[0]["data"]["a"], 1);
+
+        let records = read(dir.join("btcusdt/btcusdt_19700102.jsonl.gz"));
+        assert_eq!(records.len(), 2);
+        assert!(records[1]["exchange"].is_null());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,164 @@
+use crate::{client_order_id, Bbo, BinanceOrder, Trade};
+use log::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use xcrypto::chat::*;
+use xcrypto::error::*;
+
+// every limit is optional, an empty config lets all orders through
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct RiskConfig {
+    // price * quantity of a single order
+    pub max_notional: Option<f64>,
+    // absolute net of a session in one symbol after the order is fully filled
+    pub max_position: Option<f64>,
+    // per symbol override of max_position
+    #[serde(default)]
+    pub max_positions: HashMap<String, f64>,
+    // orders acknowledged by the exchange and not yet finished, per session
+    pub max_open_orders: Option<usize>,
+    // allowed distance from the last bbo as a fraction, 0.05 means 5%
+    pub price_band: Option<f64>,
+}
+
+pub struct Risk {
+    config: RiskConfig,
+    bbo: Bbo,
+}
+
+impl Risk {
+    pub fn new(config: RiskConfig, bbo: Bbo) -> Self {
+        info!("{:?}", config);
+        let max_positions = config
+            .max_positions
+            .into_iter()
+            .map(|(symbol, limit)| (symbol.to_lowercase(), limit))
+            .collect();
+
+        Self {
+            config: RiskConfig {
+                max_positions,
+                ..config
+            },
+            bbo,
+        }
+    }
+
+    fn bbo(&self, symbol: &str) -> Option<(f64, f64)> {
+        self.bbo.lock().unwrap().get(symbol).copied()
+    }
+
+    // an order whose price is unknown (market) is valued at the opposite touch
+    fn price(&self, order: &BinanceOrder, symbol: &str) -> Option<f64> {
+        match order.order_type {
+            OrderType::MARKET => self.bbo(symbol).map(|(bid, ask)| match order.side {
+                Side::BUY => ask,
+                Side::SELL => bid,
+            }),
+            _ => Some(order.price),
+        }
+    }
+
+    // the orders taken before and not acknowledged yet count as open, see Oms::sent
+    pub fn check<T: Trade>(&self, order: &BinanceOrder, trade: &T) -> Option<Error> {
+        if let Some(limit) = self.config.max_open_orders {
+            let open = trade.oms().open_orders(order.session_id);
+            if open >= limit {
+                return Some(Error {
+                    code: MAX_OPEN_ORDERS,
+                    msg: format!("{} open orders reach {}", open, limit),
+                });
+            }
+        }
+        self.check_order(order, trade)
+    }
+
+    // an amended order is checked with its new price and quantity
+    pub fn check_amend<T: Trade>(&self, order: &BinanceOrder, trade: &T) -> Option<Error> {
+        self.check_order(order, trade)
+    }
+
+    fn check_order<T: Trade>(&self, order: &BinanceOrder, trade: &T) -> Option<Error> {
+        let symbol = order.symbol.to_lowercase();
+
+        if let Some(limit) = self.config.max_notional {
+            let price = match self.price(order, &symbol) {
+                Some(price) => price,
+                None => return Some(no_bbo(&symbol, "max_notional")),
+            };
+            let notional = price * order.quantity;
+            if notional > limit {
+                return Some(Error {
+                    code: MAX_NOTIONAL,
+                    msg: format!("notional {} exceeds {}", notional, limit),
+                });
+            }
+        }
+
+        let limit = self
+            .config
+            .max_positions
+            .get(&symbol)
+            .copied()
+            .or(self.config.max_position);
+        if let Some(limit) = limit {
+            let net = trade
+                .get_positions(order.session_id)
+                .and_then(|positions| positions.get(&symbol))
+                .map(|position| position.net)
+                .unwrap_or_default();
+            // the other open and sent orders on the same side may fill first, an amended order
+            // counts with its new quantity only
+            let oms = trade.oms();
+            let mut open = oms.open_quantity(order.session_id, &symbol, order.side);
+            if let Some(working) = oms.get(client_order_id(order.session_id, order.id)) {
+                if working.side == order.side {
+                    open -= working.quantity - working.acc;
+                }
+            }
+            let net = match order.side {
+                Side::BUY => net + open,
+                Side::SELL => net - open,
+            };
+            let after = match order.side {
+                Side::BUY => net + order.quantity,
+                Side::SELL => net - order.quantity,
+            };
+
+            // orders reducing an oversized position are still allowed
+            if after.abs() > limit && after.abs() > net.abs() {
+                return Some(Error {
+                    code: MAX_POSITION,
+                    msg: format!("position {} of {} exceeds {}", after, symbol, limit),
+                });
+            }
+        }
+
+        if let (Some(band), OrderType::LIMIT | OrderType::LIMIT_MAKER) =
+            (self.config.price_band, &order.order_type)
+        {
+            match self.bbo(&symbol) {
+                Some((bid, ask)) => {
+                    let (low, high) = (bid * (1.0 - band), ask * (1.0 + band));
+                    if order.price < low || order.price > high {
+                        return Some(Error {
+                            code: PRICE_BAND,
+                            msg: format!("price {} out of [{}, {}]", order.price, low, high),
+                        });
+                    }
+                }
+                None => return Some(no_bbo(&symbol, "price_band")),
+            }
+        }
+
+        None
+    }
+}
+
+// right after startup or a reconnect, or a symbol nobody subscribed
+fn no_bbo(symbol: &str, check: &str) -> Error {
+    Error {
+        code: NO_BBO,
+        msg: format!("no bbo of {} for {}", symbol, check),
+    }
+}
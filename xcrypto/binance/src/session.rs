@@ -2,7 +2,7 @@ use log::*;
 use serde::Serialize;
 use std::{collections::HashMap, sync::Arc};
 use tokio::sync::mpsc::UnboundedSender;
-use xcrypto::chat::Side;
+use xcrypto::chat::{Fill, Order, Side};
 use xcrypto::position::PositionDB;
 use xcrypto::{
     chat::{Position, State},
@@ -24,13 +24,19 @@ impl Session {
         posdb: Arc<PositionDB>,
         tx: UnboundedSender<Message>,
     ) -> anyhow::Result<Self> {
+        let mut session = Self::inactive(session_id, posdb).await?;
+        session.tx = Some(tx);
+        Ok(session)
+    }
+
+    // a session nobody has logged in yet
+    pub async fn inactive(session_id: u16, posdb: Arc<PositionDB>) -> anyhow::Result<Self> {
         let positions = posdb.get_positions(session_id);
-        posdb.create_table(session_id).await?;
         Ok(Self {
             session_id,
             positions: positions.cloned().unwrap_or_default(),
             posdb,
-            tx: Some(tx),
+            tx: None,
         })
     }
 
@@ -38,7 +44,29 @@ impl Session {
         self.tx.is_some()
     }
 
-    pub fn on_order<T: OrderTrait + Serialize>(&mut self, order: &T) -> anyhow::Result<()> {
+    pub fn positions(&self) -> &HashMap<String, Position> {
+        &self.positions
+    }
+
+    // book a difference found by reconciliation at the entry price, the fill price is unknown
+    pub fn adjust(&mut self, symbol: &str, diff: f64) -> anyhow::Result<()> {
+        let position = self
+            .positions
+            .entry(symbol.into())
+            .or_insert_with(|| Position::new(symbol));
+        position.on_fill(diff, position.avg_price, 0.0);
+        info!("Session {} adjust {} by {}", self.session_id, symbol, diff);
+
+        let position = position.clone();
+        self.send(&position)?;
+        self.posdb.update(self.session_id, position);
+        Ok(())
+    }
+
+    pub fn on_order<T>(&mut self, order: &T) -> anyhow::Result<()>
+    where
+        T: OrderTrait + Serialize + Clone + Into<Order>,
+    {
         if let State::FILLED | State::PARTIALLY_FILLED = order.state() {
             self.on_trade(order)?;
         }
@@ -47,27 +75,25 @@ impl Session {
         Ok(())
     }
 
-    fn on_trade<T: OrderTrait>(&mut self, order: &T) -> anyhow::Result<()> {
-        let mut binding = Position {
-            symbol: order.symbol().into(),
-            net: 0.0,
-        };
+    fn on_trade<T: OrderTrait + Clone + Into<Order>>(&mut self, order: &T) -> anyhow::Result<()> {
         let position = self
             .positions
-            .get_mut(order.symbol())
-            .unwrap_or(&mut binding);
+            .entry(order.symbol().into())
+            .or_insert_with(|| Position::new(order.symbol()));
 
         let net = order.net()?;
-        match order.side() {
-            Side::BUY => position.net += net,
-            Side::SELL => position.net -= net,
-        }
+        let quantity = match order.side() {
+            Side::BUY => net,
+            Side::SELL => -net,
+        };
+        position.on_fill(quantity, order.trd_prc()?, order.commission());
 
-        if let Some(position) = self.positions.get(order.symbol()) {
-            self.send(position)?;
-            self.posdb.update(self.session_id, position.to_owned())
-        }
+        let position = position.clone();
+        self.send(&position)?;
+        self.posdb.update(self.session_id, position);
 
+        let fill = Fill::new(self.session_id, &order.clone().into(), order.commission());
+        self.posdb.record(fill);
         Ok(())
     }
 
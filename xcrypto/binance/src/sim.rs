@@ -0,0 +1,725 @@
+use crate::{
+    client_order_id, normalize_stream, send, Batch, BinanceAmend, BinanceCancel, BinanceOrder,
+    BinanceProduct, BinanceQuote, Oms, OrderTrait, Session, Trade, ALL_FORCE_ORDERS,
+};
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use xcrypto::chat::*;
+use xcrypto::error::*;
+use xcrypto::parser::Parser;
+use xcrypto::position::PositionDB;
+use xcrypto::rest::Rest;
+use xcrypto::tungstenite::Message;
+
+const EPSILON: f64 = 1e-9;
+
+async fn get_products(
+    rest: &Arc<Rest>,
+    exchange_info: &str,
+) -> anyhow::Result<HashMap<String, BinanceProduct>> {
+    let rsp = rest.get(exchange_info, &[], false).await?;
+    let results: serde_json::Value = serde_json::from_str(&rsp.text().await?)?;
+
+    let results = results.get("symbols").unwrap();
+    let results: Vec<BinanceProduct> = serde_json::from_value(results.to_owned())?;
+    let mut products = HashMap::new();
+
+    for result in results {
+        products.insert(result.symbol.clone(), result);
+    }
+
+    info!("products {}", products.len());
+    Ok(products)
+}
+
+// simulated fills are free of commission
+impl OrderTrait for Order {
+    fn symbol(&self) -> &str {
+        self.symbol.as_str()
+    }
+    fn trd_vol(&self) -> anyhow::Result<f64> {
+        Ok(self.trade_quantity)
+    }
+    fn trd_prc(&self) -> anyhow::Result<f64> {
+        Ok(self.trade_price)
+    }
+    fn commission(&self) -> f64 {
+        0.0
+    }
+    fn net(&self) -> anyhow::Result<f64> {
+        self.trd_vol()
+    }
+    fn side(&self) -> Side {
+        self.side
+    }
+    fn state(&self) -> State {
+        self.state
+    }
+}
+
+struct SimOrder {
+    session_id: u16,
+    order_id: i64,
+    acc: f64,
+    inner: BinanceOrder,
+}
+
+impl SimOrder {
+    fn leaves(&self) -> f64 {
+        self.inner.quantity - self.acc
+    }
+
+    fn order(&self, state: State) -> Order {
+        let inner = &self.inner;
+        let mut order = Order::new(
+            inner.id,
+            inner.symbol.clone(),
+            inner.side,
+            state,
+            inner.order_type.clone(),
+            inner.tif.clone(),
+            inner.quantity,
+            inner.price,
+        );
+        order.order_id = self.order_id;
+        order.acc = self.acc;
+        order
+    }
+
+    fn fill(&mut self, price: f64, quantity: f64, making: bool) -> Order {
+        self.acc += quantity;
+        let state = if self.leaves() > EPSILON {
+            State::PARTIALLY_FILLED
+        } else {
+            State::FILLED
+        };
+
+        let mut order = self.order(state);
+        order.trade_time = order.time;
+        order.trade_price = price;
+        order.trade_quantity = quantity;
+        order.making = making;
+        order
+    }
+
+    fn crossed(&self, price: f64) -> bool {
+        match self.inner.order_type {
+            OrderType::MARKET => true,
+            _ => match self.inner.side {
+                Side::BUY => price <= self.inner.price,
+                Side::SELL => price >= self.inner.price,
+            },
+        }
+    }
+
+    fn opposite<'a>(&self, depth: &'a Depth<BinanceQuote>) -> &'a Vec<BinanceQuote> {
+        match self.inner.side {
+            Side::BUY => &depth.asks,
+            Side::SELL => &depth.bids,
+        }
+    }
+
+    fn liquidity(&self, depth: &Depth<BinanceQuote>) -> f64 {
+        self.opposite(depth)
+            .iter()
+            .take_while(|quote| self.crossed(quote.price))
+            .map(|quote| quote.quantity)
+            .sum()
+    }
+
+    // sweep the crossing levels as a taker
+    fn take(&mut self, depth: &Depth<BinanceQuote>) -> Vec<Order> {
+        let mut fills = Vec::new();
+        for quote in self.opposite(depth).iter() {
+            if self.leaves() <= EPSILON || !self.crossed(quote.price) {
+                break;
+            }
+
+            let quantity = self.leaves().min(quote.quantity);
+            if quantity > 0.0 {
+                fills.push(self.fill(quote.price, quantity, false));
+            }
+        }
+        fills
+    }
+
+    // a resting order is filled in full at its own price once the market trades through it
+    fn make(&mut self, depth: &Depth<BinanceQuote>) -> Option<Order> {
+        let best = self.opposite(depth).first()?;
+        if self.crossed(best.price) {
+            let price = self.inner.price;
+            let quantity = self.leaves();
+            return Some(self.fill(price, quantity, true));
+        }
+        None
+    }
+}
+
+pub struct SimTrade {
+    rest: Arc<Rest>,
+    exchange_info: String,
+    txs: HashMap<SocketAddr, UnboundedSender<Message>>,
+    // addr -> session_id
+    session_id: HashMap<SocketAddr, u16>,
+    // session_id -> session
+    session: HashMap<u16, Session>,
+    posdb: Arc<PositionDB>,
+    oms: Oms,
+    products: HashMap<String, BinanceProduct>,
+    feed: UnboundedReceiver<Depth<BinanceQuote>>,
+    // symbol -> latest bbo/depth
+    books: HashMap<String, Depth<BinanceQuote>>,
+    // client order id -> working order
+    orders: HashMap<u64, SimOrder>,
+    order_id: i64,
+}
+
+impl SimTrade {
+    // db keeps the positions and fills of the simulated sessions
+    pub async fn new(
+        rest: Arc<Rest>,
+        exchange_info: &str,
+        db: &str,
+        feed: UnboundedReceiver<Depth<BinanceQuote>>,
+    ) -> anyhow::Result<Self> {
+        let products = get_products(&rest, exchange_info).await?;
+
+        Ok(Self {
+            rest,
+            exchange_info: exchange_info.into(),
+            txs: HashMap::default(),
+            session_id: HashMap::default(),
+            session: HashMap::default(),
+            posdb: Arc::new(PositionDB::new(db).await?),
+            oms: Oms::default(),
+            products,
+            feed,
+            books: HashMap::default(),
+            orders: HashMap::default(),
+            order_id: 0,
+        })
+    }
+}
+
+impl Trade for SimTrade {
+    fn disconnected(&self) -> bool {
+        self.feed.is_closed()
+    }
+
+    fn session_disconnected(&self, _: u16) -> bool {
+        self.disconnected()
+    }
+
+    fn products(&self) -> &HashMap<String, BinanceProduct> {
+        &self.products
+    }
+
+    // live once the session logged in, what pos.db loaded before
+    fn get_positions(&self, session_id: u16) -> Option<&HashMap<String, Position>> {
+        self.session
+            .get(&session_id)
+            .map(Session::positions)
+            .or_else(|| self.posdb.get_positions(session_id))
+    }
+
+    fn oms(&self) -> &Oms {
+        &self.oms
+    }
+
+    // fills are simulated against the feed, there is no wallet
+    fn get_balances(&self, _: u16) -> Vec<Balance> {
+        Vec::new()
+    }
+
+    // simulated orders never reach the exchange
+    fn rest(&self, _: u16) -> Option<&Rest> {
+        None
+    }
+
+    async fn get_trades(&self, req: &TradeReq) -> anyhow::Result<Vec<Fill>> {
+        self.posdb.get_trades(req).await
+    }
+
+    async fn get_products(&mut self) -> anyhow::Result<()> {
+        self.products = get_products(&self.rest, &self.exchange_info).await?;
+        Ok(())
+    }
+
+    async fn process(&mut self) -> anyhow::Result<bool> {
+        if let Some(depth) = self.feed.recv().await {
+            self.on_depth(depth);
+        }
+
+        Ok(self.disconnected())
+    }
+
+    fn add_order(&mut self, addr: &SocketAddr, order: &BinanceOrder) -> anyhow::Result<()> {
+        if !self.txs.contains_key(addr) {
+            warn!("Missing session {}, maybe a bug", addr);
+            return Ok(());
+        }
+
+        self.place(order);
+        Ok(())
+    }
+
+    fn cancel(&mut self, addr: &SocketAddr, cancel: &BinanceCancel) -> anyhow::Result<()> {
+        if !self.txs.contains_key(addr) {
+            warn!("Missing session {}, maybe a bug", addr);
+            return Ok(());
+        }
+
+        if let Some(e) = self.remove(cancel) {
+            warn!("{:?} {:?}", cancel, e);
+        }
+        Ok(())
+    }
+
+    fn cancel_all(&mut self, session_id: u16) -> anyhow::Result<()> {
+        let keys: Vec<_> = self
+            .orders
+            .iter()
+            .filter(|(_, order)| order.session_id == session_id)
+            .map(|(key, _)| *key)
+            .collect();
+
+        for key in keys {
+            if let Some(order) = self.orders.remove(&key) {
+                self.on_order(session_id, &order.order(State::CANCELED));
+            }
+        }
+        Ok(())
+    }
+
+    fn amend(&mut self, addr: &SocketAddr, id: i64, amend: &BinanceAmend) -> anyhow::Result<()> {
+        let tx = match self.txs.get(addr) {
+            Some(tx) => tx.clone(),
+            None => {
+                warn!("Missing session {}, maybe a bug", addr);
+                return Ok(());
+            }
+        };
+
+        let key = client_order_id(amend.session_id, amend.order_id);
+        match self.orders.remove(&key) {
+            // replaced by a fresh order keeping the id
+            Some(mut order) => {
+                order.acc = 0.0;
+                order.inner.price = amend.price;
+                order.inner.quantity = amend.quantity;
+                for event in self.execute(order) {
+                    self.on_order(amend.session_id, &event);
+                }
+            }
+            None => {
+                let e = Error {
+                    code: UNKNOWN_ORDER,
+                    msg: format!("unknown order {} to amend", amend.order_id),
+                };
+                warn!("{:?}", e);
+                send(&tx, &Response { id, result: e });
+            }
+        }
+        Ok(())
+    }
+
+    fn batch_order(
+        &mut self,
+        addr: &SocketAddr,
+        mut batch: Batch,
+        orders: Vec<BinanceOrder>,
+    ) -> anyhow::Result<()> {
+        let tx = match self.txs.get(addr) {
+            Some(tx) => tx.clone(),
+            None => {
+                warn!("Missing session {}, maybe a bug", addr);
+                return Ok(());
+            }
+        };
+
+        for order in orders {
+            if let Some(e) = self.place(&order) {
+                batch.reject(order.id, e);
+            }
+        }
+        batch.reply(&tx);
+        Ok(())
+    }
+
+    fn batch_cancel(
+        &mut self,
+        addr: &SocketAddr,
+        mut batch: Batch,
+        cancels: Vec<BinanceCancel>,
+    ) -> anyhow::Result<()> {
+        let tx = match self.txs.get(addr) {
+            Some(tx) => tx.clone(),
+            None => {
+                warn!("Missing session {}, maybe a bug", addr);
+                return Ok(());
+            }
+        };
+
+        for cancel in cancels {
+            if let Some(e) = self.remove(&cancel) {
+                batch.reject(cancel.order_id, e);
+            }
+        }
+        batch.reply(&tx);
+        Ok(())
+    }
+
+    fn handle_close(&mut self, addr: &SocketAddr) -> anyhow::Result<()> {
+        if self.txs.remove(addr).is_some() {
+            match self.session_id.remove(addr) {
+                Some(id) => match self.session.get_mut(&id) {
+                    Some(session) => {
+                        session.set_active(None);
+                    }
+                    None => warn!("Session used by {} isn't exist, maybe a bug", addr),
+                },
+                None => warn!("Session used by {} isn't exist, maybe a bug", addr),
+            }
+        }
+        Ok(())
+    }
+
+    async fn handle_login(
+        &mut self,
+        addr: &SocketAddr,
+        req: &Request<Login>,
+        tx: &UnboundedSender<Message>,
+    ) -> anyhow::Result<Option<Error>> {
+        let login = &req.params;
+        let session_id = login.session_id;
+
+        match self.session.get_mut(&session_id) {
+            Some(session) => {
+                if session.active() {
+                    return Ok(Some(Error {
+                        code: DUPLICATE_LOGIN,
+                        msg: "duplicate login".into(),
+                    }));
+                } else {
+                    session.set_active(Some(tx.clone()));
+                }
+            }
+            None => {
+                let session = Session::new(session_id, self.posdb.clone(), tx.clone()).await?;
+                self.session.insert(session_id, session);
+            }
+        }
+        self.txs.insert(*addr, tx.clone());
+        self.session_id.insert(*addr, session_id);
+
+        info!("session addr {} -> {}", addr, session_id);
+        Ok(None)
+    }
+
+    fn handle_subscribe(&mut self, _: &SocketAddr, req: &Request<Vec<String>>) -> Option<Error> {
+        for symbol in req.params.iter() {
+            if symbol == ALL_FORCE_ORDERS {
+                continue;
+            }
+            let symbol = normalize_stream(symbol);
+            match symbol.split_once("@") {
+                Some((name, stream)) => {
+                    if !self.products.contains_key(name) {
+                        return Some(Error {
+                            code: INVALID_SYMBOL,
+                            msg: format!("invalid symbol {}", symbol),
+                        });
+                    }
+                    if !self.validate_symbol(name, stream) {
+                        return Some(Error {
+                            code: INVALID_STREAM,
+                            msg: format!("invalid stream {}", symbol),
+                        });
+                    }
+                }
+                None => {
+                    return Some(Error {
+                        code: INVALID_SYMBOL,
+                        msg: format!("invalid symbol {}", symbol),
+                    });
+                }
+            }
+        }
+        None
+    }
+
+    fn validate_symbol(&self, _: &str, stream: &str) -> bool {
+        match stream.split_once(":") {
+            Some((stream, interval)) => match stream {
+                "kline" => matches!(
+                    interval,
+                    "1s" | "1m"
+                        | "3m"
+                        | "5m"
+                        | "15m"
+                        | "30m"
+                        | "1h"
+                        | "2h"
+                        | "4h"
+                        | "6h"
+                        | "8h"
+                        | "12h"
+                        | "1d"
+                        | "3d"
+                        | "1w"
+                        | "1M"
+                ),
+                "depth" => interval == "100ms",
+                "markPrice" => interval == "1s",
+                _ => false,
+            },
+            None => matches!(
+                stream,
+                "depth" | "bbo" | "book" | "trade" | "aggTrade" | "markPrice" | "forceOrder"
+            ),
+        }
+    }
+
+    fn handle_disconnect(&mut self, addr: &SocketAddr, parser: &Parser) -> anyhow::Result<()> {
+        if let Some(id) = parser.get("id") {
+            self.reply(
+                addr,
+                i64::deserialize(id)?,
+                Error {
+                    code: DISCONNECTED,
+                    msg: "market feed disconnected".into(),
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    fn reply<T: Serialize + Debug>(
+        &mut self,
+        addr: &SocketAddr,
+        id: i64,
+        result: T,
+    ) -> anyhow::Result<()> {
+        if let Some(tx) = self.txs.get_mut(addr) {
+            let response = Response { id, result };
+
+            debug!("{:?}", response);
+            let rsp = Message::Text(serde_json::to_string(&response)?);
+            tx.send(rsp)?;
+        }
+        Ok(())
+    }
+
+    async fn reconncet(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+// matching
+impl SimTrade {
+    fn place(&mut self, order: &BinanceOrder) -> Option<Error> {
+        self.order_id += 1;
+        let order = SimOrder {
+            session_id: order.session_id,
+            order_id: self.order_id,
+            acc: 0.0,
+            inner: order.clone(),
+        };
+
+        let session_id = order.session_id;
+        let (events, e) = if self.products.contains_key(&order.inner.symbol) {
+            let events = self.execute(order);
+            let e = matches!(events[0].state, State::REJECTED).then(|| Error {
+                code: UNDEF_ERROR,
+                msg: "rejected by simulator".into(),
+            });
+            (events, e)
+        } else {
+            let e = Error {
+                code: INVALID_SYMBOL,
+                msg: format!("invalid symbol {}", order.inner.symbol),
+            };
+            (vec![order.order(State::REJECTED)], Some(e))
+        };
+
+        for event in events {
+            self.on_order(session_id, &event);
+        }
+        e
+    }
+
+    fn remove(&mut self, cancel: &BinanceCancel) -> Option<Error> {
+        let orig = client_order_id(cancel.session_id, cancel.order_id);
+        match self.orders.remove(&orig) {
+            Some(order) => {
+                self.on_order(order.session_id, &order.order(State::CANCELED));
+                None
+            }
+            None => Some(Error {
+                code: UNDEF_ERROR,
+                msg: "unknown order".into(),
+            }),
+        }
+    }
+
+    fn execute(&mut self, mut order: SimOrder) -> Vec<Order> {
+        let order_type = order.inner.order_type.clone();
+        let tif = order.inner.tif.clone();
+        let quantity = order.inner.quantity;
+
+        let depth = self.books.get(&order.inner.symbol);
+        let crossed = depth
+            .and_then(|depth| order.opposite(depth).first())
+            .map(|best| order.crossed(best.price))
+            .unwrap_or(false);
+
+        let rejected = match order_type {
+            OrderType::LIMIT => false,
+            OrderType::LIMIT_MAKER => crossed,
+            OrderType::MARKET => depth.is_none(),
+            _ => true,
+        };
+        if rejected {
+            return vec![order.order(State::REJECTED)];
+        }
+
+        let mut events = vec![order.order(State::NEW)];
+        // post only
+        if tif == Tif::GTX && crossed {
+            events.push(order.order(State::EXPIRED));
+            return events;
+        }
+
+        if let Some(depth) = depth {
+            if tif == Tif::FOK && order.liquidity(depth) + EPSILON < quantity {
+                events.push(order.order(State::EXPIRED));
+                return events;
+            }
+            events.extend(order.take(depth));
+        }
+
+        if order.leaves() > EPSILON {
+            match (order_type, tif) {
+                (OrderType::MARKET, _) | (_, Tif::IOC) | (_, Tif::FOK) => {
+                    events.push(order.order(State::EXPIRED))
+                }
+                _ => {
+                    let key = client_order_id(order.session_id, order.inner.id);
+                    self.orders.insert(key, order);
+                }
+            }
+        }
+        events
+    }
+
+    fn on_depth(&mut self, depth: Depth<BinanceQuote>) {
+        let mut events = Vec::new();
+        for order in self.orders.values_mut() {
+            if order.inner.symbol != depth.symbol {
+                continue;
+            }
+            if let Some(fill) = order.make(&depth) {
+                events.push((order.session_id, fill));
+            }
+        }
+        self.orders.retain(|_, order| order.leaves() > EPSILON);
+        self.books.insert(depth.symbol.clone(), depth);
+
+        for (session_id, order) in events {
+            self.on_order(session_id, &order);
+        }
+    }
+}
+
+// callback
+impl SimTrade {
+    fn on_order(&mut self, session_id: u16, order: &Order) {
+        info!("{:?}", order);
+        self.oms.on_order(
+            client_order_id(session_id, order.internal_id),
+            order.clone(),
+        );
+        match self.session.get_mut(&session_id) {
+            Some(session) => {
+                if let Err(e) = session.on_order(order) {
+                    error!("{}", e);
+                }
+            }
+            None => warn!("Missing session {}, maybe a bug", session_id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn depth() -> Depth<BinanceQuote> {
+        let quote = |price, quantity| BinanceQuote { price, quantity };
+        Depth {
+            time: 0,
+            symbol: "btcusdt".into(),
+            stream: "btcusdt@depth".into(),
+            bids: vec![quote(99.0, 1.0), quote(98.0, 2.0)],
+            asks: vec![quote(101.0, 1.0), quote(102.0, 2.0)],
+        }
+    }
+
+    fn order(side: Side, price: f64, quantity: f64) -> SimOrder {
+        SimOrder {
+            session_id: 1,
+            order_id: 1,
+            acc: 0.0,
+            inner: BinanceOrder {
+                id: 1,
+                symbol: "btcusdt".into(),
+                price,
+                quantity,
+                side,
+                order_type: OrderType::LIMIT,
+                tif: Tif::GTC,
+                session_id: 1,
+            },
+        }
+    }
+
+    #[test]
+    fn test_take() {
+        let depth = depth();
+        let mut buy = order(Side::BUY, 102.0, 2.5);
+        assert_eq!(buy.liquidity(&depth), 3.0);
+
+        let fills = buy.take(&depth);
+        assert_eq!(fills.len(), 2);
+        assert_eq!(fills[0].trade_price, 101.0);
+        assert_eq!(fills[0].trade_quantity, 1.0);
+        assert!(matches!(fills[0].state, State::PARTIALLY_FILLED));
+        assert_eq!(fills[1].trade_price, 102.0);
+        assert_eq!(fills[1].trade_quantity, 1.5);
+        assert!(matches!(fills[1].state, State::FILLED));
+        assert!(!fills[1].making);
+
+        let mut sell = order(Side::SELL, 100.0, 1.0);
+        assert!(sell.take(&depth).is_empty());
+        assert!(sell.make(&depth).is_none());
+    }
+
+    #[test]
+    fn test_make() {
+        let mut depth = depth();
+        let mut buy = order(Side::BUY, 100.0, 1.0);
+        assert!(buy.make(&depth).is_none());
+
+        depth.asks[0].price = 100.0;
+        let fill = buy.make(&depth).unwrap();
+        assert_eq!(fill.trade_price, 100.0);
+        assert_eq!(fill.trade_quantity, 1.0);
+        assert!(fill.making);
+        assert!(matches!(fill.state, State::FILLED));
+    }
+}
@@ -0,0 +1,140 @@
+use crate::Trade;
+use log::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use xcrypto::chat::*;
+use xcrypto::error::*;
+use xcrypto::ratelimit::RateLimits;
+
+fn default_warn() -> f64 {
+    0.8
+}
+
+fn default_reject() -> f64 {
+    0.95
+}
+
+// fractions of every exchange limit, orders are rejected before binance answers 429
+#[derive(Debug, Clone, Deserialize)]
+pub struct RateLimitConfig {
+    // sessions are told the usage once it passes this
+    #[serde(default = "default_warn")]
+    pub warn: f64,
+    // orders that would pass this are rejected
+    #[serde(default = "default_reject")]
+    pub reject: f64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            warn: default_warn(),
+            reject: default_reject(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Throttle {
+    config: RateLimitConfig,
+    // (session_id, rate limit type, interval) -> reset of the window last warned
+    warned: HashMap<(u16, String, String), i64>,
+}
+
+impl Throttle {
+    pub fn new(config: RateLimitConfig) -> Self {
+        info!("{:?}", config);
+        Self {
+            config,
+            warned: HashMap::default(),
+        }
+    }
+
+    // orders is how many the session is about to send
+    pub fn check<T: Trade>(&self, session_id: u16, orders: u64, trade: &T) -> Option<Error> {
+        let rest = trade.rest(session_id)?;
+        let now = rest.now().ok()?;
+        self.check_limits(orders, &rest.rate_limits().lock().unwrap(), now)
+    }
+
+    fn check_limits(&self, orders: u64, limits: &RateLimits, now: i64) -> Option<Error> {
+        if let Some(until) = limits.banned_until(now) {
+            return Some(Error {
+                code: RATE_LIMIT,
+                msg: format!("banned by rate limit until {}", until),
+            });
+        }
+
+        // every order request counts at least 1 in each window
+        limits
+            .usage(now)
+            .into_iter()
+            .find(|usage| (usage.used + orders) as f64 > usage.limit as f64 * self.config.reject)
+            .map(|usage| Error {
+                code: RATE_LIMIT,
+                msg: format!(
+                    "{} {} used {} of {} until {}",
+                    usage.rate_limit_type, usage.interval, usage.used, usage.limit, usage.reset
+                ),
+            })
+    }
+
+    // the windows past warn the session has not been told of yet
+    pub fn warnings<T: Trade>(&mut self, session_id: u16, trade: &T) -> Vec<RateLimit> {
+        let usage = match trade.rest(session_id).map(|rest| (rest, rest.now())) {
+            Some((rest, Ok(now))) => rest.rate_limits().lock().unwrap().usage(now),
+            _ => return Vec::new(),
+        };
+        self.filter(session_id, usage)
+    }
+
+    fn filter(&mut self, session_id: u16, usage: Vec<RateLimit>) -> Vec<RateLimit> {
+        usage
+            .into_iter()
+            .filter(|usage| usage.used as f64 >= usage.limit as f64 * self.config.warn)
+            .filter(|usage| {
+                let key = (
+                    session_id,
+                    usage.rate_limit_type.clone(),
+                    usage.interval.clone(),
+                );
+                self.warned.insert(key, usage.reset) != Some(usage.reset)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xcrypto::ratelimit::ExchangeLimit;
+
+    #[test]
+    fn test_throttle() {
+        let mut limits = RateLimits::default();
+        limits.set_limits(&[ExchangeLimit {
+            rate_limit_type: "ORDERS".into(),
+            interval: "SECOND".into(),
+            interval_num: 10,
+            limit: 100,
+            count: None,
+        }]);
+        let now = 1_700_000_000_000;
+        limits.update("ORDERS", "10S", 90, now);
+
+        let mut throttle = Throttle::default();
+        assert!(throttle.check_limits(5, &limits, now).is_none());
+        let e = throttle.check_limits(6, &limits, now).unwrap();
+        assert_eq!(e.code, RATE_LIMIT);
+        // the next window starts from 0
+        assert!(throttle.check_limits(6, &limits, now + 10000).is_none());
+
+        // each window is warned once per session
+        assert_eq!(throttle.filter(1, limits.usage(now)).len(), 1);
+        assert!(throttle.filter(1, limits.usage(now)).is_empty());
+        assert_eq!(throttle.filter(2, limits.usage(now)).len(), 1);
+
+        limits.ban(now + 1000);
+        assert!(throttle.check_limits(0, &limits, now).is_some());
+    }
+}
@@ -1,4 +1,5 @@
 use binance::chat::*;
+use binance::Trade;
 use binance::*;
 use log::*;
 use native_json::Deserialize;
@@ -12,76 +13,164 @@ use xcrypto::chat::*;
 use xcrypto::error::*;
 use xcrypto::parser::Parser;
 use xcrypto::position::PositionDB;
-use xcrypto::rest::Rest;
+use xcrypto::ratelimit::ExchangeLimit;
+use xcrypto::rest::{order_params, Rest};
 use xcrypto::tungstenite::Message;
 
-async fn get_positions(rest: &Arc<Rest>) -> anyhow::Result<HashMap<String, BinanceProduct>> {
+// binance limits of one batch request
+const BATCH_ORDERS: usize = 5;
+const BATCH_CANCELS: usize = 10;
+
+// products and the rate limits of the exchange
+async fn get_positions(
+    rest: &Arc<Rest>,
+) -> anyhow::Result<(HashMap<String, BinanceProduct>, Vec<ExchangeLimit>)> {
     let rsp = rest.get("/fapi/v1/exchangeInfo", &[], false).await?;
     let results: serde_json::Value = serde_json::from_str(&rsp.text().await?)?;
 
-    let results = results.get("symbols").unwrap();
-    let results: Vec<BinanceProduct> = serde_json::from_value(results.to_owned())?;
+    let symbols = results.get("symbols").unwrap();
+    let symbols: Vec<BinanceProduct> = serde_json::from_value(symbols.to_owned())?;
     let mut products = HashMap::new();
 
-    for result in results {
-        products.insert(result.symbol.clone(), result);
+    for symbol in symbols {
+        products.insert(symbol.symbol.clone(), symbol);
     }
 
+    let limits = serde_json::from_value(results["rateLimits"].to_owned()).unwrap_or_default();
+
     info!("products {}", products.len());
-    Ok(products)
+    Ok((products, limits))
+}
+
+// symbol -> amount of every position side, short amounts are negative
+async fn get_exchange_nets(rest: Arc<Rest>) -> anyhow::Result<Nets> {
+    let rsp = rest.get("/fapi/v2/positionRisk", &[], true).await?;
+    let results: serde_json::Value = serde_json::from_str(&rsp.text().await?)?;
+    let results: Vec<PositionRisk> = match serde_json::from_value(results.clone()) {
+        Ok(results) => results,
+        Err(_) => anyhow::bail!("Failed to get positions {}", results),
+    };
+
+    let mut nets = Nets::new();
+    for result in results {
+        let amount: f64 = result.positionAmt.parse().unwrap_or_default();
+        *nets.entry(result.symbol.to_lowercase()).or_default() += amount;
+    }
+    Ok(nets)
 }
 
 pub struct UsdtTrade {
-    rest: Arc<Rest>,
     txs: HashMap<SocketAddr, UnboundedSender<Message>>,
-    account: Account<UsdtListenKey>,
+    accounts: Accounts<UsdtListenKey>,
     // addr -> session_id
     session_id: HashMap<SocketAddr, u16>,
     // session_id -> session
     session: HashMap<u16, Session>,
     posdb: Arc<PositionDB>,
+    oms: Oms,
     products: HashMap<String, BinanceProduct>,
+    // the rest of the fields are per account
+    // last account update, the exchange only pushes changes
+    balances: Vec<HashMap<String, Balance>>,
+    // (symbol, position side) -> position
+    positions: Vec<HashMap<(String, String), ExchangePosition>>,
+    reconcilers: Vec<Option<Reconciler>>,
 }
 
 impl UsdtTrade {
-    pub async fn new(rest: Arc<Rest>, account: Account<UsdtListenKey>) -> anyhow::Result<Self> {
-        let products = get_positions(&rest).await?;
+    pub async fn new(accounts: Accounts<UsdtListenKey>) -> anyhow::Result<Self> {
+        let (products, limits) = get_positions(accounts.get(0).rest()).await?;
+        accounts.set_rate_limits(&limits);
+        let len = accounts.count();
 
         Ok(Self {
-            rest,
             txs: HashMap::default(),
-            account,
+            accounts,
             session_id: HashMap::default(),
             session: HashMap::default(),
             posdb: Arc::new(PositionDB::new("pos.db").await?),
+            oms: Oms::default(),
             products,
+            balances: vec![HashMap::default(); len],
+            positions: vec![HashMap::default(); len],
+            reconcilers: (0..len).map(|_| None).collect(),
         })
     }
+
+    // compare the nets of the sessions of an account with its /fapi/v2/positionRisk
+    pub fn reconcile(&mut self, index: usize, config: ReconcileConfig) -> anyhow::Result<()> {
+        self.accounts.check_reconcile(index, &config)?;
+        self.reconcilers[index] = Some(Reconciler::new(config));
+        Ok(())
+    }
 }
 impl Trade for UsdtTrade {
     fn disconnected(&self) -> bool {
-        self.account.disconnected()
+        self.accounts.disconnected()
+    }
+
+    fn session_disconnected(&self, session_id: u16) -> bool {
+        self.accounts.session_disconnected(session_id)
     }
 
     fn products(&self) -> &HashMap<String, BinanceProduct> {
         &self.products
     }
 
+    // live once the session logged in, what pos.db loaded before
     fn get_positions(&self, session_id: u16) -> Option<&HashMap<String, Position>> {
-        self.posdb.get_positions(session_id)
+        self.session
+            .get(&session_id)
+            .map(Session::positions)
+            .or_else(|| self.posdb.get_positions(session_id))
+    }
+
+    fn oms(&self) -> &Oms {
+        &self.oms
+    }
+
+    fn get_balances(&self, session_id: u16) -> Vec<Balance> {
+        let index = self.accounts.index(session_id);
+        self.balances[index].values().cloned().collect()
+    }
+
+    fn rest(&self, session_id: u16) -> Option<&Rest> {
+        let index = self.accounts.index(session_id);
+        Some(self.accounts.get(index).rest())
+    }
+
+    async fn get_trades(&self, req: &TradeReq) -> anyhow::Result<Vec<Fill>> {
+        self.posdb.get_trades(req).await
     }
 
     async fn get_products(&mut self) -> anyhow::Result<()> {
-        self.products = get_positions(&self.rest).await?;
+        let (products, limits) = get_positions(self.accounts.get(0).rest()).await?;
+        self.accounts.set_rate_limits(&limits);
+        self.products = products;
         Ok(())
     }
 
     async fn process(&mut self) -> anyhow::Result<bool> {
-        let msg = self.account.process().await?;
+        for (index, reconciler) in self.reconcilers.iter_mut().enumerate() {
+            if let Some(reconciler) = reconciler.as_mut().filter(|r| r.due()) {
+                let rest = self.accounts.get(index).rest().clone();
+                reconciler.spawn(get_exchange_nets(rest));
+            }
+        }
 
-        if let Some(Message::Text(s)) = msg {
-            if let Event::OrderUpdate(order) = serde_json::from_str::<Event>(&s)? {
-                self.on_order(&order);
+        tokio::select! {
+            (index, msg) = self.accounts.process() => {
+                if let Some(Message::Text(s)) = msg? {
+                    match serde_json::from_str::<Event>(&s)? {
+                        Event::OrderUpdate(order) => self.on_order(&order),
+                        Event::AccountUpdate(update) => self.on_account(index, &update),
+                        Event::MarginCall(call) => self.on_margin_call(index, &call),
+                        _ => (),
+                    }
+                }
+            }
+            (index, Some(nets)) = reconciled(&mut self.reconcilers) => {
+                self.on_reconcile(index, nets).await?
             }
         }
 
@@ -91,7 +180,7 @@ impl Trade for UsdtTrade {
     fn add_order(&mut self, addr: &SocketAddr, order: &BinanceOrder) -> anyhow::Result<()> {
         match self.txs.get_mut(addr) {
             Some(tx) => {
-                let rest = self.rest.clone();
+                let transport = self.accounts.transport(order.session_id);
                 let tx = tx.clone();
 
                 let symbol = order.symbol.clone();
@@ -102,9 +191,11 @@ impl Trade for UsdtTrade {
                 let tif = order.tif.clone();
                 let session_id = order.session_id;
                 let id = order.id;
+                let order = order.clone();
+                let sent = self.oms.sent().clone();
 
                 tokio::spawn(async move {
-                    match rest
+                    let rejected = match transport
                         .add_order(
                             "/fapi/v1/order",
                             symbol.clone().to_uppercase(),
@@ -118,54 +209,17 @@ impl Trade for UsdtTrade {
                         )
                         .await
                     {
-                        Ok(rsp) => {
-                            // exchange rej
-                            if let Ok(e) = rsp.json::<xcrypto::chat::Error>().await {
-                                error!("{:?}", e);
-                                let order = Order::new(
-                                    id,
-                                    symbol,
-                                    side,
-                                    State::REJECTED,
-                                    order_type,
-                                    tif,
-                                    quantity,
-                                    price,
-                                );
-
-                                match serde_json::to_string(&order) {
-                                    Ok(s) => {
-                                        if let Err(e) = tx.send(Message::Text(s)) {
-                                            error!("{}", e);
-                                        }
-                                    }
-                                    Err(e) => error!("{}", e),
-                                }
-                            }
-                        }
+                        // exchange rej
+                        Ok(rsp) => serde_json::from_value::<xcrypto::chat::Error>(rsp)
+                            .ok()
+                            .map(|e| anyhow::anyhow!("{:?}", e)),
                         // network error
-                        Err(e) => {
-                            error!("{:?}", e);
-                            let order = Order::new(
-                                id,
-                                symbol,
-                                side,
-                                State::REJECTED,
-                                order_type,
-                                tif,
-                                quantity,
-                                price,
-                            );
-
-                            match serde_json::to_string(&order) {
-                                Ok(s) => {
-                                    if let Err(e) = tx.send(Message::Text(s)) {
-                                        error!("{}", e);
-                                    }
-                                }
-                                Err(e) => error!("{}", e),
-                            }
-                        }
+                        Err(e) => Some(e),
+                    };
+
+                    if let Some(e) = rejected {
+                        error!("{:?}", e);
+                        sent.reject(&tx, &order);
                     }
                 });
             }
@@ -178,7 +232,7 @@ impl Trade for UsdtTrade {
     fn cancel(&mut self, addr: &SocketAddr, cancel: &BinanceCancel) -> anyhow::Result<()> {
         match self.txs.get_mut(addr) {
             Some(_) => {
-                let rest = self.rest.clone();
+                let transport = self.accounts.transport(cancel.session_id);
 
                 let symbol = cancel.symbol.clone().to_uppercase();
                 let session_id = cancel.session_id;
@@ -186,7 +240,7 @@ impl Trade for UsdtTrade {
 
                 let orig = u64::from(session_id) << 32 | u64::from(order_id);
                 tokio::spawn(async move {
-                    if let Err(e) = rest.cancel("/fapi/v1/order", symbol, orig).await {
+                    if let Err(e) = transport.cancel("/fapi/v1/order", symbol, orig).await {
                         error!("{}", e)
                     }
                 });
@@ -196,6 +250,189 @@ impl Trade for UsdtTrade {
         Ok(())
     }
 
+    fn cancel_all(&mut self, session_id: u16) -> anyhow::Result<()> {
+        for order in self.oms.get_orders(session_id).open {
+            let transport = self.accounts.transport(session_id);
+            let symbol = order.symbol.to_uppercase();
+            let orig = client_order_id(session_id, order.internal_id);
+
+            tokio::spawn(async move {
+                if let Err(e) = transport.cancel("/fapi/v1/order", symbol, orig).await {
+                    error!("{}", e)
+                }
+            });
+        }
+        Ok(())
+    }
+
+    fn amend(&mut self, addr: &SocketAddr, id: i64, amend: &BinanceAmend) -> anyhow::Result<()> {
+        let tx = match self.txs.get(addr) {
+            Some(tx) => tx.clone(),
+            None => {
+                warn!("Missing session {}, maybe a bug", addr);
+                return Ok(());
+            }
+        };
+
+        let key = client_order_id(amend.session_id, amend.order_id);
+        let order = match self.oms.get(key) {
+            Some(order) => order.clone(),
+            None => {
+                let e = Error {
+                    code: UNKNOWN_ORDER,
+                    msg: format!("unknown order {} to amend", amend.order_id),
+                };
+                warn!("{:?}", e);
+                send(&tx, &Response { id, result: e });
+                return Ok(());
+            }
+        };
+
+        let transport = self.accounts.transport(amend.session_id);
+        let session_id = amend.session_id;
+        let price = amend.price.to_string();
+        let quantity = amend.quantity.to_string();
+
+        // the exchange answers with an AMENDMENT update keeping the client order id
+        tokio::spawn(async move {
+            let e = match transport
+                .modify("/fapi/v1/order", &order, session_id, price, quantity)
+                .await
+            {
+                Ok(rsp) => match serde_json::from_value::<xcrypto::chat::Error>(rsp) {
+                    Ok(e) => Error {
+                        code: AMEND_FAILED,
+                        msg: format!("{} {}", e.code, e.msg),
+                    },
+                    Err(_) => return,
+                },
+                Err(e) => Error {
+                    code: AMEND_FAILED,
+                    msg: e.to_string(),
+                },
+            };
+            error!("{:?}", e);
+            send(&tx, &Response { id, result: e });
+        });
+        Ok(())
+    }
+
+    fn batch_order(
+        &mut self,
+        addr: &SocketAddr,
+        mut batch: Batch,
+        orders: Vec<BinanceOrder>,
+    ) -> anyhow::Result<()> {
+        let tx = match self.txs.get(addr) {
+            Some(tx) => tx.clone(),
+            None => {
+                warn!("Missing session {}, maybe a bug", addr);
+                return Ok(());
+            }
+        };
+
+        let rest = self.rest_of(addr);
+        let sent = self.oms.sent().clone();
+        tokio::spawn(async move {
+            // binance takes at most 5 orders per batch
+            let chunks: Vec<_> = orders.chunks(BATCH_ORDERS).collect();
+            let rsps = futures::future::join_all(chunks.iter().map(|chunk| {
+                let params: Vec<_> = chunk
+                    .iter()
+                    .map(|order| {
+                        order_params(
+                            order.symbol.to_uppercase(),
+                            order.price.to_string(),
+                            order.quantity.to_string(),
+                            format!("{:?}", order.side),
+                            format!("{:?}", order.order_type),
+                            format!("{:?}", order.tif),
+                            client_order_id(order.session_id, order.id),
+                        )
+                    })
+                    .collect();
+                let rest = rest.clone();
+                async move {
+                    let rsp = rest.batch_orders("/fapi/v1/batchOrders", &params).await?;
+                    anyhow::Ok(rsp.json::<serde_json::Value>().await?)
+                }
+            }))
+            .await;
+
+            for (chunk, rsp) in chunks.iter().zip(rsps) {
+                for (order, e) in chunk.iter().zip(batch_errors(rsp, chunk.len())) {
+                    if let Some(e) = e {
+                        batch.reject_order(&tx, &sent, order, e);
+                    }
+                }
+            }
+            batch.reply(&tx);
+        });
+        Ok(())
+    }
+
+    fn batch_cancel(
+        &mut self,
+        addr: &SocketAddr,
+        mut batch: Batch,
+        cancels: Vec<BinanceCancel>,
+    ) -> anyhow::Result<()> {
+        let tx = match self.txs.get(addr) {
+            Some(tx) => tx.clone(),
+            None => {
+                warn!("Missing session {}, maybe a bug", addr);
+                return Ok(());
+            }
+        };
+
+        // binance cancels at most 10 orders of one symbol per batch
+        let mut symbols: HashMap<String, Vec<BinanceCancel>> = HashMap::default();
+        for cancel in cancels {
+            symbols
+                .entry(cancel.symbol.to_uppercase())
+                .or_default()
+                .push(cancel);
+        }
+
+        let rest = self.rest_of(addr);
+        tokio::spawn(async move {
+            let chunks: Vec<_> = symbols
+                .iter()
+                .flat_map(|(symbol, cancels)| {
+                    cancels
+                        .chunks(BATCH_CANCELS)
+                        .map(move |chunk| (symbol.clone(), chunk))
+                })
+                .collect();
+            let rsps = futures::future::join_all(chunks.iter().map(|(symbol, chunk)| {
+                let origs: Vec<_> = chunk
+                    .iter()
+                    .map(|cancel| client_order_id(cancel.session_id, cancel.order_id))
+                    .collect();
+                let rest = rest.clone();
+                let symbol = symbol.clone();
+                async move {
+                    let rsp = rest
+                        .batch_cancel("/fapi/v1/batchOrders", symbol, &origs)
+                        .await?;
+                    anyhow::Ok(rsp.json::<serde_json::Value>().await?)
+                }
+            }))
+            .await;
+
+            for ((_, chunk), rsp) in chunks.iter().zip(rsps) {
+                for (cancel, e) in chunk.iter().zip(batch_errors(rsp, chunk.len())) {
+                    if let Some(e) = e {
+                        error!("{:?} {:?}", cancel, e);
+                        batch.reject(cancel.order_id, e);
+                    }
+                }
+            }
+            batch.reply(&tx);
+        });
+        Ok(())
+    }
+
     fn handle_close(&mut self, addr: &SocketAddr) -> anyhow::Result<()> {
         if let Some(_) = self.txs.remove(addr) {
             match self.session_id.remove(addr) {
@@ -239,6 +476,14 @@ impl Trade for UsdtTrade {
         self.txs.insert(addr.clone(), tx.clone());
         self.session_id.insert(addr.clone(), session_id);
 
+        let index = self.accounts.index(session_id);
+        for balance in self.balances[index].values() {
+            send(tx, balance);
+        }
+        for position in self.positions[index].values() {
+            send(tx, position);
+        }
+
         info!("session addr {} -> {}", addr, session_id);
         Ok(None)
     }
@@ -248,7 +493,10 @@ impl Trade for UsdtTrade {
         let mut params = Vec::new();
 
         for symbol in req.params.iter() {
-            let symbol = symbol.to_lowercase();
+            if symbol == ALL_FORCE_ORDERS {
+                continue;
+            }
+            let symbol = normalize_stream(symbol);
             match symbol.split_once("@") {
                 Some((name, stream)) => {
                     if !self.products.contains_key(name) {
@@ -277,26 +525,36 @@ impl Trade for UsdtTrade {
     }
 
     #[allow(unused)]
-    fn validate_symbol(&self, symbol: &str, stream: &str) -> bool {
+    fn validate_symbol(&self, _: &str, stream: &str) -> bool {
         match stream.split_once(":") {
-            Some((stream, interval)) => {
-                match stream {
-                    "kline" => match interval {
-                        "1s" | "1m" | "3m" | "5m" | "15m" | "30m" | "1h" | "2h" | "4h" | "6h"
-                        | "8h" | "12h" | "1d" | "3d" | "1w" | "1M" => return true,
-                        _ => return false,
-                    },
-                    "depth" => match interval {
-                        "100ms" => return true,
-                        _ => return false,
-                    },
-                    _ => return false,
-                };
-            }
-            None => match stream {
-                "depth" | "bbo" => true,
+            Some((stream, interval)) => match stream {
+                "kline" => matches!(
+                    interval,
+                    "1s" | "1m"
+                        | "3m"
+                        | "5m"
+                        | "15m"
+                        | "30m"
+                        | "1h"
+                        | "2h"
+                        | "4h"
+                        | "6h"
+                        | "8h"
+                        | "12h"
+                        | "1d"
+                        | "3d"
+                        | "1w"
+                        | "1M"
+                ),
+                "depth" => interval == "100ms",
+                "markPrice" => interval == "1s",
                 _ => false,
             },
+            // usdt future has no raw trade stream
+            None => matches!(
+                stream,
+                "depth" | "bbo" | "book" | "aggTrade" | "markPrice" | "forceOrder"
+            ),
         }
     }
 
@@ -333,7 +591,8 @@ impl Trade for UsdtTrade {
     }
 
     async fn reconncet(&mut self) -> anyhow::Result<()> {
-        self.account.reconnect().await
+        let (_, errors) = self.accounts.reconnect().await;
+        join_errors(errors)
     }
 }
 
@@ -346,6 +605,7 @@ impl UsdtTrade {
         match client_order_id {
             Ok(client_order_id) => {
                 let session_id = (client_order_id >> 32) as u16;
+                self.oms.on_order(client_order_id, order.clone().into());
 
                 match self.session.get_mut(&session_id) {
                     Some(session) => {
@@ -359,4 +619,96 @@ impl UsdtTrade {
             Err(_) => info!("Extrnal order:{:?} ", order),
         }
     }
+
+    fn on_account(&mut self, index: usize, update: &AccountUpdate) {
+        info!("{} {:?}", self.accounts.name(index), update);
+        for balance in update.balances() {
+            self.broadcast(index, &balance);
+            self.balances[index].insert(balance.asset.clone(), balance);
+        }
+        for position in update.positions() {
+            self.broadcast(index, &position);
+            let key = (position.symbol.clone(), position.position_side.clone());
+            self.positions[index].insert(key, position);
+        }
+    }
+
+    async fn on_reconcile(
+        &mut self,
+        index: usize,
+        nets: anyhow::Result<Nets>,
+    ) -> anyhow::Result<()> {
+        let exchange = match nets {
+            Ok(nets) => nets,
+            Err(e) => {
+                error!("{}", e);
+                return Ok(());
+            }
+        };
+
+        if let Some(reconciler) = &self.reconcilers[index] {
+            let accounts = &self.accounts;
+            let owns = |session_id| accounts.index(session_id) == index;
+            reconciler
+                .reconcile(&exchange, &self.posdb, &mut self.session, owns)
+                .await?;
+        }
+        Ok(())
+    }
+
+    fn on_margin_call(&self, index: usize, call: &MarginCall) {
+        for item in call.p.iter() {
+            warn!(
+                "Margin call of {} {} {}, amount {}, unrealized {}, maintenance {}",
+                self.accounts.name(index),
+                item.s,
+                item.ps,
+                item.pa,
+                item.up,
+                item.mm
+            );
+        }
+    }
+
+    // the rest of the account the session logged in at addr trades on
+    fn rest_of(&self, addr: &SocketAddr) -> Arc<Rest> {
+        let session_id = self.session_id.get(addr).cloned().unwrap_or_default();
+        self.accounts.rest(session_id)
+    }
+
+    // every trading session of an account
+    fn broadcast<T: Serialize>(&self, index: usize, data: &T) {
+        for (addr, tx) in self.txs.iter() {
+            match self.session_id.get(addr) {
+                Some(session_id) if self.accounts.index(*session_id) == index => send(tx, data),
+                _ => (),
+            }
+        }
+    }
+}
+
+// the batch answers each item with its order or an error, a failed request fails every item
+fn batch_errors(rsp: anyhow::Result<serde_json::Value>, len: usize) -> Vec<Option<Error>> {
+    match rsp {
+        Ok(serde_json::Value::Array(items)) => items
+            .into_iter()
+            .map(|item| serde_json::from_value::<Error>(item).ok())
+            .chain(std::iter::repeat(None))
+            .take(len)
+            .collect(),
+        Ok(rsp) => {
+            let e = serde_json::from_value::<Error>(rsp.clone()).unwrap_or(Error {
+                code: UNDEF_ERROR,
+                msg: rsp.to_string(),
+            });
+            vec![Some(e); len]
+        }
+        Err(e) => vec![
+            Some(Error {
+                code: UNDEF_ERROR,
+                msg: e.to_string(),
+            });
+            len
+        ],
+    }
 }
@@ -1,6 +1,14 @@
 import sys
 from .pyalgo import *
-from pyalgo.core.trd import SmartOrder, DepthSubscription, BarSubscription
+from pyalgo.core.trd import (
+    SmartOrder,
+    DepthSubscription,
+    BarSubscription,
+    TradeSubscription,
+    MarkPriceSubscription,
+    LiquidationSubscription,
+    MarketSubscription,
+)
 from pyalgo.core.engine import Engine
 from pyalgo.core.context import Context
 
@@ -40,6 +48,10 @@ __all__ = [
     "error",
     "DepthSubscription",
     "BarSubscription",
+    "TradeSubscription",
+    "MarkPriceSubscription",
+    "LiquidationSubscription",
+    "MarketSubscription",
     "Engine",
     "Context",
     "SmartOrder",
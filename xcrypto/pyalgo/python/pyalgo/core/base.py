@@ -19,3 +19,6 @@ class ContextBase(ABC):
 
     def cancel(self, symbol: str, order_id: int):
         raise NotImplemented
+
+    def amend(self, symbol: str, order_id: int, price: float, quantity: float):
+        raise NotImplemented
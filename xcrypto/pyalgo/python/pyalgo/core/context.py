@@ -1,16 +1,26 @@
 from pyalgo import *
-from typing import Union, Dict
+from typing import Union, Dict, List
 from .trd import *
 
 
 class Context(ContextBase):
     """"""
 
-    def __init__(self, addr: str, session_id: int, name: str, trading: bool):
-        self.session = Session(addr, session_id, name, trading)
+    def __init__(self, session: Union[Session, Backtest]):
+        self.session = session
 
         self.tradings: Dict[str, Tradable] = {}
-        self.subscriptions: Dict[str, Union[DepthSubscription, BarSubscription]] = {}
+        self.subscriptions: Dict[
+            str,
+            Union[
+                DepthSubscription,
+                BarSubscription,
+                TradeSubscription,
+                MarkPriceSubscription,
+                LiquidationSubscription,
+                MarketSubscription,
+            ],
+        ] = {}
 
     @property
     def id(self):
@@ -28,10 +38,38 @@ class Context(ContextBase):
     def is_login(self):
         return self.session.is_login
 
+    @property
+    def balances(self) -> Dict[str, Balance]:
+        return self.session.balances
+
+    @property
+    def exchange_positions(self) -> List[ExchangePosition]:
+        return self.session.exchange_positions
+
+    @property
+    def finished(self) -> bool:
+        return isinstance(self.session, Backtest) and self.session.finished
+
     def connect(self):
         self.session.connect()
 
-    def on_market(self, data: Union[Depth, Kline]):
+    def refresh_balances(self):
+        self.session.refresh_balances()
+
+    def get_latency(self):
+        self.session.get_latency()
+
+    def get_trades(
+        self,
+        symbol: Optional[str] = None,
+        start: Optional[int] = None,
+        end: Optional[int] = None,
+    ):
+        self.session.get_trades(symbol, start, end)
+
+    def on_market(
+        self, data: Union[Depth, Kline, Trade, PremiumIndex, Liquidation]
+    ):
         if sub := self.subscriptions.get(data.stream):
             sub.on_market(data)
 
@@ -41,12 +79,28 @@ class Context(ContextBase):
 
     def subscribe(
         self, symbol: str, stream: str
-    ) -> Union[DepthSubscription, BarSubscription]:
-        if symbol in self.tradings:
+    ) -> Union[
+        DepthSubscription,
+        BarSubscription,
+        TradeSubscription,
+        MarkPriceSubscription,
+        LiquidationSubscription,
+    ]:
+        key = symbol + "@" + stream
+        trades = stream in ("trade", "aggTrade")
+        marks = stream.startswith("markPrice")
+        liquidations = stream == "forceOrder"
+        # these streams may sit beside the depth or bar stream of their symbol
+        beside = (TradeSubscription, MarkPriceSubscription, LiquidationSubscription)
+        current = self.tradings.get(symbol)
+        if key in self.subscriptions or (
+            not (trades or marks or liquidations)
+            and current
+            and not isinstance(current, beside)
+        ):
             raise Exception(f"Duplicate subscribe {symbol}")
 
         sub = self.session.subscribe(symbol, stream)
-        key = symbol + "@" + stream
 
         if stream.startswith("kline"):
             bar = BarSubscription(sub, self)
@@ -55,20 +109,58 @@ class Context(ContextBase):
 
             return bar
 
-        elif stream.startswith("depth") or stream == "bbo":
+        elif stream.startswith("depth") or stream in ("bbo", "book"):
             depth = DepthSubscription(sub, self)
             self.subscriptions[key] = depth
             self.tradings[symbol] = depth
 
             return depth
 
+        elif trades:
+            trade = TradeSubscription(sub, self)
+            self.subscriptions[key] = trade
+            self.tradings.setdefault(symbol, trade)
+
+            return trade
+
+        elif marks:
+            mark = MarkPriceSubscription(sub, self)
+            self.subscriptions[key] = mark
+            self.tradings.setdefault(symbol, mark)
+
+            return mark
+
+        elif liquidations:
+            liquidation = LiquidationSubscription(sub, self)
+            self.subscriptions[key] = liquidation
+            self.tradings.setdefault(symbol, liquidation)
+
+            return liquidation
+
         else:
             raise Exception(f"Unsupported stream {stream}")
 
+    def subscribe_all(self, stream: str) -> MarketSubscription:
+        key = "!" + stream + "@arr"
+        if key in self.subscriptions:
+            raise Exception(f"Duplicate subscribe {key}")
+
+        self.session.subscribe_all(stream)
+        sub = MarketSubscription(key)
+        self.subscriptions[key] = sub
+
+        return sub
+
     def process(self):
         if event := self.session.process():
             match event.event_type:
-                case EventType.Depth | EventType.Kline:
+                case (
+                    EventType.Depth
+                    | EventType.Kline
+                    | EventType.Trade
+                    | EventType.PremiumIndex
+                    | EventType.Liquidation
+                ):
                     self.on_market(event.data)
 
                 case EventType.Order:
@@ -89,3 +181,6 @@ class Context(ContextBase):
 
     def cancel(self, symbol: str, order_id: int):
         self.session.cancel(symbol, order_id)
+
+    def amend(self, symbol: str, order_id: int, price: float, quantity: float):
+        self.session.amend(symbol, order_id, price, quantity)
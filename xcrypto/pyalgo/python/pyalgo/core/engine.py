@@ -1,6 +1,6 @@
-from pyalgo import init_logger
+from pyalgo import init_logger, Session, Backtest
 from .context import Context
-from typing import Dict, Tuple
+from typing import Dict, List, Tuple
 from time import sleep
 import sys
 import signal
@@ -28,11 +28,33 @@ class Engine:
         if key in self.contexts:
             return self.contexts[key]
 
-        context = Context(addr, session_id, name, trading)
+        context = Context(Session(addr, session_id, name, trading))
         self.contexts[key] = context
 
         context.connect()
-        
+
+        return context
+
+    def make_backtest(
+        self,
+        products: str,
+        files: List[str],
+        session_id: int = 1,
+        name: str = "backtest",
+        latency: int = 0,
+        touch: bool = False,
+    ) -> Context:
+        key = ("backtest", session_id)
+        if key in self.contexts:
+            return self.contexts[key]
+
+        context = Context(
+            Backtest(products, files, session_id, name, latency, touch)
+        )
+        self.contexts[key] = context
+
+        context.connect()
+
         return context
 
     def run(self):
@@ -41,7 +63,13 @@ class Engine:
         while self.active:
             for context in self.contexts.values():
                 context.process()
-            sleep(self.interval)
+
+            # backtests stop once every replay is exhausted
+            if all(context.finished for context in self.contexts.values()):
+                break
+
+            if self.interval > 0:
+                sleep(self.interval)
 
     def stop(self, *_):
         self.active = False
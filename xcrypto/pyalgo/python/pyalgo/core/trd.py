@@ -49,6 +49,18 @@ class Tradable:
     def net(self) -> float:
         return self.subscription.net
 
+    @property
+    def avg_price(self) -> float:
+        return self.subscription.avg_price
+
+    @property
+    def realized_pnl(self) -> float:
+        return self.subscription.realized_pnl
+
+    @property
+    def fee(self) -> float:
+        return self.subscription.fee
+
     def order_support(self, order_type: OrderType) -> bool:
         return self.subscription.order_support(order_type)
 
@@ -86,6 +98,9 @@ class Tradable:
     def cancel(self, order_id: int):
         self.ctx.cancel(self.symbol, order_id)
 
+    def amend(self, order_id: int, price: float, quantity: float):
+        self.ctx.amend(self.symbol, order_id, price, quantity)
+
 
 class DepthSubscription(Tradable):
     """"""
@@ -181,6 +196,127 @@ class BarSubscription(Tradable):
         self.on_data(data)
 
 
+class TradeSubscription(Tradable):
+    """"""
+
+    def __init__(self, subscription: Subscription, ctx: ContextBase):
+        super().__init__(subscription, ctx)
+        self.on_data = lambda x: None
+        self.data: Trade = None
+
+    @property
+    def time(self) -> int:
+        return self.data.time if self.data else 0
+
+    @property
+    def datetime(self) -> datetime:
+        return self.data.datetime if self.data else datetime.min
+
+    @property
+    def id(self) -> int:
+        return self.data.id if self.data else 0
+
+    @property
+    def price(self) -> float:
+        return self.data.price if self.data else 0.0
+
+    @property
+    def quantity(self) -> float:
+        return self.data.quantity if self.data else 0.0
+
+    @property
+    def side(self) -> Optional[Side]:
+        return self.data.side if self.data else None
+
+    @property
+    def phase(self) -> Phase:
+        return self.determine(self.time)
+
+    def on_market(self, data: Trade):
+        self.data = data
+        self.on_data(data)
+
+class MarkPriceSubscription(Tradable):
+    """"""
+
+    def __init__(self, subscription: Subscription, ctx: ContextBase):
+        super().__init__(subscription, ctx)
+        self.on_data = lambda x: None
+        self.data: PremiumIndex = None
+
+    @property
+    def time(self) -> int:
+        return self.data.time if self.data else 0
+
+    @property
+    def datetime(self) -> datetime:
+        return self.data.datetime if self.data else datetime.min
+
+    @property
+    def mark_price(self) -> float:
+        return self.data.mark_price if self.data else 0.0
+
+    @property
+    def index_price(self) -> float:
+        return self.data.index_price if self.data else 0.0
+
+    @property
+    def estimated_settle_price(self) -> float:
+        return self.data.estimated_settle_price if self.data else 0.0
+
+    @property
+    def funding_rate(self) -> float:
+        return self.data.last_funding_rate if self.data else 0.0
+
+    @property
+    def next_funding_time(self) -> int:
+        return self.data.next_funding_time if self.data else 0
+
+    @property
+    def phase(self) -> Phase:
+        return self.determine(self.time)
+
+    def on_market(self, data: PremiumIndex):
+        self.data = data
+        self.on_data(data)
+
+class LiquidationSubscription(Tradable):
+    """"""
+
+    def __init__(self, subscription: Subscription, ctx: ContextBase):
+        super().__init__(subscription, ctx)
+        self.on_data = lambda x: None
+        self.data: Liquidation = None
+
+    @property
+    def time(self) -> int:
+        return self.data.time if self.data else 0
+
+    @property
+    def datetime(self) -> datetime:
+        return self.data.datetime if self.data else datetime.min
+
+    @property
+    def phase(self) -> Phase:
+        return self.determine(self.time)
+
+    def on_market(self, data: Liquidation):
+        self.data = data
+        self.on_data(data)
+
+
+class MarketSubscription:
+    """a stream of the whole market, data of every symbol is passed to on_data"""
+
+    def __init__(self, stream: str):
+        self.stream = stream
+        self.on_data = lambda x: None
+        self.data = None
+
+    def on_market(self, data):
+        self.data = data
+        self.on_data(data)
+
 class SmartOrder:
     """"""
 
@@ -245,3 +381,12 @@ class SmartOrder:
             return
 
         self.subscription.cancel(self.order.id)
+
+    def amend(self, price: float, quantity: float):
+        if not self.is_active:
+            return
+
+        price = self.subscription.round_price(price)
+        quantity = self.subscription.floor_to_lot_size(quantity)
+
+        self.subscription.amend(self.order.id, price, quantity)
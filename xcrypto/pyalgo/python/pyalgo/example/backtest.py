@@ -0,0 +1,19 @@
+from pyalgo import *
+from demo import Demo
+
+
+if __name__ == "__main__":
+    eng = Engine(0)
+    # products.json is the result of a get_products request,
+    # the replay files are written by the trading system with `record` enabled
+    session = eng.make_backtest(
+        products="products.json",
+        files=["record/dogeusdt/dogeusdt_20240508.jsonl.gz"],
+        latency=50,
+    )
+
+    sub = session.subscribe("dogeusdt", "depth")
+    demo = Demo(sub)
+    eng.run()
+
+    info(f"net {sub.net}")
@@ -1,5 +1,5 @@
 from enum import Enum
-from typing import Optional, List, Dict
+from typing import Optional, List, Dict, Tuple, Literal
 from datetime import datetime
 
 class Handle: ...
@@ -54,6 +54,172 @@ class Depth:
     def bid_vol(self, level: int) -> float: ...
     def ask_vol(self, level: int) -> float: ...
 
+class Trade:
+    """"""
+
+    @property
+    def time(self) -> int: ...
+    @property
+    def datetime(self) -> datetime: ...
+    @property
+    def symbol(self) -> str: ...
+    @property
+    def stream(self) -> str: ...
+    @property
+    def id(self) -> int: ...
+    @property
+    def price(self) -> float: ...
+    @property
+    def quantity(self) -> float: ...
+    @property
+    def side(self) -> Side: ...
+
+class Liquidation:
+    """"""
+
+    @property
+    def time(self) -> int: ...
+    @property
+    def datetime(self) -> datetime: ...
+    @property
+    def symbol(self) -> str: ...
+    @property
+    def stream(self) -> str: ...
+    @property
+    def side(self) -> Side: ...
+    @property
+    def state(self) -> State: ...
+    @property
+    def price(self) -> float: ...
+    @property
+    def avg_price(self) -> float: ...
+    @property
+    def quantity(self) -> float: ...
+    @property
+    def filled(self) -> float: ...
+
+class Balance:
+    """"""
+
+    @property
+    def time(self) -> int: ...
+    @property
+    def datetime(self) -> datetime: ...
+    @property
+    def asset(self) -> str: ...
+    @property
+    def wallet(self) -> float: ...
+    @property
+    def free(self) -> float: ...
+    @property
+    def locked(self) -> float: ...
+    @property
+    def cross_wallet(self) -> float: ...
+    @property
+    def change(self) -> float: ...
+
+class ExchangePosition:
+    """"""
+
+    @property
+    def time(self) -> int: ...
+    @property
+    def datetime(self) -> datetime: ...
+    @property
+    def symbol(self) -> str: ...
+    @property
+    def position_side(self) -> str: ...
+    @property
+    def amount(self) -> float: ...
+    @property
+    def entry_price(self) -> float: ...
+    @property
+    def break_even_price(self) -> float: ...
+    @property
+    def unrealized_pnl(self) -> float: ...
+    @property
+    def realized_pnl(self) -> float: ...
+    @property
+    def margin_type(self) -> str: ...
+    @property
+    def isolated_wallet(self) -> float: ...
+
+class RateLimit:
+    """"""
+
+    @property
+    def rate_limit_type(self) -> str: ...
+    @property
+    def interval(self) -> str: ...
+    @property
+    def used(self) -> int: ...
+    @property
+    def limit(self) -> int: ...
+    @property
+    def reset(self) -> int: ...
+
+class EndpointLatency:
+    """"""
+
+    @property
+    def endpoint(self) -> str: ...
+    @property
+    def count(self) -> int: ...
+    @property
+    def errors(self) -> int: ...
+    @property
+    def mean(self) -> int: ...
+    @property
+    def min(self) -> int: ...
+    @property
+    def max(self) -> int: ...
+    @property
+    def p50(self) -> int: ...
+    @property
+    def p90(self) -> int: ...
+    @property
+    def p99(self) -> int: ...
+    @property
+    def bounds(self) -> List[int]: ...
+    @property
+    def buckets(self) -> List[int]: ...
+
+class Latency:
+    """"""
+
+    @property
+    def offset(self) -> Optional[int]: ...
+    @property
+    def rtt(self) -> Optional[int]: ...
+    @property
+    def endpoints(self) -> List[EndpointLatency]: ...
+
+class Fill:
+    """"""
+
+    @property
+    def session_id(self) -> int: ...
+    @property
+    def symbol(self) -> str: ...
+    @property
+    def side(self) -> Side: ...
+    @property
+    def order_id(self) -> int: ...
+    @property
+    def id(self) -> int: ...
+    @property
+    def price(self) -> float: ...
+    @property
+    def quantity(self) -> float: ...
+    @property
+    def commission(self) -> float: ...
+    @property
+    def making(self) -> bool: ...
+    @property
+    def trade_time(self) -> int: ...
+    @property
+    def datetime(self) -> datetime: ...
+
 class Phase(Enum):
     AUCTION = 0
     PRE_OPEN = 1
@@ -144,8 +310,16 @@ class Order:
 class EventType(Enum):
     Depth = 0
     Kline = 1
-    Order = 2
-    Position = 3
+    Trade = 2
+    PremiumIndex = 3
+    Liquidation = 4
+    Balance = 5
+    ExchangePosition = 6
+    Order = 7
+    Position = 8
+    Fills = 9
+    RateLimit = 10
+    Latency = 11
 
 class Event:
     """"""
@@ -165,6 +339,8 @@ class PremiumIndex:
     @property
     def symbol(self) -> str: ...
     @property
+    def stream(self) -> str: ...
+    @property
     def mark_price(self) -> float: ...
     @property
     def index_price(self) -> float: ...
@@ -182,13 +358,21 @@ class PremiumIndex:
 class Rest:
     """"""
 
-    def __init__(self, base_uri: str, apikey: str, private_key: str) -> Rest: ...
+    def __init__(
+        self,
+        base_uri: str,
+        apikey: str,
+        pem: str,
+        recvwindow: int,
+        sign: Literal["ed25519", "rsa", "hmac"] = "ed25519",
+    ) -> Rest: ...
     def get(self, path: str, params: Dict[str, str], authenticate: bool) -> str: ...
     def post(self, path: str, params: Dict[str, str], authenticate: bool) -> str: ...
     def put(self, path: str, params: Dict[str, str], authenticate: bool) -> str: ...
     def delete(self, path: str, params: Dict[str, str], authenticate: bool) -> str: ...
     def patch(self, path: str, params: Dict[str, str], authenticate: bool) -> str: ...
     def sign(self, data: str) -> str: ...
+    def sync_time(self, path: str) -> Tuple[int, int]: ...
     def get_premium_index(self) -> List[PremiumIndex]: ...
 
 class TradingPhase:
@@ -216,8 +400,77 @@ class Session:
     def trading(self) -> bool: ...
     @property
     def is_login(self) -> bool: ...
+    @property
+    def balances(self) -> Dict[str, Balance]: ...
+    @property
+    def exchange_positions(self) -> List[ExchangePosition]: ...
     def connect(self): ...
+    def refresh_balances(self): ...
+    def get_latency(self): ...
+    def get_trades(
+        self,
+        symbol: Optional[str] = None,
+        start: Optional[int] = None,
+        end: Optional[int] = None,
+    ): ...
     def subscribe(self, symbol: str): ...
+    def subscribe_all(self, stream: str): ...
+    def process(self) -> Optional[Event]: ...
+    def add_order(
+        self,
+        symbol: str,
+        price: float,
+        quantity: float,
+        side: Side,
+        order_type: OrderType,
+        tif: Tif,
+    ) -> Optional[Order]: ...
+    def cancel(self, symbol: str, order_id: int): ...
+    def amend(self, symbol: str, order_id: int, price: float, quantity: float): ...
+    def batch_order(
+        self, orders: List[Tuple[str, float, float, Side, OrderType, Tif]]
+    ) -> List[Order]: ...
+    def batch_cancel(self, cancels: List[Tuple[str, int]]): ...
+
+class Backtest:
+    """"""
+
+    def __init__(
+        self,
+        products: str,
+        files: List[str],
+        session_id: int = 1,
+        name: str = "backtest",
+        latency: int = 0,
+        touch: bool = False,
+    ) -> Backtest: ...
+    @property
+    def id(self) -> int: ...
+    @property
+    def name(self) -> str: ...
+    @property
+    def trading(self) -> bool: ...
+    @property
+    def is_login(self) -> bool: ...
+    @property
+    def balances(self) -> Dict[str, Balance]: ...
+    @property
+    def exchange_positions(self) -> List[ExchangePosition]: ...
+    @property
+    def finished(self) -> bool: ...
+    @property
+    def time(self) -> int: ...
+    def connect(self): ...
+    def refresh_balances(self): ...
+    def get_latency(self): ...
+    def get_trades(
+        self,
+        symbol: Optional[str] = None,
+        start: Optional[int] = None,
+        end: Optional[int] = None,
+    ): ...
+    def subscribe(self, symbol: str, stream: str) -> Subscription: ...
+    def subscribe_all(self, stream: str): ...
     def process(self) -> Optional[Event]: ...
     def add_order(
         self,
@@ -229,6 +482,7 @@ class Session:
         tif: Tif,
     ) -> Optional[Order]: ...
     def cancel(self, symbol: str, order_id: int): ...
+    def amend(self, symbol: str, order_id: int, price: float, quantity: float): ...
 
 class Subscription:
     """"""
@@ -251,6 +505,12 @@ class Subscription:
     def min_notional(self) -> float: ...
     @property
     def net(self) -> float: ...
+    @property
+    def avg_price(self) -> float: ...
+    @property
+    def realized_pnl(self) -> float: ...
+    @property
+    def fee(self) -> float: ...
     def order_support(self, order_type: OrderType) -> bool: ...
     def tif_support(self, tif: Tif) -> bool: ...
     def floor_to_lot_size(self, vol: float) -> float: ...
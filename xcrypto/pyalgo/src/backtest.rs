@@ -0,0 +1,839 @@
+use crate::chat::{
+    Balance, Depth, ExchangePosition, Fill, Kline, Liquidation, Order, PremiumIndex, Product, Trade,
+};
+use crate::constant::*;
+use crate::subscription::Subscription;
+use crate::{Event, EventType};
+use flate2::read::MultiGzDecoder;
+use log::*;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use serde::Deserialize;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet, VecDeque};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Lines, Read};
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Data {
+    Depth(Depth),
+    Kline(Kline),
+    Trade(Trade),
+    PremiumIndex(PremiumIndex),
+    Liquidation(Liquidation),
+}
+
+impl Data {
+    fn time(&self) -> i64 {
+        match self {
+            Data::Depth(depth) => depth.time() as i64,
+            Data::Kline(kline) => kline.time() as i64,
+            Data::Trade(trade) => trade.time() as i64,
+            Data::PremiumIndex(index) => index.time(),
+            Data::Liquidation(liquidation) => liquidation.time(),
+        }
+    }
+}
+
+// a line written by the market recorder, or the bare data forwarded to strategies
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Record {
+    Recorded { local: i64, data: Data },
+    Raw(Data),
+}
+
+type Reader = Lines<BufReader<Box<dyn Read + Send>>>;
+
+// merge several files into one stream ordered by receive time
+struct Replay {
+    readers: Vec<Reader>,
+    pending: Vec<Option<Data>>,
+    heap: BinaryHeap<Reverse<(i64, usize)>>,
+}
+
+impl Replay {
+    fn new(files: &[String]) -> anyhow::Result<Self> {
+        let mut replay = Self {
+            readers: Vec::new(),
+            pending: Vec::new(),
+            heap: BinaryHeap::new(),
+        };
+
+        for (idx, path) in files.iter().enumerate() {
+            let file = File::open(path)?;
+            let inner: Box<dyn Read + Send> = if path.ends_with(".gz") {
+                Box::new(MultiGzDecoder::new(file))
+            } else {
+                Box::new(file)
+            };
+
+            info!("Replay {}", path);
+            replay.readers.push(BufReader::new(inner).lines());
+            replay.pending.push(None);
+            replay.fetch(idx);
+        }
+        Ok(replay)
+    }
+
+    fn fetch(&mut self, idx: usize) {
+        for line in self.readers[idx].by_ref() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    error!("{}", e);
+                    break;
+                }
+            };
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            match serde_json::from_str::<Record>(&line) {
+                Ok(Record::Recorded { local, data }) => {
+                    self.heap.push(Reverse((local, idx)));
+                    self.pending[idx] = Some(data);
+                    return;
+                }
+                Ok(Record::Raw(data)) => {
+                    self.heap.push(Reverse((data.time(), idx)));
+                    self.pending[idx] = Some(data);
+                    return;
+                }
+                Err(e) => error!("{} {}", e, line),
+            }
+        }
+    }
+
+    fn next(&mut self) -> Option<(i64, Data)> {
+        let Reverse((time, idx)) = self.heap.pop()?;
+        let data = self.pending[idx].take()?;
+        self.fetch(idx);
+        Some((time, data))
+    }
+}
+
+enum Tick<'a> {
+    Depth(&'a Depth),
+    Kline(&'a Kline),
+}
+
+impl<'a> Tick<'a> {
+    fn symbol(&self) -> &str {
+        match self {
+            Tick::Depth(depth) => depth.symbol(),
+            Tick::Kline(kline) => kline.symbol(),
+        }
+    }
+
+    // liquidity a taker on this side can hit, best price first
+    fn levels(&self, side: Side) -> Vec<(f64, f64)> {
+        match self {
+            Tick::Depth(depth) => match side {
+                Side::BUY => (0..depth.ask_level())
+                    .map(|i| (depth.ask_prc(i), depth.ask_vol(i)))
+                    .filter(|(_, vol)| *vol > 0.0)
+                    .collect(),
+                Side::SELL => (0..depth.bid_level())
+                    .map(|i| (depth.bid_prc(i), depth.bid_vol(i)))
+                    .filter(|(_, vol)| *vol > 0.0)
+                    .collect(),
+            },
+            // bars carry no depth, takers fill in full at the open
+            Tick::Kline(kline) => vec![(kline.open(), f64::INFINITY)],
+        }
+    }
+
+    // whether the market has traded through a resting order
+    fn through(&self, side: Side, price: f64, touch: bool) -> bool {
+        match self {
+            Tick::Depth(_) => {
+                let bid = self.levels(Side::SELL).first().map(|(p, _)| *p);
+                let ask = self.levels(Side::BUY).first().map(|(p, _)| *p);
+                match side {
+                    Side::BUY => {
+                        ask.is_some_and(|ask| ask <= price)
+                            || bid.is_some_and(|bid| bid < price || (touch && bid == price))
+                    }
+                    Side::SELL => {
+                        bid.is_some_and(|bid| bid >= price)
+                            || ask.is_some_and(|ask| ask > price || (touch && ask == price))
+                    }
+                }
+            }
+            Tick::Kline(kline) => match side {
+                Side::BUY => kline.low() < price || (touch && kline.low() == price),
+                Side::SELL => kline.high() > price || (touch && kline.high() == price),
+            },
+        }
+    }
+}
+
+fn crosses(side: Side, level: f64, limit: f64) -> bool {
+    match side {
+        Side::BUY => level <= limit,
+        Side::SELL => level >= limit,
+    }
+}
+
+struct SimOrder {
+    order: Order,
+    arrive: i64,
+    cancel: Option<i64>,
+    // arrival time, price and quantity of the replacing order
+    amend: Option<(i64, f64, f64)>,
+    live: bool,
+}
+
+impl SimOrder {
+    fn leaves(&self) -> f64 {
+        self.order.quantity - self.order.acc
+    }
+
+    fn done(&self) -> bool {
+        self.leaves() <= self.order.quantity * 1e-9
+    }
+
+    fn update(&mut self, state: State, time: i64, events: &mut Vec<Order>) {
+        self.order.state = state;
+        self.order.time = time;
+        self.order.trade_quantity = 0.0;
+        events.push(self.order.clone());
+    }
+
+    fn fill(
+        &mut self,
+        price: f64,
+        quantity: f64,
+        making: bool,
+        time: i64,
+        events: &mut Vec<Order>,
+    ) {
+        self.order.acc += quantity;
+        self.order.state = if self.done() {
+            State::FILLED
+        } else {
+            State::PARTIALLY_FILLED
+        };
+        self.order.time = time;
+        self.order.trade_time = time;
+        self.order.trade_price = price;
+        self.order.trade_quantity = quantity;
+        self.order.making = Some(making);
+        events.push(self.order.clone());
+    }
+
+    // first tick after the order reached the exchange, returns whether it rests
+    fn arrive(&mut self, tick: &Tick, time: i64, events: &mut Vec<Order>) -> bool {
+        let side = self.order.side;
+        let price = self.order.price;
+        let market = match self.order.order_type {
+            OrderType::MARKET => true,
+            OrderType::LIMIT | OrderType::LIMIT_MAKER => false,
+            _ => {
+                self.update(State::REJECTED, time, events);
+                return false;
+            }
+        };
+
+        let levels = tick.levels(side);
+        if market && levels.is_empty() {
+            self.update(State::REJECTED, time, events);
+            return false;
+        }
+
+        let crossed = levels
+            .first()
+            .is_some_and(|(p, _)| market || crosses(side, *p, price));
+        if crossed && self.order.order_type == OrderType::LIMIT_MAKER {
+            self.update(State::REJECTED, time, events);
+            return false;
+        }
+
+        self.update(State::NEW, time, events);
+        if crossed && self.order.tif == Tif::GTX {
+            self.update(State::EXPIRED, time, events);
+            return false;
+        }
+
+        let eligible: Vec<_> = levels
+            .into_iter()
+            .take_while(|(p, _)| market || crosses(side, *p, price))
+            .collect();
+
+        let liquidity: f64 = eligible.iter().map(|(_, q)| q).sum();
+        if self.order.tif == Tif::FOK && liquidity < self.leaves() {
+            self.update(State::EXPIRED, time, events);
+            return false;
+        }
+
+        for (p, q) in eligible {
+            if self.done() {
+                break;
+            }
+            let quantity = q.min(self.leaves());
+            self.fill(p, quantity, false, time, events);
+        }
+
+        if self.done() {
+            return false;
+        }
+
+        if market || matches!(self.order.tif, Tif::IOC | Tif::FOK) {
+            self.update(State::EXPIRED, time, events);
+            return false;
+        }
+        true
+    }
+}
+
+// fill simulator, orders reach the exchange after `latency` milliseconds
+struct Matcher {
+    latency: i64,
+    touch: bool,
+    orders: BTreeMap<u32, SimOrder>,
+}
+
+impl Matcher {
+    fn new(latency: i64, touch: bool) -> Self {
+        Self {
+            latency,
+            touch,
+            orders: BTreeMap::new(),
+        }
+    }
+
+    fn add(&mut self, order: Order, now: i64) {
+        let arrive = now + self.latency;
+        self.orders.insert(
+            order.internal_id,
+            SimOrder {
+                order,
+                arrive,
+                cancel: None,
+                amend: None,
+                live: false,
+            },
+        );
+    }
+
+    fn cancel(&mut self, id: u32, now: i64) {
+        if let Some(order) = self.orders.get_mut(&id) {
+            order.cancel.get_or_insert(now + self.latency);
+        }
+    }
+
+    // the working order is replaced by a fresh one keeping the id
+    fn amend(&mut self, id: u32, price: f64, quantity: f64, now: i64) {
+        if let Some(order) = self.orders.get_mut(&id) {
+            order.amend = Some((now + self.latency, price, quantity));
+        }
+    }
+
+    fn on_tick(&mut self, time: i64, tick: &Tick) -> Vec<Order> {
+        let mut events = Vec::new();
+        let mut finished = Vec::new();
+
+        for (id, order) in self.orders.iter_mut() {
+            if order.order.symbol != tick.symbol() || time < order.arrive {
+                continue;
+            }
+
+            let cancelled = order.cancel.is_some_and(|cancel| time >= cancel);
+            let amended = order.amend.filter(|(arrive, _, _)| time >= *arrive);
+            if !order.live {
+                order.live = order.arrive(tick, time, &mut events);
+            } else if let (false, Some((_, price, quantity))) = (cancelled, amended) {
+                order.amend = None;
+                order.order.price = price;
+                order.order.quantity = quantity;
+                order.order.acc = 0.0;
+                order.live = order.arrive(tick, time, &mut events);
+            } else if !cancelled && tick.through(order.order.side, order.order.price, self.touch) {
+                let (price, quantity) = (order.order.price, order.leaves());
+                order.fill(price, quantity, true, time, &mut events);
+                order.live = false;
+            }
+
+            if order.live && cancelled {
+                order.update(State::CANCELED, time, &mut events);
+                order.live = false;
+            }
+
+            if !order.live {
+                finished.push(*id);
+            }
+        }
+
+        for id in finished {
+            self.orders.remove(&id);
+        }
+        events
+    }
+}
+
+#[pyclass]
+pub struct Backtest {
+    session_id: u16,
+    name: String,
+    subscription: HashMap<String, Py<Subscription>>,
+    streams: HashSet<String>,
+    orders: HashMap<u32, Py<Order>>,
+    positions: HashMap<String, xcrypto::chat::Position>,
+    fills: Vec<Fill>,
+    events: VecDeque<Pending>,
+    matcher: Matcher,
+    replay: Replay,
+    login: bool,
+    finished: bool,
+    time: i64,
+    id: u32,
+}
+
+// order updates are applied when they are handed to the strategy, so an event
+// never shows a later state than the one it reports
+enum Pending {
+    Event(Py<PyAny>),
+    Order(Order),
+}
+
+impl Backtest {
+    fn on_data(&mut self, time: i64, data: Data) {
+        let updates = match &data {
+            Data::Depth(depth) => self.matcher.on_tick(time, &Tick::Depth(depth)),
+            Data::Kline(kline) => self.matcher.on_tick(time, &Tick::Kline(kline)),
+            // prints, mark prices and liquidations are replayed to strategies but do not drive the matcher
+            Data::Trade(_) | Data::PremiumIndex(_) | Data::Liquidation(_) => Vec::new(),
+        };
+
+        match data {
+            Data::Depth(depth) if self.streams.contains(depth.stream()) => {
+                let event = Event::new(EventType::Depth, depth);
+                self.events.push_back(Pending::Event(event))
+            }
+            Data::Kline(kline) if self.streams.contains(kline.stream()) => {
+                let event = Event::new(EventType::Kline, kline);
+                self.events.push_back(Pending::Event(event))
+            }
+            Data::Trade(trade) if self.streams.contains(trade.stream()) => {
+                let event = Event::new(EventType::Trade, trade);
+                self.events.push_back(Pending::Event(event))
+            }
+            Data::PremiumIndex(index) if self.streams.contains(index.stream()) => {
+                let event = Event::new(EventType::PremiumIndex, index);
+                self.events.push_back(Pending::Event(event))
+            }
+            Data::Liquidation(liquidation) if self.streams.contains(liquidation.stream()) => {
+                let event = Event::new(EventType::Liquidation, liquidation);
+                self.events.push_back(Pending::Event(event))
+            }
+            _ => (),
+        }
+
+        self.events.extend(updates.into_iter().map(Pending::Order));
+    }
+
+    fn pop(&mut self, py: Python<'_>) -> Option<Py<PyAny>> {
+        while let Some(pending) = self.events.pop_front() {
+            let event = match pending {
+                Pending::Event(event) => Some(event),
+                Pending::Order(order) => self.on_order(py, order),
+            };
+            if event.is_some() {
+                return event;
+            }
+        }
+        None
+    }
+
+    fn on_order(&mut self, py: Python<'_>, order: Order) -> Option<Py<PyAny>> {
+        debug!("{:?}", order);
+        if order.trade_quantity > 0.0 {
+            let position = self
+                .positions
+                .entry(order.symbol.clone())
+                .or_insert_with(|| xcrypto::chat::Position::new(&order.symbol));
+            let quantity = match order.side {
+                Side::BUY => order.trade_quantity,
+                Side::SELL => -order.trade_quantity,
+            };
+            // the replay has no commission
+            position.on_fill(quantity, order.trade_price, 0.0);
+            self.fills.push(Fill::new(self.session_id, &order));
+
+            if let Some(sub) = self.subscription.get(&order.symbol) {
+                sub.borrow_mut(py).on_position(position.clone().into());
+            }
+        }
+
+        let active = order.is_active();
+        let id = order.id();
+        let pyorder = self.orders.get(&id)?.clone();
+        pyorder.borrow_mut(py).on_update(order);
+        if !active {
+            self.orders.remove(&id);
+        }
+        Some(Event::new(EventType::Order, pyorder))
+    }
+}
+
+#[pymethods]
+impl Backtest {
+    #[new]
+    #[pyo3(signature = (products, files, session_id=1, name="backtest".to_string(), latency=0, touch=false))]
+    fn new(
+        py: Python<'_>,
+        products: &str,
+        files: Vec<String>,
+        session_id: u16,
+        name: String,
+        latency: i64,
+        touch: bool,
+    ) -> PyResult<Self> {
+        let err = |e: anyhow::Error| PyException::new_err(e.to_string());
+
+        let buf = std::fs::read_to_string(products).map_err(|e| err(e.into()))?;
+        let products: Vec<Product> = serde_json::from_str(&buf).map_err(|e| err(e.into()))?;
+        let mut subscription = HashMap::default();
+        for product in products {
+            let symbol = product.symbol().clone();
+            subscription.insert(symbol, Py::new(py, Subscription::new(product))?);
+        }
+        info!("Total products {}", subscription.len());
+
+        Ok(Self {
+            session_id,
+            name,
+            subscription,
+            streams: HashSet::default(),
+            orders: HashMap::default(),
+            positions: HashMap::default(),
+            fills: Vec::new(),
+            events: VecDeque::default(),
+            matcher: Matcher::new(latency, touch),
+            replay: Replay::new(&files).map_err(err)?,
+            login: false,
+            finished: false,
+            time: 0,
+            id: 0,
+        })
+    }
+
+    #[getter]
+    fn id(&self) -> u16 {
+        self.session_id
+    }
+
+    #[getter]
+    fn name(&self) -> &String {
+        &self.name
+    }
+
+    #[getter]
+    fn is_login(&self) -> bool {
+        self.login
+    }
+
+    #[getter]
+    fn trading(&self) -> bool {
+        true
+    }
+
+    // no exchange account behind a replay
+    #[getter]
+    fn balances(&self) -> HashMap<String, Py<Balance>> {
+        HashMap::default()
+    }
+
+    #[getter]
+    fn exchange_positions(&self) -> Vec<Py<ExchangePosition>> {
+        Vec::new()
+    }
+
+    fn refresh_balances(&mut self) {}
+
+    fn get_latency(&mut self) {}
+
+    // answered from the fills of this run, after the events already queued
+    #[pyo3(signature = (symbol=None, start=None, end=None))]
+    fn get_trades(&mut self, symbol: Option<String>, start: Option<i64>, end: Option<i64>) {
+        let fills: Vec<_> = self
+            .fills
+            .iter()
+            .filter(|fill| fill.matches(symbol.as_ref(), start, end))
+            .cloned()
+            .collect();
+        let event = Event::new(EventType::Fills, fills);
+        self.events.push_back(Pending::Event(event));
+    }
+
+    #[getter]
+    fn finished(&self) -> bool {
+        self.finished
+    }
+
+    // replay clock in milliseconds
+    #[getter]
+    fn time(&self) -> i64 {
+        self.time
+    }
+
+    fn connect(&mut self) {
+        self.login = true;
+    }
+
+    fn subscribe(&mut self, symbol: &str, stream: &str) -> PyResult<Py<Subscription>> {
+        if !self.login {
+            return Err(PyException::new_err("Please login first"));
+        }
+
+        match self.subscription.get(symbol) {
+            Some(inner) => {
+                self.streams.insert(format!("{}@{}", symbol, stream));
+                Ok(inner.clone())
+            }
+            None => Err(PyException::new_err(format!("Invalid symbol {}", symbol))),
+        }
+    }
+
+    fn subscribe_all(&mut self, stream: &str) -> PyResult<()> {
+        if !self.login {
+            return Err(PyException::new_err("Please login first"));
+        }
+
+        self.streams.insert(format!("!{}@arr", stream));
+        Ok(())
+    }
+
+    fn add_order(
+        &mut self,
+        symbol: &str,
+        price: f64,
+        quantity: f64,
+        side: &Side,
+        order_type: &OrderType,
+        tif: &Tif,
+    ) -> Option<Py<Order>> {
+        if !self.login {
+            return None;
+        }
+
+        let id = self.id;
+        self.id += 1;
+
+        let mut order = Order::new(
+            id,
+            symbol,
+            price,
+            quantity,
+            side.to_owned(),
+            order_type.to_owned(),
+            tif.to_owned(),
+        );
+        order.time = self.time;
+        order.order_id = i64::from(id);
+        self.matcher.add(order.clone(), self.time);
+
+        let pyorder = Python::with_gil(|py| Py::new(py, order).ok())?;
+        self.orders.insert(id, pyorder.clone());
+        Some(pyorder)
+    }
+
+    fn cancel(&mut self, symbol: String, order_id: u32) {
+        if !self.login {
+            return;
+        }
+
+        debug!("cancel {} {}", symbol, order_id);
+        self.matcher.cancel(order_id, self.time);
+    }
+
+    fn amend(&mut self, symbol: String, order_id: u32, price: f64, quantity: f64) {
+        if !self.login {
+            return;
+        }
+
+        debug!("amend {} {} {} {}", symbol, order_id, price, quantity);
+        self.matcher.amend(order_id, price, quantity, self.time);
+    }
+
+    fn process(&mut self, py: Python<'_>) -> Option<Py<PyAny>> {
+        if let Some(event) = self.pop(py) {
+            return Some(event);
+        }
+
+        match self.replay.next() {
+            Some((time, data)) => {
+                self.time = time;
+                self.on_data(time, data);
+                self.pop(py)
+            }
+            None => {
+                if !self.finished {
+                    info!("Session {} backtest finished", self.session_id);
+                    self.finished = true;
+                }
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn depth(bids: &[(f64, f64)], asks: &[(f64, f64)]) -> Depth {
+        let quote = |q: &[(f64, f64)]| {
+            q.iter()
+                .map(|(p, v)| format!(r#"{{"price":{},"quantity":{}}}"#, p, v))
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        let s = format!(
+            r#"{{"time":0,"symbol":"btcusdt","stream":"btcusdt@depth","bids":[{}],"asks":[{}]}}"#,
+            quote(bids),
+            quote(asks)
+        );
+        serde_json::from_str(&s).unwrap()
+    }
+
+    fn order(
+        id: u32,
+        price: f64,
+        quantity: f64,
+        side: Side,
+        order_type: OrderType,
+        tif: Tif,
+    ) -> Order {
+        Order::new(id, "btcusdt", price, quantity, side, order_type, tif)
+    }
+
+    fn states(events: &[Order]) -> Vec<State> {
+        events.iter().map(|o| o.state).collect()
+    }
+
+    #[test]
+    fn test_take() {
+        let mut matcher = Matcher::new(10, false);
+        let book = depth(&[(99.0, 1.0)], &[(100.0, 1.0), (101.0, 1.0), (102.0, 1.0)]);
+
+        matcher.add(
+            order(1, 101.0, 3.0, Side::BUY, OrderType::LIMIT, Tif::IOC),
+            0,
+        );
+        // not arrived yet
+        assert!(matcher.on_tick(5, &Tick::Depth(&book)).is_empty());
+
+        let events = matcher.on_tick(10, &Tick::Depth(&book));
+        assert_eq!(
+            states(&events),
+            [
+                State::NEW,
+                State::PARTIALLY_FILLED,
+                State::PARTIALLY_FILLED,
+                State::EXPIRED
+            ]
+        );
+        assert_eq!(events[1].trade_price, 100.0);
+        assert_eq!(events[2].trade_price, 101.0);
+        assert_eq!(events[3].acc, 2.0);
+        assert!(matcher.orders.is_empty());
+
+        matcher.add(
+            order(2, 0.0, 1.5, Side::SELL, OrderType::MARKET, Tif::UNDEF),
+            10,
+        );
+        let events = matcher.on_tick(20, &Tick::Depth(&book));
+        assert_eq!(
+            states(&events),
+            [State::NEW, State::PARTIALLY_FILLED, State::EXPIRED]
+        );
+
+        matcher.add(
+            order(3, 102.0, 5.0, Side::BUY, OrderType::LIMIT, Tif::FOK),
+            20,
+        );
+        let events = matcher.on_tick(30, &Tick::Depth(&book));
+        assert_eq!(states(&events), [State::NEW, State::EXPIRED]);
+
+        matcher.add(
+            order(4, 100.0, 1.0, Side::BUY, OrderType::LIMIT_MAKER, Tif::GTC),
+            30,
+        );
+        let events = matcher.on_tick(40, &Tick::Depth(&book));
+        assert_eq!(states(&events), [State::REJECTED]);
+    }
+
+    #[test]
+    fn test_make() {
+        let mut matcher = Matcher::new(0, false);
+        let book = depth(&[(99.0, 1.0)], &[(100.0, 1.0)]);
+
+        matcher.add(
+            order(1, 99.0, 1.0, Side::BUY, OrderType::LIMIT, Tif::GTC),
+            0,
+        );
+        assert_eq!(
+            states(&matcher.on_tick(0, &Tick::Depth(&book))),
+            [State::NEW]
+        );
+
+        // touching our price is not enough without `touch`
+        assert!(matcher.on_tick(1, &Tick::Depth(&book)).is_empty());
+
+        let book = depth(&[(98.0, 1.0)], &[(99.5, 1.0)]);
+        let events = matcher.on_tick(2, &Tick::Depth(&book));
+        assert_eq!(states(&events), [State::FILLED]);
+        assert_eq!(events[0].trade_price, 99.0);
+        assert_eq!(events[0].making, Some(true));
+
+        // resting order cancelled once the request arrives
+        let mut matcher = Matcher::new(5, true);
+        matcher.add(
+            order(2, 90.0, 1.0, Side::BUY, OrderType::LIMIT, Tif::GTC),
+            0,
+        );
+        assert_eq!(
+            states(&matcher.on_tick(5, &Tick::Depth(&book))),
+            [State::NEW]
+        );
+        matcher.cancel(2, 6);
+        assert!(matcher.on_tick(10, &Tick::Depth(&book)).is_empty());
+        assert_eq!(
+            states(&matcher.on_tick(11, &Tick::Depth(&book))),
+            [State::CANCELED]
+        );
+        assert!(matcher.orders.is_empty());
+    }
+
+    #[test]
+    fn test_amend() {
+        let mut matcher = Matcher::new(5, false);
+        let book = depth(&[(99.0, 1.0)], &[(100.0, 1.0), (101.0, 1.0)]);
+        matcher.add(
+            order(1, 98.0, 1.0, Side::BUY, OrderType::LIMIT, Tif::GTC),
+            0,
+        );
+        matcher.on_tick(5, &Tick::Depth(&book));
+
+        // still resting at the new price
+        matcher.amend(1, 98.5, 2.0, 6);
+        assert!(matcher.on_tick(10, &Tick::Depth(&book)).is_empty());
+        let events = matcher.on_tick(11, &Tick::Depth(&book));
+        assert_eq!(states(&events), [State::NEW]);
+        assert_eq!(events[0].price, 98.5);
+        assert_eq!(events[0].quantity, 2.0);
+
+        // amended through the book, taking both levels
+        matcher.amend(1, 101.0, 2.0, 12);
+        let events = matcher.on_tick(17, &Tick::Depth(&book));
+        assert_eq!(
+            states(&events),
+            [State::NEW, State::PARTIALLY_FILLED, State::FILLED]
+        );
+        assert!(matcher.orders.is_empty());
+    }
+}
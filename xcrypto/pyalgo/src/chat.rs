@@ -4,7 +4,8 @@ use chrono_tz::{Asia::Shanghai, Tz};
 use pyo3::prelude::*;
 use serde::{Deserialize, Deserializer, Serialize};
 use std::collections::HashSet;
-use xcrypto::chat::{ErrorResponse, LoginResponse, Response, Success};
+use xcrypto::chat::{BatchRsp, ErrorResponse, LoginResponse, Response, Success};
+use xcrypto::timesync::ClockEstimate;
 
 #[derive(Debug, Deserialize)]
 struct Quote {
@@ -25,7 +26,7 @@ pub struct Depth {
 #[pymethods]
 impl Depth {
     #[getter]
-    fn time(&self) -> u64 {
+    pub fn time(&self) -> u64 {
         self.time
     }
 
@@ -37,47 +38,47 @@ impl Depth {
     }
 
     #[getter]
-    fn symbol(&self) -> &String {
+    pub fn symbol(&self) -> &String {
         &self.symbol
     }
 
     #[getter]
-    fn stream(&self) -> &String {
+    pub fn stream(&self) -> &String {
         &self.stream
     }
 
     #[getter]
-    fn bid_level(&self) -> usize {
+    pub fn bid_level(&self) -> usize {
         self.bids.len()
     }
 
     #[getter]
-    fn ask_level(&self) -> usize {
+    pub fn ask_level(&self) -> usize {
         self.asks.len()
     }
 
-    fn bid_prc(&self, level: usize) -> f64 {
+    pub fn bid_prc(&self, level: usize) -> f64 {
         match self.bids.get(level) {
             Some(quote) => quote.price,
             None => 0.0,
         }
     }
 
-    fn bid_vol(&self, level: usize) -> f64 {
+    pub fn bid_vol(&self, level: usize) -> f64 {
         match self.bids.get(level) {
             Some(quote) => quote.quantity,
             None => 0.0,
         }
     }
 
-    fn ask_prc(&self, level: usize) -> f64 {
+    pub fn ask_prc(&self, level: usize) -> f64 {
         match self.asks.get(level) {
             Some(quote) => quote.price,
             None => 0.0,
         }
     }
 
-    fn ask_vol(&self, level: usize) -> f64 {
+    pub fn ask_vol(&self, level: usize) -> f64 {
         match self.asks.get(level) {
             Some(quote) => quote.quantity,
             None => 0.0,
@@ -102,7 +103,7 @@ pub struct Kline {
 #[pymethods]
 impl Kline {
     #[getter]
-    fn time(&self) -> u64 {
+    pub fn time(&self) -> u64 {
         self.time
     }
 
@@ -114,32 +115,32 @@ impl Kline {
     }
 
     #[getter]
-    fn symbol(&self) -> &String {
+    pub fn symbol(&self) -> &String {
         &self.symbol
     }
 
     #[getter]
-    fn stream(&self) -> &String {
+    pub fn stream(&self) -> &String {
         &self.stream
     }
 
     #[getter]
-    fn open(&self) -> f64 {
+    pub fn open(&self) -> f64 {
         self.open
     }
 
     #[getter]
-    fn high(&self) -> f64 {
+    pub fn high(&self) -> f64 {
         self.high
     }
 
     #[getter]
-    fn low(&self) -> f64 {
+    pub fn low(&self) -> f64 {
         self.low
     }
 
     #[getter]
-    fn close(&self) -> f64 {
+    pub fn close(&self) -> f64 {
         self.close
     }
 
@@ -162,6 +163,71 @@ impl Kline {
     }
 }
 
+#[derive(Debug, Deserialize)]
+#[pyclass]
+pub struct Trade {
+    time: u64,
+    symbol: String,
+    stream: String,
+    id: i64,
+    price: f64,
+    quantity: f64,
+    side: Side,
+}
+
+#[pymethods]
+impl Trade {
+    #[getter]
+    pub fn time(&self) -> u64 {
+        self.time
+    }
+
+    #[getter]
+    fn datetime(&self) -> DateTime<Tz> {
+        DateTime::from_timestamp_millis(self.time as i64)
+            .unwrap()
+            .with_timezone(&Shanghai)
+    }
+
+    #[getter]
+    pub fn symbol(&self) -> &String {
+        &self.symbol
+    }
+
+    #[getter]
+    pub fn stream(&self) -> &String {
+        &self.stream
+    }
+
+    #[getter]
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    #[getter]
+    pub fn price(&self) -> f64 {
+        self.price
+    }
+
+    #[getter]
+    pub fn quantity(&self) -> f64 {
+        self.quantity
+    }
+
+    #[getter]
+    pub fn side(&self) -> Side {
+        self.side
+    }
+
+    fn __str__(&self) -> String {
+        format!("{:#?}", self)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:#?}", self)
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct Size {
     size: f64,
@@ -244,17 +310,39 @@ pub struct PositionRsp {
     pub positions: Vec<Position>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct BalanceRsp {
+    pub balances: Vec<Balance>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TradeRsp {
+    pub session_id: u16,
+    pub trades: Vec<Fill>,
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(untagged)]
 pub enum Message {
+    // before Success, a trade carries an id as well
+    Trade(Trade),
     Success(Success),
     Login(LoginResponse),
     Error(ErrorResponse),
     Depth(Depth),
     Kline(Kline),
+    PremiumIndex(PremiumIndex),
+    Liquidation(Liquidation),
+    Balance(Balance),
+    ExchangePosition(ExchangePosition),
+    RateLimit(RateLimit),
     Order(Order),
     Products(Products),
     Positions(Response<PositionRsp>),
+    Balances(Response<BalanceRsp>),
+    Trades(Response<TradeRsp>),
+    Latency(Response<Latency>),
+    Batch(Response<BatchRsp>),
     Position(Position),
     Close,
 }
@@ -265,8 +353,16 @@ pub enum EventType {
     Login,
     Depth,
     Kline,
+    Trade,
+    PremiumIndex,
+    Liquidation,
+    Balance,
+    ExchangePosition,
     Order,
     Position,
+    Fills,
+    RateLimit,
+    Latency,
 }
 
 #[derive(Debug)]
@@ -308,25 +404,25 @@ impl Event {
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 #[pyclass]
 pub struct Order {
-    time: i64,
-    symbol: String,
-    side: Side,
-    state: State,
-    order_type: OrderType,
-    tif: Tif,
-    quantity: f64,
-    price: f64,
+    pub(crate) time: i64,
+    pub(crate) symbol: String,
+    pub(crate) side: Side,
+    pub(crate) state: State,
+    pub(crate) order_type: OrderType,
+    pub(crate) tif: Tif,
+    pub(crate) quantity: f64,
+    pub(crate) price: f64,
     #[allow(unused)]
-    order_id: i64,
-    internal_id: u32,
-    trade_time: i64,
-    trade_price: f64,
-    trade_quantity: f64,
-    acc: f64,
-    making: Option<bool>,
+    pub(crate) order_id: i64,
+    pub(crate) internal_id: u32,
+    pub(crate) trade_time: i64,
+    pub(crate) trade_price: f64,
+    pub(crate) trade_quantity: f64,
+    pub(crate) acc: f64,
+    pub(crate) making: Option<bool>,
 }
 
 impl Order {
@@ -471,37 +567,566 @@ pub struct CancelRequest {
     pub order_id: u32,
 }
 
+#[derive(Debug, Serialize)]
+pub struct AmendRequest {
+    pub symbol: String,
+    pub session_id: u16,
+    pub order_id: u32,
+    pub price: f64,
+    pub quantity: f64,
+}
+
 #[derive(Debug, Deserialize, Clone)]
 #[pyclass]
 pub struct Position {
     pub symbol: String,
     pub net: f64,
+    #[serde(default)]
+    pub avg_price: f64,
+    #[serde(default)]
+    pub realized_pnl: f64,
+    #[serde(default)]
+    pub fee: f64,
+}
+
+impl From<xcrypto::chat::Position> for Position {
+    fn from(value: xcrypto::chat::Position) -> Self {
+        Self {
+            symbol: value.symbol,
+            net: value.net,
+            avg_price: value.avg_price,
+            realized_pnl: value.realized_pnl,
+            fee: value.fee,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
 #[pyclass]
-#[allow(non_snake_case)]
+pub struct Liquidation {
+    time: i64,
+    symbol: String,
+    stream: String,
+    side: Side,
+    state: State,
+    price: f64,
+    avg_price: f64,
+    quantity: f64,
+    filled: f64,
+}
+
+#[pymethods]
+impl Liquidation {
+    #[getter]
+    pub fn time(&self) -> i64 {
+        self.time
+    }
+
+    #[getter]
+    fn datetime(&self) -> DateTime<Tz> {
+        DateTime::from_timestamp_millis(self.time)
+            .unwrap()
+            .with_timezone(&Shanghai)
+    }
+
+    #[getter]
+    pub fn symbol(&self) -> &String {
+        &self.symbol
+    }
+
+    #[getter]
+    pub fn stream(&self) -> &String {
+        &self.stream
+    }
+
+    #[getter]
+    pub fn side(&self) -> Side {
+        self.side
+    }
+
+    #[getter]
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    #[getter]
+    pub fn price(&self) -> f64 {
+        self.price
+    }
+
+    #[getter]
+    pub fn avg_price(&self) -> f64 {
+        self.avg_price
+    }
+
+    #[getter]
+    pub fn quantity(&self) -> f64 {
+        self.quantity
+    }
+
+    #[getter]
+    pub fn filled(&self) -> f64 {
+        self.filled
+    }
+
+    fn __str__(&self) -> String {
+        format!("{:#?}", self)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:#?}", self)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[pyclass]
+pub struct Balance {
+    time: i64,
+    asset: String,
+    wallet: f64,
+    free: f64,
+    locked: f64,
+    cross_wallet: f64,
+    change: f64,
+}
+
+#[pymethods]
+impl Balance {
+    #[getter]
+    fn time(&self) -> i64 {
+        self.time
+    }
+
+    #[getter]
+    fn datetime(&self) -> DateTime<Tz> {
+        DateTime::from_timestamp_millis(self.time)
+            .unwrap()
+            .with_timezone(&Shanghai)
+    }
+
+    #[getter]
+    pub fn asset(&self) -> &String {
+        &self.asset
+    }
+
+    #[getter]
+    fn wallet(&self) -> f64 {
+        self.wallet
+    }
+
+    #[getter]
+    fn free(&self) -> f64 {
+        self.free
+    }
+
+    #[getter]
+    fn locked(&self) -> f64 {
+        self.locked
+    }
+
+    #[getter]
+    fn cross_wallet(&self) -> f64 {
+        self.cross_wallet
+    }
+
+    #[getter]
+    fn change(&self) -> f64 {
+        self.change
+    }
+
+    fn __str__(&self) -> String {
+        format!("{:#?}", self)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:#?}", self)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[pyclass]
+pub struct ExchangePosition {
+    time: i64,
+    symbol: String,
+    position_side: String,
+    amount: f64,
+    entry_price: f64,
+    break_even_price: f64,
+    unrealized_pnl: f64,
+    realized_pnl: f64,
+    margin_type: String,
+    isolated_wallet: f64,
+}
+
+#[pymethods]
+impl ExchangePosition {
+    #[getter]
+    fn time(&self) -> i64 {
+        self.time
+    }
+
+    #[getter]
+    fn datetime(&self) -> DateTime<Tz> {
+        DateTime::from_timestamp_millis(self.time)
+            .unwrap()
+            .with_timezone(&Shanghai)
+    }
+
+    #[getter]
+    pub fn symbol(&self) -> &String {
+        &self.symbol
+    }
+
+    #[getter]
+    pub fn position_side(&self) -> &String {
+        &self.position_side
+    }
+
+    #[getter]
+    fn amount(&self) -> f64 {
+        self.amount
+    }
+
+    #[getter]
+    fn entry_price(&self) -> f64 {
+        self.entry_price
+    }
+
+    #[getter]
+    fn break_even_price(&self) -> f64 {
+        self.break_even_price
+    }
+
+    #[getter]
+    fn unrealized_pnl(&self) -> f64 {
+        self.unrealized_pnl
+    }
+
+    #[getter]
+    fn realized_pnl(&self) -> f64 {
+        self.realized_pnl
+    }
+
+    #[getter]
+    fn margin_type(&self) -> &String {
+        &self.margin_type
+    }
+
+    #[getter]
+    fn isolated_wallet(&self) -> f64 {
+        self.isolated_wallet
+    }
+
+    fn __str__(&self) -> String {
+        format!("{:#?}", self)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:#?}", self)
+    }
+}
+
+// a limit of the exchange account getting close, reset is when its window ends in milliseconds
+#[derive(Debug, Deserialize)]
+#[pyclass]
+pub struct RateLimit {
+    rate_limit_type: String,
+    interval: String,
+    used: u64,
+    limit: u64,
+    reset: i64,
+}
+
+#[pymethods]
+impl RateLimit {
+    #[getter]
+    fn rate_limit_type(&self) -> &String {
+        &self.rate_limit_type
+    }
+
+    #[getter]
+    fn interval(&self) -> &String {
+        &self.interval
+    }
+
+    #[getter]
+    fn used(&self) -> u64 {
+        self.used
+    }
+
+    #[getter]
+    fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    #[getter]
+    fn reset(&self) -> i64 {
+        self.reset
+    }
+
+    fn __str__(&self) -> String {
+        format!("{:#?}", self)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:#?}", self)
+    }
+}
+
+// response times of one rest endpoint or websocket api method in microseconds, buckets count
+// the requests answered within each of bounds and the last one those slower than every bound
+#[derive(Debug, Clone, Deserialize)]
+#[pyclass]
+pub struct EndpointLatency {
+    endpoint: String,
+    count: u64,
+    errors: u64,
+    mean: u64,
+    min: u64,
+    max: u64,
+    p50: u64,
+    p90: u64,
+    p99: u64,
+    bounds: Vec<u64>,
+    buckets: Vec<u64>,
+}
+
+#[pymethods]
+impl EndpointLatency {
+    #[getter]
+    fn endpoint(&self) -> &String {
+        &self.endpoint
+    }
+
+    #[getter]
+    fn count(&self) -> u64 {
+        self.count
+    }
+
+    #[getter]
+    fn errors(&self) -> u64 {
+        self.errors
+    }
+
+    #[getter]
+    fn mean(&self) -> u64 {
+        self.mean
+    }
+
+    #[getter]
+    fn min(&self) -> u64 {
+        self.min
+    }
+
+    #[getter]
+    fn max(&self) -> u64 {
+        self.max
+    }
+
+    #[getter]
+    fn p50(&self) -> u64 {
+        self.p50
+    }
+
+    #[getter]
+    fn p90(&self) -> u64 {
+        self.p90
+    }
+
+    #[getter]
+    fn p99(&self) -> u64 {
+        self.p99
+    }
+
+    #[getter]
+    fn bounds(&self) -> Vec<u64> {
+        self.bounds.clone()
+    }
+
+    #[getter]
+    fn buckets(&self) -> Vec<u64> {
+        self.buckets.clone()
+    }
+
+    fn __str__(&self) -> String {
+        format!("{:#?}", self)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:#?}", self)
+    }
+}
+
+// the answer of get_latency, offset and rtt of the exchange clock are None until it is sampled
+#[derive(Debug, Deserialize)]
+#[pyclass]
+pub struct Latency {
+    clock: Option<ClockEstimate>,
+    endpoints: Vec<EndpointLatency>,
+}
+
+#[pymethods]
+impl Latency {
+    #[getter]
+    fn offset(&self) -> Option<i64> {
+        self.clock.map(|clock| clock.offset)
+    }
+
+    #[getter]
+    fn rtt(&self) -> Option<i64> {
+        self.clock.map(|clock| clock.rtt)
+    }
+
+    #[getter]
+    fn endpoints(&self) -> Vec<EndpointLatency> {
+        self.endpoints.clone()
+    }
+
+    fn __str__(&self) -> String {
+        format!("{:#?}", self)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:#?}", self)
+    }
+}
+
+// an execution of an order of the session, as journaled by the trade server
+#[derive(Debug, Clone, Deserialize)]
+#[pyclass]
+pub struct Fill {
+    session_id: u16,
+    symbol: String,
+    side: Side,
+    order_id: i64,
+    internal_id: u32,
+    price: f64,
+    quantity: f64,
+    commission: f64,
+    making: bool,
+    trade_time: i64,
+}
+
+impl Fill {
+    pub fn new(session_id: u16, order: &Order) -> Self {
+        Self {
+            session_id,
+            symbol: order.symbol.clone(),
+            side: order.side,
+            order_id: order.order_id,
+            internal_id: order.internal_id,
+            price: order.trade_price,
+            quantity: order.trade_quantity,
+            commission: 0.0,
+            making: order.making.unwrap_or_default(),
+            trade_time: order.trade_time,
+        }
+    }
+
+    pub fn matches(&self, symbol: Option<&String>, start: Option<i64>, end: Option<i64>) -> bool {
+        symbol.is_none_or(|symbol| self.symbol.eq_ignore_ascii_case(symbol))
+            && start.is_none_or(|start| self.trade_time >= start)
+            && end.is_none_or(|end| self.trade_time <= end)
+    }
+}
+
+#[pymethods]
+impl Fill {
+    #[getter]
+    fn session_id(&self) -> u16 {
+        self.session_id
+    }
+
+    #[getter]
+    fn symbol(&self) -> &String {
+        &self.symbol
+    }
+
+    #[getter]
+    fn side(&self) -> Side {
+        self.side
+    }
+
+    // given by the exchange
+    #[getter]
+    fn order_id(&self) -> i64 {
+        self.order_id
+    }
+
+    // the id of the Order, as cancel and amend take it
+    #[getter]
+    fn id(&self) -> u32 {
+        self.internal_id
+    }
+
+    #[getter]
+    fn price(&self) -> f64 {
+        self.price
+    }
+
+    #[getter]
+    fn quantity(&self) -> f64 {
+        self.quantity
+    }
+
+    #[getter]
+    fn commission(&self) -> f64 {
+        self.commission
+    }
+
+    #[getter]
+    fn making(&self) -> bool {
+        self.making
+    }
+
+    #[getter]
+    fn trade_time(&self) -> i64 {
+        self.trade_time
+    }
+
+    #[getter]
+    fn datetime(&self) -> DateTime<Tz> {
+        DateTime::from_timestamp_millis(self.trade_time)
+            .unwrap()
+            .with_timezone(&Shanghai)
+    }
+
+    fn __str__(&self) -> String {
+        format!("{:#?}", self)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:#?}", self)
+    }
+}
+
+// both the rest premium index and the markPrice stream, the stream has no interest rate
+#[derive(Debug, Deserialize)]
+#[pyclass]
 pub struct PremiumIndex {
     #[serde(deserialize_with = "deserialize_symbol")]
     symbol: String,
-    #[serde(deserialize_with = "string_to_f64")]
-    markPrice: f64,
-    #[serde(deserialize_with = "string_to_f64")]
-    indexPrice: f64,
-    #[serde(deserialize_with = "string_to_f64")]
-    estimatedSettlePrice: f64,
-    #[serde(deserialize_with = "string_to_f64")]
-    lastFundingRate: f64,
-    nextFundingTime: i64,
-    #[serde(deserialize_with = "string_to_f64")]
-    interestRate: f64,
+    #[serde(default)]
+    stream: String,
+    #[serde(alias = "markPrice", deserialize_with = "string_to_f64")]
+    mark_price: f64,
+    #[serde(alias = "indexPrice", deserialize_with = "string_to_f64")]
+    index_price: f64,
+    #[serde(alias = "estimatedSettlePrice", deserialize_with = "string_to_f64")]
+    estimated_settle_price: f64,
+    #[serde(alias = "lastFundingRate", deserialize_with = "string_to_f64")]
+    last_funding_rate: f64,
+    #[serde(alias = "nextFundingTime")]
+    next_funding_time: i64,
+    #[serde(default, alias = "interestRate", deserialize_with = "string_to_f64")]
+    interest_rate: f64,
     time: i64,
 }
 
 #[pymethods]
 impl PremiumIndex {
     #[getter]
-    fn time(&self) -> i64 {
+    pub fn time(&self) -> i64 {
         self.time
     }
     #[getter]
@@ -515,34 +1140,38 @@ impl PremiumIndex {
         &self.symbol
     }
     #[getter]
+    pub fn stream(&self) -> &String {
+        &self.stream
+    }
+    #[getter]
     fn mark_price(&self) -> f64 {
-        self.markPrice
+        self.mark_price
     }
     #[getter]
     fn index_price(&self) -> f64 {
-        self.indexPrice
+        self.index_price
     }
     #[getter]
     fn estimated_settle_price(&self) -> f64 {
-        self.estimatedSettlePrice
+        self.estimated_settle_price
     }
     #[getter]
     fn last_funding_rate(&self) -> f64 {
-        self.lastFundingRate
+        self.last_funding_rate
     }
     #[getter]
     fn next_funding_time(&self) -> i64 {
-        self.nextFundingTime
+        self.next_funding_time
     }
     #[getter]
     fn next_funding_dt(&self) -> DateTime<Tz> {
-        DateTime::from_timestamp_millis(self.nextFundingTime as i64)
+        DateTime::from_timestamp_millis(self.next_funding_time)
             .unwrap()
             .with_timezone(&Shanghai)
     }
     #[getter]
     fn interest_rate(&self) -> f64 {
-        self.interestRate
+        self.interest_rate
     }
     fn __repr__(&self) -> String {
         format!("{:?}", self)
@@ -553,12 +1182,22 @@ impl PremiumIndex {
     }
 }
 
+// rest sends numbers as strings
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Number {
+    Float(f64),
+    Text(String),
+}
+
 fn string_to_f64<'de, D>(deserializer: D) -> Result<f64, D::Error>
 where
     D: Deserializer<'de>,
 {
-    let s: String = Deserialize::deserialize(deserializer)?;
-    Ok(s.parse::<f64>().unwrap())
+    match Number::deserialize(deserializer)? {
+        Number::Float(f) => Ok(f),
+        Number::Text(s) => s.parse::<f64>().map_err(serde::de::Error::custom),
+    }
 }
 
 fn deserialize_symbol<'de, D>(deserializer: D) -> Result<String, D::Error>
@@ -667,6 +1306,213 @@ mod tests {
         assert_eq!(kline.datetime().to_string(), "2024-05-08 00:14:55.999 CST");
     }
 
+    #[test]
+    fn test_trade() {
+        let s = r#"{"time":1715098495999,
+                          "symbol":"btcusdt",
+                          "stream":"btcusdt@aggTrade",
+                          "id":3012554712,
+                          "price":63763.07,
+                          "quantity":0.03,
+                          "side":"SELL"}"#;
+
+        match serde_json::from_str::<Message>(s).unwrap() {
+            Message::Trade(trade) => {
+                assert_eq!(trade.stream(), "btcusdt@aggTrade");
+                assert_eq!(trade.id(), 3012554712);
+                assert_eq!(trade.price(), 63763.07);
+                assert_eq!(trade.quantity(), 0.03);
+                assert_eq!(trade.side(), Side::SELL);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_premium_index() {
+        let s = r#"{"time":1562305380000,
+                          "symbol":"btcusdt",
+                          "stream":"btcusdt@markPrice:1s",
+                          "mark_price":11794.15,
+                          "index_price":11784.62659091,
+                          "estimated_settle_price":11784.25641265,
+                          "last_funding_rate":0.00038167,
+                          "next_funding_time":1562306400000}"#;
+        match serde_json::from_str::<Message>(s).unwrap() {
+            Message::PremiumIndex(index) => {
+                assert_eq!(index.stream(), "btcusdt@markPrice:1s");
+                assert_eq!(index.mark_price(), 11794.15);
+                assert_eq!(index.last_funding_rate(), 0.00038167);
+                assert_eq!(index.interest_rate(), 0.0);
+            }
+            other => panic!("{:?}", other),
+        }
+
+        // rest
+        let s = r#"{"symbol":"BTCUSDT",
+                          "markPrice":"11793.63104562",
+                          "indexPrice":"11781.80495970",
+                          "estimatedSettlePrice":"11781.16138815",
+                          "lastFundingRate":"0.00038246",
+                          "interestRate":"0.00010000",
+                          "nextFundingTime":1597392000000,
+                          "time":1597370495002}"#;
+        let index: PremiumIndex = serde_json::from_str(s).unwrap();
+        assert_eq!(index.symbol(), "btcusdt");
+        assert_eq!(index.stream(), "");
+        assert_eq!(index.mark_price(), 11793.63104562);
+        assert_eq!(index.interest_rate(), 0.0001);
+        assert_eq!(index.next_funding_time(), 1597392000000);
+    }
+
+    #[test]
+    fn test_liquidation() {
+        let s = r#"{"time":1568014460893,
+                          "symbol":"btcusdt",
+                          "stream":"!forceOrder@arr",
+                          "side":"SELL",
+                          "state":"FILLED",
+                          "price":9910.0,
+                          "avg_price":9910.0,
+                          "quantity":0.014,
+                          "filled":0.014}"#;
+        match serde_json::from_str::<Message>(s).unwrap() {
+            Message::Liquidation(liquidation) => {
+                assert_eq!(liquidation.stream(), "!forceOrder@arr");
+                assert_eq!(liquidation.symbol(), "btcusdt");
+                assert_eq!(liquidation.side(), Side::SELL);
+                assert_eq!(liquidation.state(), State::FILLED);
+                assert_eq!(liquidation.filled(), 0.014);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_account() {
+        let s = r#"{"time":1564745798939,
+                          "asset":"USDT",
+                          "wallet":122624.12345678,
+                          "free":0.0,
+                          "locked":0.0,
+                          "cross_wallet":100.12345678,
+                          "change":50.12345678}"#;
+        match serde_json::from_str::<Message>(s).unwrap() {
+            Message::Balance(balance) => {
+                assert_eq!(balance.asset(), "USDT");
+                assert_eq!(balance.wallet(), 122624.12345678);
+            }
+            other => panic!("{:?}", other),
+        }
+
+        let s = r#"{"time":1564745798939,
+                          "symbol":"btcusdt",
+                          "position_side":"BOTH",
+                          "amount":-0.5,
+                          "entry_price":60000.0,
+                          "break_even_price":60012.0,
+                          "unrealized_pnl":-25.0,
+                          "realized_pnl":0.0,
+                          "margin_type":"cross",
+                          "isolated_wallet":0.0}"#;
+        match serde_json::from_str::<Message>(s).unwrap() {
+            Message::ExchangePosition(position) => {
+                assert_eq!(position.symbol(), "btcusdt");
+                assert_eq!(position.amount(), -0.5);
+                assert_eq!(position.unrealized_pnl(), -25.0);
+                assert_eq!(position.margin_type(), "cross");
+            }
+            other => panic!("{:?}", other),
+        }
+
+        let s = r#"{"rate_limit_type":"ORDERS",
+                    "interval":"10S",
+                    "used":85,
+                    "limit":100,
+                    "reset":1564745800000}"#;
+        match serde_json::from_str::<Message>(s).unwrap() {
+            Message::RateLimit(usage) => {
+                assert_eq!(usage.rate_limit_type(), "ORDERS");
+                assert_eq!(usage.used(), 85);
+            }
+            other => panic!("{:?}", other),
+        }
+
+        let s = r#"{"id":4,
+                    "result":{"clock":{"offset":-12,"rtt":3,"time":1564034571105},
+                              "endpoints":[{"endpoint":"POST /api/v3/order",
+                                            "count":2,
+                                            "errors":0,
+                                            "mean":1500,
+                                            "min":1000,
+                                            "max":2000,
+                                            "p50":1000,
+                                            "p90":2000,
+                                            "p99":2000,
+                                            "bounds":[1000,2000],
+                                            "buckets":[1,1,0]}]}}"#;
+        match serde_json::from_str::<Message>(s).unwrap() {
+            Message::Latency(rsp) => {
+                assert_eq!(rsp.result.offset(), Some(-12));
+                assert_eq!(rsp.result.endpoints()[0].p90(), 2000);
+            }
+            other => panic!("{:?}", other),
+        }
+
+        // spot balances answering get_balances
+        let s = r#"{"id":3,
+                    "result":{"balances":[{"time":1564034571105,
+                                           "asset":"BTC",
+                                           "wallet":1.5,
+                                           "free":1.0,
+                                           "locked":0.5,
+                                           "cross_wallet":0.0,
+                                           "change":0.0}]}}"#;
+        match serde_json::from_str::<Message>(s).unwrap() {
+            Message::Balances(rsp) => {
+                let balance = &rsp.result.balances[0];
+                assert_eq!(balance.asset(), "BTC");
+                assert_eq!(balance.free(), 1.0);
+                assert_eq!(balance.locked(), 0.5);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_trades() {
+        let s = r#"{"id":4,
+                    "result":{"session_id":1,
+                              "trades":[{"session_id":1,
+                                         "symbol":"btcusdt",
+                                         "side":"SELL",
+                                         "order_id":28457,
+                                         "internal_id":7,
+                                         "price":60000.0,
+                                         "quantity":0.002,
+                                         "commission":0.048,
+                                         "making":true,
+                                         "trade_time":1564034571105}]}}"#;
+        match serde_json::from_str::<Message>(s).unwrap() {
+            Message::Trades(rsp) => {
+                let fill = &rsp.result.trades[0];
+                assert_eq!(fill.id(), 7);
+                assert_eq!(fill.quantity(), 0.002);
+                assert!(fill.making());
+                assert!(fill.matches(Some(&"BTCUSDT".into()), Some(1564034571105), None));
+                assert!(!fill.matches(None, None, Some(1564034571104)));
+            }
+            other => panic!("{:?}", other),
+        }
+
+        // nothing filled
+        let s = r#"{"id":5,"result":{"session_id":1,"trades":[]}}"#;
+        assert!(matches!(
+            serde_json::from_str::<Message>(s).unwrap(),
+            Message::Trades(_)
+        ));
+    }
+
     #[test]
     fn test_product() {
         // spot
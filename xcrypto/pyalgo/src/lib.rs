@@ -1,3 +1,4 @@
+pub mod backtest;
 pub mod chat;
 pub mod constant;
 pub mod phase;
@@ -6,6 +7,7 @@ pub mod session;
 pub mod subscription;
 pub mod ws;
 
+use backtest::Backtest;
 use chat::*;
 use constant::*;
 use log::Record;
@@ -81,9 +83,19 @@ fn log_error(filename: String, lineno: u32, msg: String) {
 fn pyalgo(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<Kline>()?;
     m.add_class::<Depth>()?;
+    m.add_class::<Trade>()?;
+    m.add_class::<PremiumIndex>()?;
+    m.add_class::<Liquidation>()?;
+    m.add_class::<Balance>()?;
+    m.add_class::<ExchangePosition>()?;
+    m.add_class::<RateLimit>()?;
+    m.add_class::<EndpointLatency>()?;
+    m.add_class::<Latency>()?;
+    m.add_class::<Fill>()?;
     m.add_class::<Order>()?;
     m.add_class::<Rest>()?;
     m.add_class::<Session>()?;
+    m.add_class::<Backtest>()?;
     m.add_class::<TradingPhase>()?;
     m.add_class::<Phase>()?;
     m.add_class::<Side>()?;
@@ -1,14 +1,12 @@
-use base64::prelude::BASE64_STANDARD;
-use base64::Engine;
 use log::debug;
-use openssl::pkey::{PKey, Private};
-use openssl::sign::Signer;
+use pyo3::exceptions::PyException;
 use pyo3::prelude::*;
 use pyo3::types::PyDict;
 use reqwest::{blocking, Method};
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::Read;
+use std::sync::Mutex;
+use xcrypto::rest::{timestamp, SignMethod, SigningKey};
+use xcrypto::timesync::TimeSync;
 
 use crate::PremiumIndex;
 
@@ -16,8 +14,11 @@ use crate::PremiumIndex;
 pub struct Rest {
     base_uri: String,
     apikey: String,
-    private_key: PKey<Private>,
+    key: SigningKey,
     recvwindow: i64,
+    time_sync: Mutex<TimeSync>,
+    // keeps its connections alive between requests
+    client: blocking::Client,
 }
 
 impl Rest {
@@ -29,10 +30,7 @@ impl Rest {
         authenticate: bool,
     ) -> String {
         if authenticate {
-            let ts = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_millis();
+            let ts = timestamp().unwrap() as i64 + self.time_sync.lock().unwrap().offset();
 
             params.insert("timestamp".into(), ts.to_string());
             if self.recvwindow > 0 {
@@ -40,7 +38,8 @@ impl Rest {
             }
         }
 
-        let mut builder = blocking::Client::new()
+        let mut builder = self
+            .client
             .request(
                 method,
                 format!("{}/{}", self.base_uri, path.trim_start_matches("/")),
@@ -65,24 +64,57 @@ impl Rest {
 
 #[pymethods]
 impl Rest {
+    // sign is ed25519, rsa or hmac, pem holds the secret key of hmac
     #[new]
-    pub fn new(base_uri: &str, apikey: &str, pem: &str, recvwindow: i64) -> Self {
-        let mut buf = Vec::new();
-        File::open(pem).unwrap().read_to_end(&mut buf).unwrap();
-        let private_key = PKey::private_key_from_pem(&buf).unwrap();
-
-        Self {
+    #[pyo3(signature = (base_uri, apikey, pem, recvwindow, sign="ed25519"))]
+    pub fn new(
+        base_uri: &str,
+        apikey: &str,
+        pem: &str,
+        recvwindow: i64,
+        sign: &str,
+    ) -> PyResult<Self> {
+        let err = |e: anyhow::Error| PyException::new_err(e.to_string());
+        let method = sign.parse::<SignMethod>().map_err(err)?;
+
+        Ok(Self {
             base_uri: base_uri.trim_end_matches("/").into(),
             apikey: apikey.into(),
-            private_key,
+            key: SigningKey::load(pem, method).map_err(err)?,
             recvwindow,
-        }
+            time_sync: Mutex::default(),
+            client: blocking::Client::new(),
+        })
     }
 
     pub fn sign(&self, data: &str) -> String {
-        let mut signer = Signer::new_without_digest(&self.private_key).unwrap();
-        let signature = signer.sign_oneshot_to_vec(data.as_bytes());
-        BASE64_STANDARD.encode(signature.unwrap())
+        self.key.sign(data.as_bytes()).unwrap()
+    }
+
+    // sample the server time of /api/v3/time or /fapi/v1/time, signed requests are stamped
+    // with it from then on, returns the (offset, rtt) estimate in milliseconds
+    pub fn sync_time(&self, path: &str) -> PyResult<(i64, i64)> {
+        let err = |e: anyhow::Error| PyException::new_err(e.to_string());
+        let sent = timestamp().map_err(err)? as i64;
+        let rsp = self.send(Method::GET, path, HashMap::new(), false);
+        let received = timestamp().map_err(err)? as i64;
+        let server = serde_json::from_str::<serde_json::Value>(&rsp)
+            .ok()
+            .and_then(|rsp| rsp["serverTime"].as_i64());
+        let server = match server {
+            Some(server) => server,
+            None => {
+                return Err(PyException::new_err(format!(
+                    "Failed to get server time {}",
+                    rsp
+                )))
+            }
+        };
+
+        let mut time_sync = self.time_sync.lock().unwrap();
+        time_sync.add(sent, server, received);
+        let estimate = time_sync.estimate().unwrap_or_default();
+        Ok((estimate.offset, estimate.rtt))
     }
 
     pub fn get(
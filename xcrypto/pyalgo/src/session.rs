@@ -1,8 +1,8 @@
-use crate::chat::{CancelRequest, Message, OrderRequest, Product};
+use crate::chat::{AmendRequest, CancelRequest, Message, OrderRequest, Product};
 use crate::subscription::Subscription;
 use crate::ws::WebSocketClient;
-use crate::{constant::*, Order, PositionRsp};
-use crate::{Event, Position};
+use crate::{constant::*, BalanceRsp, Order, PositionRsp, TradeRsp};
+use crate::{Balance, Event, ExchangePosition, Position};
 use log::*;
 use pyo3::prelude::*;
 use serde::Serialize;
@@ -10,7 +10,10 @@ use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 use std::time::{Duration, Instant};
 use std::vec;
-use xcrypto::chat::{Error, Login, LoginResponse, PositionReq, Request, Response};
+use xcrypto::chat::{
+    BatchRsp, Error, Login, LoginResponse, PositionReq, Request, Response, TradeReq,
+};
+use xcrypto::error::*;
 
 #[pyclass]
 pub struct Session {
@@ -19,6 +22,10 @@ pub struct Session {
     name: String,
     subscription: HashMap<String, Py<Subscription>>,
     orders: HashMap<u32, Py<Order>>,
+    // asset -> last balance
+    balances: HashMap<String, Py<Balance>>,
+    // (symbol, position side) -> last position reported by the exchange
+    exchange_positions: HashMap<(String, String), Py<ExchangePosition>>,
     symbols: HashSet<String>,
     login: bool,
     trading: bool,
@@ -42,6 +49,9 @@ impl Session {
     fn on_login(&mut self, login: LoginResponse) -> Option<Py<PyAny>> {
         info!("{:?}", login);
         self.login = true;
+        if self.trading {
+            self.refresh_balances();
+        }
 
         Some(Event::new(crate::EventType::Login, self.login))
     }
@@ -98,7 +108,26 @@ impl Session {
     }
 
     fn on_error(&mut self, response: Response<Error>) {
-        panic!("{:?}", response);
+        match response.result.code {
+            // the order is rejected by a REJECTED update right after
+            MAX_NOTIONAL | MAX_POSITION | MAX_OPEN_ORDERS | PRICE_BAND | NO_BBO => {
+                error!("{:?}", response)
+            }
+            // the amended order keeps working as it is, or comes CANCELED if it is gone
+            UNKNOWN_ORDER | AMEND_UNSUPPORTED | AMEND_FAILED => error!("{:?}", response),
+            // an order is rejected like above, an amend leaves it as it is
+            RATE_LIMIT => warn!("{:?}", response),
+            _ => panic!("{:?}", response),
+        }
+    }
+
+    // failed orders also come as REJECTED updates, failed cancels leave the order as it is
+    fn on_batch(&mut self, rsp: Response<BatchRsp>) {
+        for result in rsp.result.results {
+            if result.code != 0 {
+                error!("Batch {} {:?}", rsp.id, result);
+            }
+        }
     }
 
     fn on_order(&mut self, order: Order) -> Option<Py<PyAny>> {
@@ -133,6 +162,46 @@ impl Session {
         }
     }
 
+    fn on_balance(&mut self, balance: Balance) -> Option<Py<PyAny>> {
+        info!("{:?}", balance);
+        let asset = balance.asset().clone();
+        let balance = Python::with_gil(|py| Py::new(py, balance)).ok()?;
+        self.balances.insert(asset, balance.clone());
+        Some(Event::new(crate::EventType::Balance, balance))
+    }
+
+    // the whole account, pushed updates follow as single balances
+    fn on_balances(&mut self, rsp: Response<BalanceRsp>) {
+        for balance in rsp.result.balances {
+            info!("{:?}", balance);
+            let asset = balance.asset().clone();
+            match Python::with_gil(|py| Py::new(py, balance)) {
+                Ok(balance) => {
+                    self.balances.insert(asset, balance);
+                }
+                Err(e) => error!("{}", e),
+            }
+        }
+    }
+
+    fn on_trades(&mut self, rsp: Response<TradeRsp>) -> Option<Py<PyAny>> {
+        let trades = rsp.result.trades;
+        info!(
+            "Session {} has {} fills",
+            rsp.result.session_id,
+            trades.len()
+        );
+        Some(Event::new(crate::EventType::Fills, trades))
+    }
+
+    fn on_exchange_position(&mut self, position: ExchangePosition) -> Option<Py<PyAny>> {
+        info!("{:?}", position);
+        let key = (position.symbol().clone(), position.position_side().clone());
+        let position = Python::with_gil(|py| Py::new(py, position)).ok()?;
+        self.exchange_positions.insert(key, position.clone());
+        Some(Event::new(crate::EventType::ExchangePosition, position))
+    }
+
     fn on_close(&mut self) {
         info!("Session {} is closed", self.id);
     }
@@ -152,8 +221,27 @@ impl Session {
                     error!("{}", e);
                 }
             }
+            Message::Balances(rsp) => self.on_balances(rsp),
+            Message::Trades(rsp) => return self.on_trades(rsp),
+            Message::Latency(rsp) => {
+                return Some(Event::new(crate::EventType::Latency, rsp.result))
+            }
+            Message::Batch(rsp) => self.on_batch(rsp),
             Message::Kline(kline) => return Some(Event::new(crate::EventType::Kline, kline)),
             Message::Depth(depth) => return Some(Event::new(crate::EventType::Depth, depth)),
+            Message::Trade(trade) => return Some(Event::new(crate::EventType::Trade, trade)),
+            Message::PremiumIndex(index) => {
+                return Some(Event::new(crate::EventType::PremiumIndex, index))
+            }
+            Message::Liquidation(liquidation) => {
+                return Some(Event::new(crate::EventType::Liquidation, liquidation))
+            }
+            Message::Balance(balance) => return self.on_balance(balance),
+            Message::ExchangePosition(position) => return self.on_exchange_position(position),
+            Message::RateLimit(usage) => {
+                warn!("{:?}", usage);
+                return Some(Event::new(crate::EventType::RateLimit, usage));
+            }
             Message::Order(order) => return self.on_order(order),
             Message::Position(position) => self.on_position(position),
             Message::Close => self.on_close(),
@@ -186,6 +274,8 @@ impl Session {
             name,
             subscription: HashMap::default(),
             orders: HashMap::default(),
+            balances: HashMap::default(),
+            exchange_positions: HashMap::default(),
             symbols: HashSet::default(),
             login: false,
             trading,
@@ -214,6 +304,16 @@ impl Session {
         self.trading
     }
 
+    #[getter]
+    fn balances(&self) -> HashMap<String, Py<Balance>> {
+        self.balances.clone()
+    }
+
+    #[getter]
+    fn exchange_positions(&self) -> Vec<Py<ExchangePosition>> {
+        self.exchange_positions.values().cloned().collect()
+    }
+
     fn connect(&mut self) {
         match self.connection_time {
             Some(t) => {
@@ -256,6 +356,58 @@ impl Session {
         }
     }
 
+    // streams of the whole market, forceOrder is sent as !forceOrder@arr
+    fn subscribe_all(&mut self, stream: &str) -> PyResult<()> {
+        if !self.login {
+            return Err(pyo3::exceptions::PyException::new_err("Please login first"));
+        }
+
+        match self.send("subscribe", vec![format!("!{}@arr", stream)]) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(pyo3::exceptions::PyException::new_err(e.to_string())),
+        }
+    }
+
+    // balances are also requested at login
+    fn refresh_balances(&mut self) {
+        if !self.login || !self.trading {
+            return;
+        }
+
+        if let Err(e) = self.send("get_balances", Vec::<String>::new()) {
+            error!("{:?}", e);
+        }
+    }
+
+    // the response times of the exchange account come back as a Latency event
+    fn get_latency(&mut self) {
+        if !self.login || !self.trading {
+            return;
+        }
+
+        if let Err(e) = self.send("get_latency", Vec::<String>::new()) {
+            error!("{:?}", e);
+        }
+    }
+
+    // fills of the session come back as a Fills event, bounds are inclusive milliseconds
+    #[pyo3(signature = (symbol=None, start=None, end=None))]
+    fn get_trades(&mut self, symbol: Option<String>, start: Option<i64>, end: Option<i64>) {
+        if !self.login {
+            return;
+        }
+
+        let params = TradeReq {
+            session_id: self.session_id,
+            symbol,
+            start,
+            end,
+        };
+        if let Err(e) = self.send("get_trades", params) {
+            error!("{:?}", e);
+        }
+    }
+
     fn add_order(
         &mut self,
         symbol: &str,
@@ -299,6 +451,72 @@ impl Session {
         return None;
     }
 
+    // orders are (symbol, price, quantity, side, order_type, tif)
+    fn batch_order(
+        &mut self,
+        orders: Vec<(String, f64, f64, Side, OrderType, Tif)>,
+    ) -> Vec<Py<Order>> {
+        if !self.login || !self.trading {
+            return Vec::new();
+        }
+
+        let mut params = Vec::new();
+        let mut pyorders = Vec::new();
+        for (symbol, price, quantity, side, order_type, tif) in orders {
+            // every order takes its own id, the request takes the next one
+            let id = self.id;
+            self.id += 1;
+
+            let order = Order::new(id, &symbol, price, quantity, side, order_type, tif);
+            params.push(OrderRequest {
+                id,
+                symbol,
+                price,
+                quantity,
+                side,
+                order_type,
+                tif,
+                session_id: self.session_id,
+            });
+            pyorders.push(order);
+        }
+
+        if let Err(e) = self.send("batch_order", params) {
+            error!("{:?}", e);
+            return Vec::new();
+        }
+
+        pyorders
+            .into_iter()
+            .map(|order| {
+                let id = order.id();
+                let pyorder = Python::with_gil(|py| Py::new(py, order).unwrap());
+                self.orders.insert(id, pyorder.clone());
+                pyorder
+            })
+            .collect()
+    }
+
+    // cancels are (symbol, order_id)
+    fn batch_cancel(&mut self, cancels: Vec<(String, u32)>) {
+        if !self.login || !self.trading {
+            return;
+        }
+
+        let params: Vec<_> = cancels
+            .into_iter()
+            .map(|(symbol, order_id)| CancelRequest {
+                symbol,
+                session_id: self.session_id,
+                order_id,
+            })
+            .collect();
+
+        if let Err(e) = self.send("batch_cancel", params) {
+            error!("{:?}", e);
+        }
+    }
+
     fn cancel(&mut self, symbol: String, order_id: u32) {
         if !self.login || !self.trading {
             return;
@@ -315,6 +533,24 @@ impl Session {
         }
     }
 
+    fn amend(&mut self, symbol: String, order_id: u32, price: f64, quantity: f64) {
+        if !self.login || !self.trading {
+            return;
+        }
+
+        let params = AmendRequest {
+            symbol,
+            session_id: self.session_id,
+            order_id,
+            price,
+            quantity,
+        };
+
+        if let Err(e) = self.send("amend", params) {
+            error!("{:?}", e);
+        }
+    }
+
     fn process(&mut self) -> Option<Py<PyAny>> {
         if let Some(msg) = self.ws.read() {
             debug!("{:?}", msg);
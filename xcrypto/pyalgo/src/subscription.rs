@@ -76,6 +76,30 @@ impl Subscription {
         }
     }
 
+    #[getter]
+    fn avg_price(&self) -> f64 {
+        match &self.position {
+            Some(position) => position.avg_price,
+            None => 0.0,
+        }
+    }
+
+    #[getter]
+    fn realized_pnl(&self) -> f64 {
+        match &self.position {
+            Some(position) => position.realized_pnl,
+            None => 0.0,
+        }
+    }
+
+    #[getter]
+    fn fee(&self) -> f64 {
+        match &self.position {
+            Some(position) => position.fee,
+            None => 0.0,
+        }
+    }
+
     pub fn order_support(&self, order_type: &OrderType) -> bool {
         self.product.order_support(order_type)
     }
@@ -1,3 +1,4 @@
+use crate::timesync::ClockEstimate;
 use native_json::{is_default, json};
 use serde::{Deserialize, Serialize};
 use std::{fmt::Debug, str::FromStr};
@@ -29,6 +30,34 @@ json! {
     }
 }
 
+json! {
+    OrderReq {
+        session_id: u16,
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct OrderRsp {
+    pub session_id: u16,
+    pub open: Vec<Order>,
+    pub finished: Vec<Order>,
+}
+
+// code 0 means the exchange accepted the order or cancel
+json! {
+    OrderResult {
+        order_id: u32,
+        code: i32,
+        msg: String,
+    }
+}
+
+json! {
+    BatchRsp {
+        results: Vec<OrderResult>,
+    }
+}
+
 json! {
     Error {
     code: i32,
@@ -66,7 +95,129 @@ pub struct Kline {
     pub amount: f64,
 }
 
+// a public trade, side is the taker side
+#[derive(Debug, Clone, Serialize)]
+pub struct Trade {
+    pub time: i64,
+    pub symbol: String,
+    pub stream: String,
+    pub id: i64,
+    pub price: f64,
+    pub quantity: f64,
+    pub side: Side,
+}
+
+// mark price and funding of a usdt future
+#[derive(Debug, Clone, Serialize)]
+pub struct PremiumIndex {
+    pub time: i64,
+    pub symbol: String,
+    pub stream: String,
+    pub mark_price: f64,
+    pub index_price: f64,
+    pub estimated_settle_price: f64,
+    pub last_funding_rate: f64,
+    pub next_funding_time: i64,
+}
+
+// a liquidation order of a usdt future, stream is !forceOrder@arr for the whole market
+#[derive(Debug, Clone, Serialize)]
+pub struct Liquidation {
+    pub time: i64,
+    pub symbol: String,
+    pub stream: String,
+    pub side: Side,
+    pub state: State,
+    pub price: f64,
+    pub avg_price: f64,
+    pub quantity: f64,
+    pub filled: f64,
+}
+
+// a balance of an asset, spot reports free and locked, usdt future reports cross wallet
+// and change, the balance change except pnl and commission, the others stay 0
+#[derive(Debug, Clone, Serialize)]
+pub struct Balance {
+    pub time: i64,
+    pub asset: String,
+    pub wallet: f64,
+    pub free: f64,
+    pub locked: f64,
+    pub cross_wallet: f64,
+    pub change: f64,
+}
+
+// what is used of a binance limit in its current window, reset is when the window ends in
+// milliseconds, interval is like 1M or 10S
+#[derive(Debug, Clone, Serialize)]
+pub struct RateLimit {
+    pub rate_limit_type: String,
+    pub interval: String,
+    pub used: u64,
+    pub limit: u64,
+    pub reset: i64,
+}
+
+// trade_time bounds are inclusive milliseconds, a missing filter matches everything
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TradeReq {
+    pub session_id: u16,
+    pub symbol: Option<String>,
+    pub start: Option<i64>,
+    pub end: Option<i64>,
+}
+
 #[derive(Debug, Serialize)]
+pub struct TradeRsp {
+    pub session_id: u16,
+    pub trades: Vec<Fill>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BalanceRsp {
+    pub balances: Vec<Balance>,
+}
+
+// response times of one rest endpoint or websocket api method in microseconds, buckets count
+// the requests answered within each of bounds and the last one those slower than every bound
+#[derive(Debug, Clone, Serialize)]
+pub struct EndpointLatency {
+    pub endpoint: String,
+    pub count: u64,
+    pub errors: u64,
+    pub mean: u64,
+    pub min: u64,
+    pub max: u64,
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub bounds: Vec<u64>,
+    pub buckets: Vec<u64>,
+}
+
+// the clock estimate is missing until the server time is sampled
+#[derive(Debug, Serialize)]
+pub struct LatencyRsp {
+    pub clock: Option<ClockEstimate>,
+    pub endpoints: Vec<EndpointLatency>,
+}
+
+// a position as the exchange reports it, position_side is BOTH in one-way mode
+#[derive(Debug, Clone, Serialize)]
+pub struct ExchangePosition {
+    pub time: i64,
+    pub symbol: String,
+    pub position_side: String,
+    pub amount: f64,
+    pub entry_price: f64,
+    pub break_even_price: f64,
+    pub unrealized_pnl: f64,
+    pub realized_pnl: f64,
+    pub margin_type: String,
+    pub isolated_wallet: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct Order {
     pub time: i64,
     pub symbol: String,
@@ -85,6 +236,38 @@ pub struct Order {
     pub making: bool,
 }
 
+// an execution of a session's order as journaled in pos.db
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct Fill {
+    pub session_id: u16,
+    pub symbol: String,
+    pub side: Side,
+    pub order_id: i64,
+    pub internal_id: u32,
+    pub price: f64,
+    pub quantity: f64,
+    pub commission: f64,
+    pub making: bool,
+    pub trade_time: i64,
+}
+
+impl Fill {
+    pub fn new(session_id: u16, order: &Order, commission: f64) -> Self {
+        Self {
+            session_id,
+            symbol: order.symbol.clone(),
+            side: order.side,
+            order_id: order.order_id,
+            internal_id: order.internal_id,
+            price: order.trade_price,
+            quantity: order.trade_quantity,
+            commission,
+            making: order.making,
+            trade_time: order.trade_time,
+        }
+    }
+}
+
 impl Order {
     pub fn new(
         id: u32,
@@ -132,7 +315,7 @@ pub enum State {
     EXPIRED_IN_MATCH,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, sqlx::Type)]
 pub enum Side {
     BUY,
     SELL,
@@ -212,8 +395,82 @@ impl FromStr for Tif {
 pub struct Position {
     pub symbol: String,
     pub net: f64,
+    // average entry price of net, 0 when flat
+    #[serde(default)]
+    pub avg_price: f64,
+    // closed quantity * (exit - entry), before commission
+    #[serde(default)]
+    pub realized_pnl: f64,
+    // commission of every fill, in the commission asset
+    #[serde(default)]
+    pub fee: f64,
+}
+
+impl Position {
+    pub fn new(symbol: &str) -> Self {
+        Self {
+            symbol: symbol.into(),
+            net: 0.0,
+            avg_price: 0.0,
+            realized_pnl: 0.0,
+            fee: 0.0,
+        }
+    }
+
+    // quantity is negative for a sell
+    pub fn on_fill(&mut self, quantity: f64, price: f64, commission: f64) {
+        let net = self.net + quantity;
+        if self.net == 0.0 || (self.net > 0.0) == (quantity > 0.0) {
+            self.avg_price = (self.avg_price * self.net + price * quantity) / net;
+        } else {
+            let closed = quantity.abs().min(self.net.abs());
+            self.realized_pnl += closed * (price - self.avg_price) * self.net.signum();
+            // flipped, the rest opens at the fill price
+            if quantity.abs() > self.net.abs() {
+                self.avg_price = price;
+            }
+        }
+
+        self.net = net;
+        self.fee += commission;
+        if self.net.abs() < 1e-12 {
+            self.net = 0.0;
+            self.avg_price = 0.0;
+        }
+    }
 }
 
 pub type Success = Response<Option<u8>>;
 pub type LoginResponse = Response<Login>;
 pub type ErrorResponse = Response<Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_position() {
+        let mut position = Position::new("btcusdt");
+        position.on_fill(1.0, 100.0, 0.1);
+        position.on_fill(1.0, 110.0, 0.1);
+        assert_eq!(position.net, 2.0);
+        assert_eq!(position.avg_price, 105.0);
+
+        // closing keeps the entry price
+        position.on_fill(-0.5, 120.0, 0.1);
+        assert_eq!(position.avg_price, 105.0);
+        assert_eq!(position.realized_pnl, 7.5);
+
+        // flipping opens the rest at the fill price
+        position.on_fill(-2.5, 95.0, 0.1);
+        assert_eq!(position.net, -1.0);
+        assert_eq!(position.avg_price, 95.0);
+        assert_eq!(position.realized_pnl, 7.5 - 15.0);
+
+        position.on_fill(1.0, 90.0, 0.1);
+        assert_eq!(position.net, 0.0);
+        assert_eq!(position.avg_price, 0.0);
+        assert_eq!(position.realized_pnl, -2.5);
+        assert!((position.fee - 0.5).abs() < 1e-12);
+    }
+}
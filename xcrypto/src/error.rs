@@ -4,5 +4,16 @@ pub const DUPLICATE_LOGIN: i32 = -10002;
 pub const INVALID_SYMBOL: i32 = -10003;
 pub const INVALID_STREAM: i32 = -10004;
 pub const NONTRADING: i32 = -10005;
+// pre-trade risk
+pub const MAX_NOTIONAL: i32 = -20001;
+pub const MAX_POSITION: i32 = -20002;
+pub const MAX_OPEN_ORDERS: i32 = -20003;
+pub const PRICE_BAND: i32 = -20004;
+pub const RATE_LIMIT: i32 = -20005;
+pub const NO_BBO: i32 = -20006;
+pub const UNKNOWN_ORDER: i32 = -20007;
+// amends binance refused or can't take, the order keeps working unless it was canceled
+pub const AMEND_UNSUPPORTED: i32 = -20008;
+pub const AMEND_FAILED: i32 = -20009;
 pub const DISCONNECTED: i32 = -30002;
 pub const UNDEF_ERROR: i32 = -30003;
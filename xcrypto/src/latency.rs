@@ -0,0 +1,130 @@
+use crate::chat::EndpointLatency;
+use std::collections::HashMap;
+use std::time::Duration;
+
+// upper bounds of the buckets in microseconds, one more bucket takes what is slower
+pub const BOUNDS: [u64; 12] = [
+    500, 1000, 2000, 5000, 10_000, 20_000, 50_000, 100_000, 200_000, 500_000, 1_000_000, 5_000_000,
+];
+
+#[derive(Debug, Default, Clone)]
+struct Histogram {
+    buckets: [u64; BOUNDS.len() + 1],
+    count: u64,
+    // requests without a response, timeouts and connection errors
+    errors: u64,
+    sum: u64,
+    min: u64,
+    max: u64,
+}
+
+impl Histogram {
+    fn record(&mut self, us: u64) {
+        let bucket = BOUNDS.iter().position(|bound| us <= *bound);
+        self.buckets[bucket.unwrap_or(BOUNDS.len())] += 1;
+        self.min = if self.count == 0 {
+            us
+        } else {
+            self.min.min(us)
+        };
+        self.max = self.max.max(us);
+        self.count += 1;
+        self.sum += us;
+    }
+
+    // the bound of the bucket holding the quantile, never more than the slowest request
+    fn quantile(&self, q: f64) -> u64 {
+        let rank = (self.count as f64 * q).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return BOUNDS
+                    .get(bucket)
+                    .map_or(self.max, |bound| self.max.min(*bound));
+            }
+        }
+        self.max
+    }
+
+    fn stats(&self, endpoint: &str) -> EndpointLatency {
+        EndpointLatency {
+            endpoint: endpoint.into(),
+            count: self.count,
+            errors: self.errors,
+            mean: self.sum.checked_div(self.count).unwrap_or_default(),
+            min: self.min,
+            max: self.max,
+            p50: self.quantile(0.5),
+            p90: self.quantile(0.9),
+            p99: self.quantile(0.99),
+            bounds: BOUNDS.to_vec(),
+            buckets: self.buckets.to_vec(),
+        }
+    }
+}
+
+// endpoint like "POST /api/v3/order" or a websocket api method -> its response times
+#[derive(Debug, Default)]
+pub struct Latency {
+    endpoints: HashMap<String, Histogram>,
+}
+
+impl Latency {
+    pub fn record(&mut self, endpoint: &str, elapsed: Duration) {
+        self.histogram(endpoint).record(elapsed.as_micros() as u64);
+    }
+
+    pub fn error(&mut self, endpoint: &str) {
+        self.histogram(endpoint).errors += 1;
+    }
+
+    fn histogram(&mut self, endpoint: &str) -> &mut Histogram {
+        if !self.endpoints.contains_key(endpoint) {
+            self.endpoints.insert(endpoint.into(), Histogram::default());
+        }
+        self.endpoints.get_mut(endpoint).unwrap()
+    }
+
+    pub fn stats(&self) -> Vec<EndpointLatency> {
+        let mut stats: Vec<_> = self
+            .endpoints
+            .iter()
+            .map(|(endpoint, histogram)| histogram.stats(endpoint))
+            .collect();
+        stats.sort_by(|a, b| a.endpoint.cmp(&b.endpoint));
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency() {
+        let mut latency = Latency::default();
+        for ms in 1..=100 {
+            latency.record("POST /api/v3/order", Duration::from_millis(ms));
+        }
+        latency.record("GET /api/v3/time", Duration::from_secs(10));
+        latency.error("GET /api/v3/time");
+
+        let stats = latency.stats();
+        assert_eq!(stats[0].endpoint, "GET /api/v3/time");
+        assert_eq!((stats[0].count, stats[0].errors), (1, 1));
+        // slower than every bound, the quantiles are the slowest request
+        assert_eq!(stats[0].p99, 10_000_000);
+        assert_eq!(stats[0].buckets[BOUNDS.len()], 1);
+
+        let order = &stats[1];
+        assert_eq!((order.count, order.min, order.max), (100, 1000, 100_000));
+        assert_eq!(order.mean, 50_500);
+        // 50ms is the bound of the bucket of the 50th request, 90 and 99 fall in (50ms, 100ms]
+        assert_eq!(
+            (order.p50, order.p90, order.p99),
+            (50_000, 100_000, 100_000)
+        );
+        assert_eq!(order.buckets.iter().sum::<u64>(), 100);
+    }
+}
@@ -1,7 +1,11 @@
 pub mod chat;
 pub mod error;
+pub mod latency;
 pub mod parser;
 pub mod position;
+pub mod ratelimit;
 pub mod rest;
+pub mod timesync;
 pub mod ws;
+pub mod wsapi;
 pub use websocket::*;
@@ -1,11 +1,18 @@
-use crate::chat::Position;
+use crate::chat::{Fill, Position, TradeReq};
 use log::*;
-use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
-use sqlx::{Pool, Row, Sqlite};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteConnection, SqlitePoolOptions};
+use sqlx::{FromRow, Pool, Row, Sqlite};
 use std::sync::Arc;
 use std::{borrow::Borrow, collections::HashMap};
 
 type Positions = HashMap<String, Position>;
+
+// the schema this version reads and writes
+pub const VERSION: i64 = 1;
+
+// columns of the session tables before version 1 that old tables may miss
+const SESSION_COLUMNS: [&str; 3] = ["avg_price", "realized_pnl", "fee"];
+
 pub struct PositionDB {
     conn: Arc<Pool<Sqlite>>,
     positions: HashMap<u16, Positions>,
@@ -24,35 +31,115 @@ impl PositionDB {
                 .await?,
         );
 
-        let mut session_positions = HashMap::default();
-        let query = "SELECT name FROM sqlite_master WHERE type='table';";
-        let rows = sqlx::query(query).fetch_all(conn.borrow()).await?;
+        Self::migrate(&conn).await?;
+        let positions = Self::load(&conn).await?;
+        Ok(Self { conn, positions })
+    }
 
-        for row in rows {
-            let session_id: String = row.get(0);
-            let session_id: u16 = session_id.parse()?;
-            let positions = Self::load(conn.clone(), session_id).await?;
+    // 0 is a database without a schema version, an empty one or one of the session tables
+    pub async fn version(conn: &Pool<Sqlite>) -> anyhow::Result<i64> {
+        let query = "CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL)";
+        sqlx::query(query).execute(conn).await?;
+        let query = "SELECT MAX(version) FROM schema_version";
+        let version: Option<i64> = sqlx::query_scalar(query).fetch_one(conn).await?;
+        Ok(version.unwrap_or_default())
+    }
 
-            session_positions.insert(session_id, positions);
+    // one transaction per version, an interrupted upgrade resumes from the last version
+    async fn migrate(conn: &Pool<Sqlite>) -> anyhow::Result<()> {
+        let mut version = Self::version(conn).await?;
+        if version > VERSION {
+            anyhow::bail!("Schema version {} is newer than {}", version, VERSION);
         }
-        Ok(Self {
-            conn,
-            positions: session_positions,
-        })
+
+        while version < VERSION {
+            let mut tx = conn.begin().await?;
+            match version {
+                0 => Self::migrate_session_tables(&mut tx).await?,
+                _ => unreachable!(),
+            }
+            version += 1;
+            sqlx::query("DELETE FROM schema_version")
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("INSERT INTO schema_version (version) VALUES ($1)")
+                .bind(version)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+            info!("Migrate schema to version {}", version);
+        }
+        Ok(())
     }
 
-    async fn load(
-        conn: Arc<Pool<Sqlite>>,
-        session_id: u16,
-    ) -> anyhow::Result<HashMap<String, Position>> {
-        let mut positions = HashMap::new();
+    // version 1 keeps every session in one table, the tables named by session are moved into it
+    async fn migrate_session_tables(conn: &mut SqliteConnection) -> anyhow::Result<()> {
+        let query = "CREATE TABLE IF NOT EXISTS positions (session_id INTEGER NOT NULL,
+            symbol TEXT NOT NULL, net REAL NOT NULL, avg_price REAL NOT NULL DEFAULT 0,
+            realized_pnl REAL NOT NULL DEFAULT 0, fee REAL NOT NULL DEFAULT 0,
+            PRIMARY KEY (session_id, symbol))";
+        sqlx::query(query).execute(&mut *conn).await?;
+
+        let query = "CREATE TABLE IF NOT EXISTS trades (id INTEGER PRIMARY KEY,
+            session_id INTEGER NOT NULL, symbol TEXT NOT NULL, side TEXT NOT NULL,
+            order_id INTEGER NOT NULL, internal_id INTEGER NOT NULL, price REAL NOT NULL,
+            quantity REAL NOT NULL, commission REAL NOT NULL, making BOOLEAN NOT NULL,
+            trade_time INTEGER NOT NULL)";
+        sqlx::query(query).execute(&mut *conn).await?;
+        let query = "CREATE INDEX IF NOT EXISTS trades_session ON trades (session_id, trade_time)";
+        sqlx::query(query).execute(&mut *conn).await?;
+
+        let query = "SELECT name FROM sqlite_master WHERE type='table'";
+        let names: Vec<String> = sqlx::query_scalar(query).fetch_all(&mut *conn).await?;
+        // other tables are left alone
+        let session_ids = names.iter().filter_map(|name| name.parse::<u16>().ok());
 
-        let query = format!("SELECT * FROM \"{}\" WHERE net <> 0", session_id);
-        let rows: Vec<Position> = sqlx::query_as(&query).fetch_all(conn.borrow()).await?;
+        for session_id in session_ids {
+            let query = format!("PRAGMA table_info(\"{}\")", session_id);
+            let rows = sqlx::query(&query).fetch_all(&mut *conn).await?;
+            let columns: Vec<String> = rows.iter().map(|row| row.get("name")).collect();
+            let values: Vec<_> = SESSION_COLUMNS
+                .iter()
+                .map(|column| match columns.iter().any(|name| name == column) {
+                    true => column.to_string(),
+                    false => "0".to_string(),
+                })
+                .collect();
+
+            let query = format!(
+                "INSERT OR REPLACE INTO positions (session_id, symbol, net, avg_price, realized_pnl, fee)
+                SELECT {}, symbol, net, {} FROM \"{}\"",
+                session_id,
+                values.join(", "),
+                session_id
+            );
+            let moved = sqlx::query(&query).execute(&mut *conn).await?;
+            let query = format!("DROP TABLE \"{}\"", session_id);
+            sqlx::query(&query).execute(&mut *conn).await?;
+            info!(
+                "Move {} positions of session {}",
+                moved.rows_affected(),
+                session_id
+            );
+        }
+        Ok(())
+    }
+
+    async fn load(conn: &Pool<Sqlite>) -> anyhow::Result<HashMap<u16, Positions>> {
+        let mut positions: HashMap<u16, Positions> = HashMap::new();
+
+        // a flat position still carries its pnl and fee
+        let query = "SELECT * FROM positions WHERE net <> 0 OR realized_pnl <> 0 OR fee <> 0";
+        let rows = sqlx::query(query).fetch_all(conn).await?;
 
         for row in rows {
-            info!("Session {} {:?}", session_id, row);
-            positions.insert(row.symbol.clone(), row);
+            let session_id: u16 = row.try_get("session_id")?;
+            let position = Position::from_row(&row)?;
+            info!("Session {} {:?}", session_id, position);
+            positions
+                .entry(session_id)
+                .or_default()
+                .insert(position.symbol.clone(), position);
         }
 
         Ok(positions)
@@ -61,15 +148,17 @@ impl PositionDB {
     pub fn update(&self, session_id: u16, position: Position) {
         let conn = self.conn.clone();
 
-        let query = format!(
-            "REPLACE INTO \"{}\" (symbol, net) VALUES ($1, $2)",
-            session_id
-        );
+        let query = "REPLACE INTO positions (session_id, symbol, net, avg_price, realized_pnl, fee)
+            VALUES ($1, $2, $3, $4, $5, $6)";
 
         tokio::spawn(async move {
-            match sqlx::query(&query)
+            match sqlx::query(query)
+                .bind(session_id)
                 .bind(position.symbol.clone())
                 .bind(position.net)
+                .bind(position.avg_price)
+                .bind(position.realized_pnl)
+                .bind(position.fee)
                 .execute(conn.borrow())
                 .await
             {
@@ -79,23 +168,129 @@ impl PositionDB {
         });
     }
 
+    // append a fill to the journal, rows are never updated or deleted
+    pub fn record(&self, fill: Fill) {
+        let conn = self.conn.clone();
+        let query = "INSERT INTO trades (session_id, symbol, side, order_id, internal_id, price,
+            quantity, commission, making, trade_time) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)";
+
+        tokio::spawn(async move {
+            match sqlx::query(query)
+                .bind(fill.session_id)
+                .bind(fill.symbol.clone())
+                .bind(fill.side)
+                .bind(fill.order_id)
+                .bind(fill.internal_id)
+                .bind(fill.price)
+                .bind(fill.quantity)
+                .bind(fill.commission)
+                .bind(fill.making)
+                .bind(fill.trade_time)
+                .execute(conn.borrow())
+                .await
+            {
+                Ok(_) => info!("Record {:?}", fill),
+                Err(e) => error!("{}", e),
+            }
+        });
+    }
+
+    // fills of a session in trade order
+    pub async fn get_trades(&self, req: &TradeReq) -> anyhow::Result<Vec<Fill>> {
+        let query = "SELECT session_id, symbol, side, order_id, internal_id, price, quantity,
+            commission, making, trade_time FROM trades WHERE session_id = $1
+            AND ($2 IS NULL OR symbol = $2 COLLATE NOCASE) AND ($3 IS NULL OR trade_time >= $3)
+            AND ($4 IS NULL OR trade_time <= $4) ORDER BY trade_time, id";
+        let fills = sqlx::query_as(query)
+            .bind(req.session_id)
+            .bind(req.symbol.clone())
+            .bind(req.start)
+            .bind(req.end)
+            .fetch_all(self.conn.borrow())
+            .await?;
+        Ok(fills)
+    }
+
     pub fn get_positions(&self, session_id: u16) -> Option<&Positions> {
         self.positions.get(&session_id)
     }
 
-    pub async fn create_table(&self, session_id: u16) -> anyhow::Result<()> {
-        let query = format!(
-            "CREATE TABLE IF NOT EXISTS \"{}\" (symbol TEXT PRIMARY KEY NOT NULL,  
-            net REAL NOT NULL )",
-            session_id
-        );
-        sqlx::query(&query).execute(self.conn.borrow()).await?;
+    // session_id -> positions as loaded at startup
+    pub fn all_positions(&self) -> &HashMap<u16, Positions> {
+        &self.positions
+    }
+
+    // forget the positions of a session, its fills stay in the journal
+    pub async fn clear(&self, session_id: u16) -> anyhow::Result<()> {
+        sqlx::query("DELETE FROM positions WHERE session_id = $1")
+            .bind(session_id)
+            .execute(self.conn.borrow())
+            .await?;
         Ok(())
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn open(path: &std::path::Path) -> anyhow::Result<Pool<Sqlite>> {
+        let options = SqliteConnectOptions::new()
+            .create_if_missing(true)
+            .filename(path);
+        Ok(SqlitePoolOptions::new().connect_with(options).await?)
+    }
+
+    #[tokio::test]
+    async fn test_migrate() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("migrate-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        // a table of the first versions, one with the pnl columns and an unrelated one
+        let conn = open(&path).await?;
+        for query in [
+            "CREATE TABLE \"3\" (symbol TEXT PRIMARY KEY NOT NULL, net REAL NOT NULL)",
+            "INSERT INTO \"3\" VALUES ('btcusdt', 0.5), ('ethusdt', 0)",
+            "CREATE TABLE \"7\" (symbol TEXT PRIMARY KEY NOT NULL, net REAL NOT NULL,
+                avg_price REAL NOT NULL, realized_pnl REAL NOT NULL, fee REAL NOT NULL)",
+            "INSERT INTO \"7\" VALUES ('ethusdt', -2, 3000, 15, 0.3)",
+            "CREATE TABLE notes (text TEXT)",
+        ] {
+            sqlx::query(query).execute(&conn).await?;
+        }
+        conn.close().await;
+
+        let posdb = PositionDB::new(path.to_str().unwrap()).await?;
+        assert_eq!(PositionDB::version(&posdb.conn).await?, VERSION);
+        let positions = posdb.all_positions();
+        assert_eq!(positions.len(), 2);
+        assert_eq!(positions[&3].len(), 1);
+        assert_eq!(positions[&3]["btcusdt"].net, 0.5);
+        assert_eq!(positions[&3]["btcusdt"].avg_price, 0.0);
+        assert_eq!(positions[&7]["ethusdt"].avg_price, 3000.0);
+        assert_eq!(positions[&7]["ethusdt"].fee, 0.3);
+
+        let query = "SELECT name FROM sqlite_master WHERE type='table' ORDER BY name";
+        let names: Vec<String> = sqlx::query_scalar(query)
+            .fetch_all(posdb.conn.borrow())
+            .await?;
+        assert_eq!(names, ["notes", "positions", "schema_version", "trades"]);
+
+        // opening again finds nothing to migrate
+        posdb.clear(7).await?;
+        drop(posdb);
+        let posdb = PositionDB::new(path.to_str().unwrap()).await?;
+        assert!(posdb.get_positions(7).is_none());
+        assert_eq!(posdb.get_positions(3).unwrap()["btcusdt"].net, 0.5);
+
+        sqlx::query("UPDATE schema_version SET version = $1")
+            .bind(VERSION + 1)
+            .execute(posdb.conn.borrow())
+            .await?;
+        drop(posdb);
+        assert!(PositionDB::new(path.to_str().unwrap()).await.is_err());
 
-    pub async fn drop_table(&self, session_id: u16) -> anyhow::Result<()> {
-        let query = format!("DROP TABLE IF EXISTS \"{}\"", session_id);
-        sqlx::query(&query).execute(self.conn.borrow()).await?;
+        let _ = std::fs::remove_file(&path);
         Ok(())
     }
 }
@@ -0,0 +1,284 @@
+use crate::chat::RateLimit;
+use log::*;
+use reqwest::header::HeaderMap;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// a limit as exchangeInfo lists it, websocket api responses add what is used of it
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExchangeLimit {
+    pub rate_limit_type: String,
+    pub interval: String,
+    pub interval_num: u64,
+    pub limit: u64,
+    pub count: Option<u64>,
+}
+
+impl ExchangeLimit {
+    // 1M, 10S or 1D like the suffix of the headers
+    pub fn interval(&self) -> String {
+        format!(
+            "{}{}",
+            self.interval_num,
+            self.interval.chars().next().unwrap_or_default()
+        )
+    }
+}
+
+// milliseconds of an interval like 10S
+fn span(interval: &str) -> Option<i64> {
+    let (num, unit) = interval.split_at(interval.len().checked_sub(1)?);
+    let unit = match unit {
+        "S" => 1000,
+        "M" => 60 * 1000,
+        "H" => 60 * 60 * 1000,
+        "D" => 24 * 60 * 60 * 1000,
+        _ => return None,
+    };
+    Some(num.parse::<i64>().ok()? * unit)
+}
+
+#[derive(Debug, Default, Clone)]
+struct Window {
+    // 0 until exchangeInfo tells it
+    limit: u64,
+    used: u64,
+    // milliseconds, binance counts in windows aligned to the clock
+    start: i64,
+    span: i64,
+}
+
+impl Window {
+    fn start(&self, now: i64) -> i64 {
+        now - now % self.span
+    }
+
+    // nothing is used once the window of the last count has passed
+    fn used(&self, now: i64) -> u64 {
+        if self.start == self.start(now) {
+            self.used
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Windows(HashMap<(String, String), Window>);
+
+impl Windows {
+    fn window(&mut self, rate_limit_type: &str, interval: &str) -> Option<&mut Window> {
+        let key = (rate_limit_type.to_string(), interval.to_uppercase());
+        if !self.0.contains_key(&key) {
+            let span = span(&key.1)?;
+            self.0.insert(
+                key.clone(),
+                Window {
+                    span,
+                    ..Default::default()
+                },
+            );
+        }
+        self.0.get_mut(&key)
+    }
+
+    fn usage(&self, now: i64) -> impl Iterator<Item = RateLimit> + '_ {
+        self.0.iter().filter(|(_, window)| window.limit > 0).map(
+            move |((rate_limit_type, interval), window)| RateLimit {
+                rate_limit_type: rate_limit_type.clone(),
+                interval: interval.clone(),
+                used: window.used(now),
+                limit: window.limit,
+                reset: window.start(now) + window.span,
+            },
+        )
+    }
+}
+
+// what binance counts per ip, every account sending from it shares one, see HttpClient
+#[derive(Debug, Default)]
+pub struct IpLimits {
+    // REQUEST_WEIGHT and RAW_REQUESTS
+    windows: Windows,
+    // milliseconds, requests are refused until then after a 429 or 418
+    banned_until: i64,
+}
+
+#[derive(Debug, Default)]
+pub struct RateLimits {
+    // ORDERS, counted per account
+    windows: Windows,
+    ip: Arc<Mutex<IpLimits>>,
+}
+
+impl RateLimits {
+    pub fn with_ip(ip: Arc<Mutex<IpLimits>>) -> Self {
+        Self {
+            windows: Windows::default(),
+            ip,
+        }
+    }
+
+    // ORDERS count per account, the rest per ip
+    fn update_window(
+        &mut self,
+        rate_limit_type: &str,
+        interval: &str,
+        f: impl FnOnce(&mut Window),
+    ) {
+        if rate_limit_type == "ORDERS" {
+            self.windows.window(rate_limit_type, interval).map(f);
+        } else {
+            let mut ip = self.ip.lock().unwrap();
+            ip.windows.window(rate_limit_type, interval).map(f);
+        }
+    }
+
+    pub fn set_limits(&mut self, limits: &[ExchangeLimit]) {
+        for limit in limits {
+            self.update_window(&limit.rate_limit_type, &limit.interval(), |window| {
+                window.limit = limit.limit
+            });
+        }
+    }
+
+    pub fn update(&mut self, rate_limit_type: &str, interval: &str, used: u64, now: i64) {
+        self.update_window(rate_limit_type, interval, |window| {
+            let start = window.start(now);
+            // responses of one window may come back out of order
+            if window.start != start || used > window.used {
+                window.used = used;
+            }
+            window.start = start;
+        });
+    }
+
+    // X-MBX-USED-WEIGHT-1M and X-MBX-ORDER-COUNT-10S of a rest response
+    pub fn update_headers(&mut self, headers: &HeaderMap, now: i64) {
+        for (name, value) in headers {
+            let (rate_limit_type, interval) = match name.as_str().strip_prefix("x-mbx-used-weight-")
+            {
+                Some(interval) => ("REQUEST_WEIGHT", interval),
+                None => match name.as_str().strip_prefix("x-mbx-order-count-") {
+                    Some(interval) => ("ORDERS", interval),
+                    None => continue,
+                },
+            };
+            if let Some(used) = value.to_str().ok().and_then(|v| v.parse().ok()) {
+                self.update(rate_limit_type, interval, used, now);
+            }
+        }
+    }
+
+    // the rateLimits of a websocket api response
+    pub fn update_limits(&mut self, limits: &[ExchangeLimit], now: i64) {
+        for limit in limits {
+            if let Some(count) = limit.count {
+                let interval = limit.interval();
+                self.update(&limit.rate_limit_type, &interval, count, now);
+                self.update_window(&limit.rate_limit_type, &interval, |window| {
+                    window.limit = limit.limit
+                });
+            }
+        }
+    }
+
+    // binance bans the ip, not the key
+    pub fn ban(&mut self, until: i64) {
+        warn!("Rate limit exceeded, banned until {}", until);
+        let mut ip = self.ip.lock().unwrap();
+        ip.banned_until = ip.banned_until.max(until);
+    }
+
+    pub fn banned_until(&self, now: i64) -> Option<i64> {
+        let banned_until = self.ip.lock().unwrap().banned_until;
+        (banned_until > now).then_some(banned_until)
+    }
+
+    // every limit exchangeInfo told, in the window of now
+    pub fn usage(&self, now: i64) -> Vec<RateLimit> {
+        let mut usage: Vec<_> = self.windows.usage(now).collect();
+        usage.extend(self.ip.lock().unwrap().windows.usage(now));
+        usage.sort_by(|a, b| (&a.rate_limit_type, a.reset).cmp(&(&b.rate_limit_type, b.reset)));
+        usage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn limit(rate_limit_type: &str, interval: &str, num: u64, limit: u64) -> ExchangeLimit {
+        ExchangeLimit {
+            rate_limit_type: rate_limit_type.into(),
+            interval: interval.into(),
+            interval_num: num,
+            limit,
+            count: None,
+        }
+    }
+
+    #[test]
+    fn test_rate_limits() {
+        let mut limits = RateLimits::default();
+        limits.set_limits(&[
+            limit("REQUEST_WEIGHT", "MINUTE", 1, 6000),
+            limit("ORDERS", "SECOND", 10, 100),
+            limit("RAW_REQUESTS", "MINUTE", 5, 61000),
+        ]);
+
+        let now = 1_700_000_000_000;
+        let mut headers = HeaderMap::new();
+        headers.insert("x-mbx-used-weight-1m", HeaderValue::from_static("120"));
+        headers.insert("x-mbx-order-count-10s", HeaderValue::from_static("7"));
+        headers.insert("x-mbx-order-count-1d", HeaderValue::from_static("900"));
+        limits.update_headers(&headers, now);
+
+        let usage = limits.usage(now);
+        let used: Vec<_> = usage
+            .iter()
+            .map(|l| (l.rate_limit_type.as_str(), l.interval.as_str(), l.used))
+            .collect();
+        // 1D has no limit from exchangeInfo
+        assert_eq!(
+            used,
+            [
+                ("ORDERS", "10S", 7),
+                ("RAW_REQUESTS", "5M", 0),
+                ("REQUEST_WEIGHT", "1M", 120)
+            ]
+        );
+        assert_eq!(usage[0].reset, now - now % 10000 + 10000);
+
+        // a late response of the window doesn't lower it, the next window starts from 0
+        limits.update("ORDERS", "10s", 5, now);
+        assert_eq!(limits.usage(now)[0].used, 7);
+        assert_eq!(limits.usage(now + 10000)[0].used, 0);
+
+        limits.ban(now + 60000);
+        assert_eq!(limits.banned_until(now), Some(now + 60000));
+        assert_eq!(limits.banned_until(now + 60000), None);
+
+        // another account from the same ip shares the weight and the ban, not the orders
+        let ip = Arc::new(Mutex::new(IpLimits::default()));
+        let mut first = RateLimits::with_ip(ip.clone());
+        let mut second = RateLimits::with_ip(ip);
+        first.set_limits(&[
+            limit("REQUEST_WEIGHT", "MINUTE", 1, 6000),
+            limit("ORDERS", "SECOND", 10, 100),
+        ]);
+        second.set_limits(&[limit("ORDERS", "SECOND", 10, 100)]);
+        first.update_headers(&headers, now);
+        let used: Vec<_> = second
+            .usage(now)
+            .iter()
+            .map(|l| (l.rate_limit_type.clone(), l.used))
+            .collect();
+        assert_eq!(used, [("ORDERS".into(), 0), ("REQUEST_WEIGHT".into(), 120)]);
+        first.ban(now + 60000);
+        assert_eq!(second.banned_until(now), Some(now + 60000));
+    }
+}
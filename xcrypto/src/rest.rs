@@ -1,43 +1,248 @@
+use crate::chat::{Order, OrderType};
+use crate::latency::Latency;
+use crate::ratelimit::{IpLimits, RateLimits};
+use crate::timesync::TimeSync;
 use base64::prelude::BASE64_STANDARD;
 use base64::Engine;
 use log::*;
-use openssl::pkey::{PKey, Private};
+use openssl::hash::MessageDigest;
+use openssl::pkey::{Id, PKey, Private};
 use openssl::sign::Signer;
-use reqwest::{Method, Response};
-use std::fs::File;
-use std::io::Read;
+use reqwest::{Client, Method, Response};
+use serde::Deserialize;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 pub trait IntoIterTuple<K, V> {
     type Iter: Iterator<Item = (K, V)>;
     fn into_iter(self) -> Self::Iter;
 }
 
+// the kind of the api key, ed25519 and rsa keys are pem files, hmac reads its secret from a file
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SignMethod {
+    #[default]
+    Ed25519,
+    Rsa,
+    Hmac,
+}
+
+impl FromStr for SignMethod {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ed25519" => Ok(Self::Ed25519),
+            "rsa" => Ok(Self::Rsa),
+            "hmac" => Ok(Self::Hmac),
+            _ => anyhow::bail!("Unknown sign method {}", s),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SigningKey {
+    method: SignMethod,
+    key: PKey<Private>,
+}
+
+impl SigningKey {
+    pub fn load(path: &str, method: SignMethod) -> anyhow::Result<Self> {
+        let buf = std::fs::read(path)?;
+        let (key, id) = match method {
+            SignMethod::Ed25519 => (PKey::private_key_from_pem(&buf)?, Id::ED25519),
+            SignMethod::Rsa => (PKey::private_key_from_pem(&buf)?, Id::RSA),
+            SignMethod::Hmac => (
+                PKey::hmac(String::from_utf8(buf)?.trim().as_bytes())?,
+                Id::HMAC,
+            ),
+        };
+
+        if key.id() != id {
+            anyhow::bail!("{} isn't a {:?} key", path, method);
+        }
+        Ok(Self { method, key })
+    }
+
+    pub fn method(&self) -> SignMethod {
+        self.method
+    }
+
+    // ed25519 and rsa (sha256) signatures are base64, hmac sha256 is hex
+    pub fn sign(&self, data: &[u8]) -> anyhow::Result<String> {
+        match self.method {
+            SignMethod::Ed25519 => {
+                let mut signer = Signer::new_without_digest(&self.key)?;
+                Ok(BASE64_STANDARD.encode(signer.sign_oneshot_to_vec(data)?))
+            }
+            SignMethod::Rsa => {
+                let mut signer = Signer::new(MessageDigest::sha256(), &self.key)?;
+                Ok(BASE64_STANDARD.encode(signer.sign_oneshot_to_vec(data)?))
+            }
+            SignMethod::Hmac => {
+                let mut signer = Signer::new(MessageDigest::sha256(), &self.key)?;
+                let signature = signer.sign_oneshot_to_vec(data)?;
+                Ok(signature.iter().map(|b| format!("{:02x}", b)).collect())
+            }
+        }
+    }
+}
+
+fn default_timeout() -> u64 {
+    10000
+}
+
+fn default_connect_timeout() -> u64 {
+    3000
+}
+
+fn default_keep_alive() -> u64 {
+    90000
+}
+
+// the pooled client every account shares, milliseconds
+#[derive(Debug, Clone, Deserialize)]
+pub struct HttpConfig {
+    // a whole request, from connecting to the end of the body
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout: u64,
+    // idle connections are kept this long for the next request
+    #[serde(default = "default_keep_alive")]
+    pub keep_alive: u64,
+    // negotiated by alpn, http/1.1 when the server doesn't take it
+    #[serde(default)]
+    pub http2: bool,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            timeout: default_timeout(),
+            connect_timeout: default_connect_timeout(),
+            keep_alive: default_keep_alive(),
+            http2: false,
+        }
+    }
+}
+
+// connections and what binance counts per ip, every account requesting through it shares them
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: Client,
+    ip_limits: Arc<Mutex<IpLimits>>,
+}
+
+impl HttpConfig {
+    pub fn client(&self) -> anyhow::Result<HttpClient> {
+        let mut builder = Client::builder()
+            .timeout(Duration::from_millis(self.timeout))
+            .connect_timeout(Duration::from_millis(self.connect_timeout))
+            .pool_idle_timeout(Duration::from_millis(self.keep_alive))
+            .tcp_keepalive(Duration::from_secs(30))
+            .tcp_nodelay(true);
+        if !self.http2 {
+            builder = builder.http1_only();
+        }
+        Ok(HttpClient {
+            client: builder.build()?,
+            ip_limits: Arc::default(),
+        })
+    }
+}
+
 #[derive(Debug)]
 pub struct Rest {
     base_uri: String,
     apikey: String,
-    private_key: PKey<Private>,
+    // none for public market data only
+    key: Option<SigningKey>,
     recvwindow: i64,
+    rate_limits: Mutex<RateLimits>,
+    time_sync: Mutex<TimeSync>,
+    client: Client,
+    latency: Mutex<Latency>,
 }
 
 impl Rest {
+    // an ed25519 key
     pub fn new(base_uri: &str, apikey: &str, pem: &str, recvwindow: i64) -> anyhow::Result<Self> {
-        let mut buf = Vec::new();
-        File::open(pem).unwrap().read_to_end(&mut buf)?;
-        let private_key = PKey::private_key_from_pem(&buf)?;
+        Self::with_sign(base_uri, apikey, pem, SignMethod::Ed25519, recvwindow)
+    }
 
+    pub fn with_sign(
+        base_uri: &str,
+        apikey: &str,
+        key: &str,
+        sign: SignMethod,
+        recvwindow: i64,
+    ) -> anyhow::Result<Self> {
         Ok(Self {
             base_uri: base_uri.trim_end_matches("/").into(),
             apikey: apikey.into(),
-            private_key,
+            key: Some(SigningKey::load(key, sign)?),
             recvwindow,
+            rate_limits: Mutex::default(),
+            time_sync: Mutex::default(),
+            client: HttpConfig::default().client()?.client,
+            latency: Mutex::default(),
+        })
+    }
+
+    // without credentials, signed requests fail
+    pub fn public(base_uri: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            base_uri: base_uri.trim_end_matches("/").into(),
+            apikey: String::new(),
+            key: None,
+            recvwindow: 0,
+            rate_limits: Mutex::default(),
+            time_sync: Mutex::default(),
+            client: HttpConfig::default().client()?.client,
+            latency: Mutex::default(),
         })
     }
 
+    // share the connections and the ip limits of one client, see HttpConfig
+    pub fn with_client(self, client: HttpClient) -> Self {
+        Self {
+            client: client.client,
+            rate_limits: Mutex::new(RateLimits::with_ip(client.ip_limits)),
+            ..self
+        }
+    }
+
     pub fn apikey(&self) -> &str {
         &self.apikey
     }
 
+    pub fn recvwindow(&self) -> i64 {
+        self.recvwindow
+    }
+
+    // what the responses of this key tell about the limits of the account and its ip
+    pub fn rate_limits(&self) -> &Mutex<RateLimits> {
+        &self.rate_limits
+    }
+
+    // the offset of the exchange clock, see timesync::start
+    pub fn time_sync(&self) -> &Mutex<TimeSync> {
+        &self.time_sync
+    }
+
+    // response times of every endpoint requested with this key, the websocket api included
+    pub fn latency(&self) -> &Mutex<Latency> {
+        &self.latency
+    }
+
+    // milliseconds, the local clock corrected to the exchange one
+    pub fn now(&self) -> anyhow::Result<i64> {
+        Ok(timestamp()? as i64 + self.time_sync.lock().unwrap().offset())
+    }
+
     pub async fn get(
         &self,
         path: &str,
@@ -84,10 +289,14 @@ impl Rest {
     }
 
     pub fn sign(&self, data: &String) -> anyhow::Result<String> {
-        let mut signer = Signer::new_without_digest(&self.private_key).unwrap();
-        let signature = signer.sign_oneshot_to_vec(data.as_bytes())?;
+        match &self.key {
+            Some(key) => key.sign(data.as_bytes()),
+            None => anyhow::bail!("No key to sign requests to {}", self.base_uri),
+        }
+    }
 
-        Ok(BASE64_STANDARD.encode(signature))
+    pub fn sign_method(&self) -> Option<SignMethod> {
+        self.key.as_ref().map(|key| key.method())
     }
 
     async fn send(
@@ -97,38 +306,72 @@ impl Rest {
         params: &[(String, String)],
         signature: bool,
     ) -> anyhow::Result<Response> {
+        // every request while banned extends the ban
+        let now = self.now()?;
+        if let Some(until) = self.rate_limits.lock().unwrap().banned_until(now) {
+            anyhow::bail!("Banned by rate limit until {}", until);
+        }
+
         let mut params: Vec<_> = params.into_iter().cloned().collect();
 
         if signature {
-            let ts = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)?
-                .as_millis();
-            params.push(("timestamp".into(), ts.to_string()));
+            params.push(("timestamp".into(), now.to_string()));
             if self.recvwindow > 0 {
                 params.push(("recvWindow".into(), self.recvwindow.to_string()));
             }
         }
 
-        let mut builder = reqwest::Client::new()
+        let endpoint = format!("{} {}", method, path);
+        let mut builder = self
+            .client
             .request(
                 method,
                 format!("{}/{}", self.base_uri, path.trim_start_matches("/")),
             )
-            .header("X-MBX-APIKEY", &self.apikey)
             .query(&params);
+        if self.key.is_some() {
+            builder = builder.header("X-MBX-APIKEY", &self.apikey);
+        }
 
         if signature {
-            let query: String = params
-                .iter()
-                .map(|(k, v)| format!("{}={}", k, v))
-                .collect::<Vec<String>>()
-                .join("&");
+            // sign what is sent, json params like batchOrders are percent encoded
+            let query = url::form_urlencoded::Serializer::new(String::new())
+                .extend_pairs(&params)
+                .finish();
             builder = builder.query(&[("signature", &self.sign(&query)?)]);
         }
 
-        let rsp = builder.send().await?;
+        let start = Instant::now();
+        let rsp = match builder.send().await {
+            Ok(rsp) => {
+                self.latency
+                    .lock()
+                    .unwrap()
+                    .record(&endpoint, start.elapsed());
+                rsp
+            }
+            Err(e) => {
+                self.latency.lock().unwrap().error(&endpoint);
+                return Err(e.into());
+            }
+        };
         debug!("{:?}", rsp);
 
+        self.rate_limits
+            .lock()
+            .unwrap()
+            .update_headers(rsp.headers(), now);
+        // 429 is a warning, 418 a ban of the ip, both tell how long to wait in seconds
+        if rsp.status() == 429 || rsp.status() == 418 {
+            let retry = rsp
+                .headers()
+                .get("retry-after")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<i64>().ok())
+                .unwrap_or(60);
+            self.rate_limits.lock().unwrap().ban(now + retry * 1000);
+        }
+
         Ok(rsp)
     }
 
@@ -145,36 +388,43 @@ impl Rest {
         id: u32,
     ) -> anyhow::Result<Response> {
         let client_order_id = u64::from(session_id) << 32 | u64::from(id);
-        let mut params = vec![
-            ("symbol".into(), symbol),
-            ("side".into(), side),
-            ("type".into(), order_type.clone()),
-            ("quantity".into(), quantity),
-            ("newClientOrderId".into(), client_order_id.to_string()),
-            ("newOrderRespType".into(), "RESULT".into()),
-        ];
-
-        match order_type.as_str() {
-            "STOP_MARKET" => {
-                // For stop market orders, the price is treated as stopPrice
-                params.push(("stopPrice".into(), price));
-            }
-            "MARKET" => {
-                // Market orders don't need a price, so no action needed
-            }
-            _ => {
-                // For other types (e.g., LIMIT), use price
-                params.push(("price".into(), price));
-            }
-        }
-
-        if tif != "UNDEF" {
-            params.push(("timeInForce".into(), tif));
-        }
+        let params = order_params(
+            symbol,
+            price,
+            quantity,
+            side,
+            order_type,
+            tif,
+            client_order_id,
+        );
 
         self.post(path, &params, true).await
     }
 
+    // usdt future, up to 5 orders built by order_params in one request
+    pub async fn batch_orders(
+        &self,
+        path: &str,
+        orders: &[Vec<(String, String)>],
+    ) -> anyhow::Result<Response> {
+        let orders: Vec<serde_json::Map<String, serde_json::Value>> = orders
+            .iter()
+            .map(|params| {
+                params
+                    .iter()
+                    .map(|(k, v)| (k.clone(), serde_json::Value::String(v.clone())))
+                    .collect()
+            })
+            .collect();
+
+        self.post(
+            path,
+            &[("batchOrders".into(), serde_json::to_string(&orders)?)],
+            true,
+        )
+        .await
+    }
+
     pub async fn cancel(&self, path: &str, symbol: String, orig: u64) -> anyhow::Result<Response> {
         self.delete(
             path,
@@ -186,4 +436,178 @@ impl Rest {
         )
         .await
     }
+
+    // usdt future, up to 10 orders of one symbol in one request
+    pub async fn batch_cancel(
+        &self,
+        path: &str,
+        symbol: String,
+        origs: &[u64],
+    ) -> anyhow::Result<Response> {
+        let origs: Vec<String> = origs.iter().map(|orig| orig.to_string()).collect();
+        self.delete(
+            path,
+            &[
+                ("symbol".into(), symbol),
+                (
+                    "origClientOrderIdList".into(),
+                    serde_json::to_string(&origs)?,
+                ),
+            ],
+            true,
+        )
+        .await
+    }
+
+    // spot, cancel the order and place it again with the same client order id
+    pub async fn cancel_replace(
+        &self,
+        path: &str,
+        order: &Order,
+        session_id: u16,
+        price: String,
+        quantity: String,
+    ) -> anyhow::Result<Response> {
+        let params = cancel_replace_params(order, session_id, price, quantity);
+        self.post(path, &params, true).await
+    }
+
+    // usdt future, modify the price and quantity in place
+    pub async fn modify(
+        &self,
+        path: &str,
+        order: &Order,
+        session_id: u16,
+        price: String,
+        quantity: String,
+    ) -> anyhow::Result<Response> {
+        let params = modify_params(order, session_id, price, quantity);
+        self.put(path, &params, true).await
+    }
+}
+
+pub fn order_params(
+    symbol: String,
+    price: String,
+    quantity: String,
+    side: String,
+    order_type: String,
+    tif: String,
+    client_order_id: u64,
+) -> Vec<(String, String)> {
+    let mut params = vec![
+        ("symbol".into(), symbol),
+        ("side".into(), side),
+        ("type".into(), order_type.clone()),
+        ("quantity".into(), quantity),
+        ("newClientOrderId".into(), client_order_id.to_string()),
+        ("newOrderRespType".into(), "RESULT".into()),
+    ];
+
+    match order_type.as_str() {
+        "STOP_MARKET" => {
+            // For stop market orders, the price is treated as stopPrice
+            params.push(("stopPrice".into(), price));
+        }
+        "MARKET" => {
+            // Market orders don't need a price, so no action needed
+        }
+        _ => {
+            // For other types (e.g., LIMIT), use price
+            params.push(("price".into(), price));
+        }
+    }
+
+    if tif != "UNDEF" {
+        params.push(("timeInForce".into(), tif));
+    }
+    params
+}
+
+pub fn cancel_replace_params(
+    order: &Order,
+    session_id: u16,
+    price: String,
+    quantity: String,
+) -> Vec<(String, String)> {
+    let client_order_id = u64::from(session_id) << 32 | u64::from(order.internal_id);
+    let mut params = vec![
+        ("symbol".into(), order.symbol.to_uppercase()),
+        ("side".into(), format!("{:?}", order.side)),
+        ("type".into(), format!("{:?}", order.order_type)),
+        ("cancelReplaceMode".into(), "STOP_ON_FAILURE".into()),
+        (
+            "cancelOrigClientOrderId".into(),
+            client_order_id.to_string(),
+        ),
+        ("newClientOrderId".into(), client_order_id.to_string()),
+        ("newOrderRespType".into(), "RESULT".into()),
+        ("price".into(), price),
+        ("quantity".into(), quantity),
+    ];
+
+    // LIMIT_MAKER takes no time in force
+    if order.order_type == OrderType::LIMIT {
+        params.push(("timeInForce".into(), format!("{:?}", order.tif)));
+    }
+    params
+}
+
+pub fn modify_params(
+    order: &Order,
+    session_id: u16,
+    price: String,
+    quantity: String,
+) -> Vec<(String, String)> {
+    let client_order_id = u64::from(session_id) << 32 | u64::from(order.internal_id);
+    vec![
+        ("symbol".into(), order.symbol.to_uppercase()),
+        ("side".into(), format!("{:?}", order.side)),
+        ("origClientOrderId".into(), client_order_id.to_string()),
+        ("price".into(), price),
+        ("quantity".into(), quantity),
+    ]
+}
+
+// milliseconds since the epoch, the timestamp of signed requests
+pub fn timestamp() -> anyhow::Result<u128> {
+    Ok(std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_millis())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::rsa::Rsa;
+    use openssl::sign::Verifier;
+
+    fn write(name: &str, buf: &[u8]) -> String {
+        let path = std::env::temp_dir().join(format!("{}-{}", std::process::id(), name));
+        std::fs::write(&path, buf).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn test_sign() -> anyhow::Result<()> {
+        // the example of the binance api documentation
+        let secret = "NhqPtmdSJYdKjVHjA7PZj4Mge3R5YNiP1e3UZjInClVN65XAbvqqM6A7H5fATj0j\n";
+        let query = "symbol=LTCBTC&side=BUY&type=LIMIT&timeInForce=GTC&quantity=1&price=0.1&recvWindow=5000&timestamp=1499827319559";
+        let key = SigningKey::load(&write("secret", secret.as_bytes()), SignMethod::Hmac)?;
+        assert_eq!(
+            key.sign(query.as_bytes())?,
+            "c8db56825ae71d6d79447849e617115f4a920fa2acdcab2b053c4b2838bd6b71"
+        );
+
+        let rsa = PKey::from_rsa(Rsa::generate(2048)?)?;
+        let pem = write("rsa.pem", &rsa.private_key_to_pem_pkcs8()?);
+        let signature = SigningKey::load(&pem, SignMethod::Rsa)?.sign(query.as_bytes())?;
+        let mut verifier = Verifier::new(MessageDigest::sha256(), &rsa)?;
+        assert!(verifier.verify_oneshot(&BASE64_STANDARD.decode(signature)?, query.as_bytes())?);
+
+        // the method has to match the key
+        assert!(SigningKey::load(&pem, SignMethod::Ed25519).is_err());
+        assert_eq!("HMAC".parse::<SignMethod>()?, SignMethod::Hmac);
+        Ok(())
+    }
 }
@@ -0,0 +1,129 @@
+use crate::rest::{timestamp, Rest};
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+// samples kept, the estimate is taken from the one of the shortest round trip
+const SAMPLES: usize = 8;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ServerTime {
+    server_time: i64,
+}
+
+// milliseconds, offset is the exchange clock minus the local one
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClockEstimate {
+    pub offset: i64,
+    pub rtt: i64,
+    // local time of the sample
+    pub time: i64,
+}
+
+#[derive(Debug, Default)]
+pub struct TimeSync {
+    samples: VecDeque<ClockEstimate>,
+}
+
+impl TimeSync {
+    // the server answered with its time somewhere between sent and received
+    pub fn add(&mut self, sent: i64, server: i64, received: i64) -> ClockEstimate {
+        let rtt = (received - sent).max(0);
+        let sample = ClockEstimate {
+            offset: server - (sent + rtt / 2),
+            rtt,
+            time: received,
+        };
+        if self.samples.len() == SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+        sample
+    }
+
+    // the shortest round trip is the least skewed by the network
+    pub fn estimate(&self) -> Option<ClockEstimate> {
+        self.samples.iter().min_by_key(|sample| sample.rtt).copied()
+    }
+
+    pub fn last(&self) -> Option<ClockEstimate> {
+        self.samples.back().copied()
+    }
+
+    // 0 until the first sample
+    pub fn offset(&self) -> i64 {
+        self.estimate().map(|e| e.offset).unwrap_or_default()
+    }
+}
+
+// one round trip of /api/v3/time or /fapi/v1/time
+pub async fn sync(rest: &Rest, path: &str) -> anyhow::Result<ClockEstimate> {
+    let sent = timestamp()? as i64;
+    let rsp = rest.get(path, &[], false).await?;
+    let received = timestamp()? as i64;
+    let text = rsp.text().await?;
+    let server = match serde_json::from_str::<ServerTime>(&text) {
+        Ok(server) => server.server_time,
+        Err(_) => anyhow::bail!("Failed to get server time {}", text),
+    };
+
+    let mut time_sync = rest.time_sync().lock().unwrap();
+    let sample = time_sync.add(sent, server, received);
+    info!(
+        "Server time {:?}, estimate {:?}",
+        sample,
+        time_sync.estimate()
+    );
+    Ok(sample)
+}
+
+// sample the server time now and then every interval, until the rest is dropped
+pub async fn start(rest: &Arc<Rest>, path: &str, interval: Duration) {
+    if let Err(e) = sync(rest, path).await {
+        error!("{}", e);
+    }
+
+    let rest = Arc::downgrade(rest);
+    let path = path.to_string();
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+            let rest = match Weak::upgrade(&rest) {
+                Some(rest) => rest,
+                None => break,
+            };
+            if let Err(e) = sync(&rest, &path).await {
+                error!("{}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time_sync() {
+        let mut time_sync = TimeSync::default();
+        assert_eq!(time_sync.offset(), 0);
+
+        // the server is 500ms ahead, answering in the middle of the round trip
+        let sample = time_sync.add(1000, 1520, 1040);
+        assert_eq!((sample.offset, sample.rtt), (500, 40));
+
+        // a slow round trip doesn't move the estimate
+        time_sync.add(2000, 2900, 2600);
+        assert_eq!(time_sync.offset(), 500);
+        assert_eq!(time_sync.last().unwrap().rtt, 600);
+
+        // the oldest samples are dropped
+        for i in 0..SAMPLES as i64 {
+            time_sync.add(3000 + i, 3510 + i, 3100 + i);
+        }
+        assert_eq!(time_sync.offset(), 460);
+    }
+}
@@ -0,0 +1,324 @@
+use crate::chat::Order;
+use crate::ratelimit::ExchangeLimit;
+use crate::rest::{
+    cancel_replace_params, modify_params, order_params, HttpConfig, Rest, SignMethod,
+};
+use crate::ws::WebSocket;
+use log::*;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+use websocket::tungstenite::Message;
+
+type Reply = oneshot::Sender<anyhow::Result<Value>>;
+
+// id, request frame, where its response goes
+type Request = (u64, String, Reply);
+
+// the id of the logon, requests count from 1
+const LOGON: u64 = 0;
+
+// binance websocket api, every request rides one connection logged on with the rest key
+pub struct WsApi {
+    id: AtomicU64,
+    rest: Arc<Rest>,
+    requests: UnboundedSender<Request>,
+    // a request still unanswered by then fails
+    timeout: Duration,
+}
+
+impl WsApi {
+    // the connection lives in the background and logs on again after every reconnect
+    pub fn new(addr: &str, rest: Arc<Rest>) -> Self {
+        let (tx, rx) = unbounded_channel();
+        tokio::spawn(run(addr.to_string(), rest.clone(), rx));
+
+        Self {
+            id: AtomicU64::new(LOGON + 1),
+            rest,
+            requests: tx,
+            timeout: Duration::from_millis(HttpConfig::default().timeout),
+        }
+    }
+
+    // the timeout of the rest requests, see HttpConfig
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Self { timeout, ..self }
+    }
+
+    // the result of the request or its error, the body the rest endpoint would answer
+    pub async fn request(
+        &self,
+        method: &str,
+        mut params: Vec<(String, String)>,
+    ) -> anyhow::Result<Value> {
+        let now = self.rest.now()?;
+        if let Some(until) = self.rest.rate_limits().lock().unwrap().banned_until(now) {
+            anyhow::bail!("Banned by rate limit until {}", until);
+        }
+
+        let id = self.id.fetch_add(1, Ordering::Relaxed);
+        params.push(("timestamp".into(), now.to_string()));
+        if self.rest.recvwindow() > 0 {
+            params.push(("recvWindow".into(), self.rest.recvwindow().to_string()));
+        }
+
+        // only ed25519 keys can log on, the others sign every request
+        if !logs_on(&self.rest) {
+            params.push(("apiKey".into(), self.rest.apikey().into()));
+            params.sort();
+            let payload: Vec<_> = params.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+            let signature = self.rest.sign(&payload.join("&"))?;
+            params.push(("signature".into(), signature));
+        }
+
+        let params: serde_json::Map<String, Value> = params
+            .into_iter()
+            .map(|(k, v)| (k, Value::String(v)))
+            .collect();
+        let frame = json!({"id": id, "method": method, "params": params});
+        let (tx, rx) = oneshot::channel();
+        let start = Instant::now();
+        if self.requests.send((id, frame.to_string(), tx)).is_err() {
+            anyhow::bail!("Websocket api is closed");
+        }
+        // the connection is up but the answer never comes, pending drops it once rx is gone
+        let rsp = match tokio::time::timeout(self.timeout, rx).await {
+            Ok(rsp) => rsp?,
+            Err(_) => Err(anyhow::anyhow!(
+                "Websocket api {} {} timed out after {:?}",
+                method,
+                id,
+                self.timeout
+            )),
+        };
+
+        // kept next to the rest endpoints, a method has no slash to clash with them
+        let mut latency = self.rest.latency().lock().unwrap();
+        match rsp {
+            Ok(_) => latency.record(method, start.elapsed()),
+            Err(_) => latency.error(method),
+        }
+        rsp
+    }
+
+    // the arguments of Rest::add_order
+    #[allow(clippy::too_many_arguments)]
+    pub async fn add_order(
+        &self,
+        symbol: String,
+        price: String,
+        quantity: String,
+        side: String,
+        order_type: String,
+        tif: String,
+        session_id: u16,
+        id: u32,
+    ) -> anyhow::Result<Value> {
+        let client_order_id = u64::from(session_id) << 32 | u64::from(id);
+        let params = order_params(
+            symbol,
+            price,
+            quantity,
+            side,
+            order_type,
+            tif,
+            client_order_id,
+        );
+
+        self.request("order.place", params).await
+    }
+
+    pub async fn cancel(&self, symbol: String, orig: u64) -> anyhow::Result<Value> {
+        let params = vec![
+            ("symbol".into(), symbol),
+            ("origClientOrderId".into(), orig.to_string()),
+        ];
+        self.request("order.cancel", params).await
+    }
+
+    // spot, cancel the order and place it again with the same client order id
+    pub async fn cancel_replace(
+        &self,
+        order: &Order,
+        session_id: u16,
+        price: String,
+        quantity: String,
+    ) -> anyhow::Result<Value> {
+        let params = cancel_replace_params(order, session_id, price, quantity);
+        self.request("order.cancelReplace", params).await
+    }
+
+    // usdt future, modify the price and quantity in place
+    pub async fn modify(
+        &self,
+        order: &Order,
+        session_id: u16,
+        price: String,
+        quantity: String,
+    ) -> anyhow::Result<Value> {
+        let params = modify_params(order, session_id, price, quantity);
+        self.request("order.modify", params).await
+    }
+}
+
+async fn run(addr: String, rest: Arc<Rest>, mut requests: UnboundedReceiver<Request>) {
+    loop {
+        match logon(&addr, &rest).await {
+            Ok(mut ws) => {
+                if !serve(&mut ws, &rest, &mut requests).await {
+                    return;
+                }
+                error!("Websocket api disconnected");
+            }
+            Err(e) => error!("{}", e),
+        }
+
+        // requests fail until the next attempt instead of waiting for it
+        let retry = tokio::time::sleep(Duration::from_secs(10));
+        tokio::pin!(retry);
+        loop {
+            tokio::select! {
+                _ = &mut retry => break,
+                request = requests.recv() => match request {
+                    Some((_, _, reply)) => {
+                        let _ = reply.send(Err(anyhow::anyhow!("Websocket api is disconnected")));
+                    }
+                    None => return,
+                },
+            }
+        }
+    }
+}
+
+fn logs_on(rest: &Rest) -> bool {
+    rest.sign_method() == Some(SignMethod::Ed25519)
+}
+
+// the signature covers every param of the logon sorted by name
+async fn logon(addr: &str, rest: &Rest) -> anyhow::Result<WebSocket> {
+    let mut ws = WebSocket::client(addr).await?;
+    if !logs_on(rest) {
+        info!(
+            "Websocket api connected {}, requests are signed one by one",
+            addr
+        );
+        return Ok(ws);
+    }
+
+    let timestamp = rest.now()?;
+    let payload = format!("apiKey={}&timestamp={}", rest.apikey(), timestamp);
+    let frame = json!({
+        "id": LOGON,
+        "method": "session.logon",
+        "params": {
+            "apiKey": rest.apikey(),
+            "timestamp": timestamp,
+            "signature": rest.sign(&payload)?
+        }
+    });
+    ws.send(Message::Text(frame.to_string())).await?;
+
+    let rsp = tokio::time::timeout(Duration::from_secs(3), async {
+        loop {
+            match ws.recv().await? {
+                Some(Message::Text(text)) => {
+                    return anyhow::Ok(serde_json::from_str::<Value>(&text)?)
+                }
+                Some(_) => (),
+                None => anyhow::bail!("Websocket api closed before logon"),
+            }
+        }
+    })
+    .await??;
+
+    if rsp["status"] != 200 {
+        anyhow::bail!("Websocket api logon failed {}", rsp["error"]);
+    }
+    info!("Websocket api logon {}", addr);
+    Ok(ws)
+}
+
+// false once every WsApi is dropped, pending requests fail when the connection is gone
+async fn serve(ws: &mut WebSocket, rest: &Rest, requests: &mut UnboundedReceiver<Request>) -> bool {
+    let mut pending: HashMap<u64, Reply> = HashMap::new();
+    let mut prune = tokio::time::interval(Duration::from_secs(1));
+    let running = loop {
+        tokio::select! {
+            // requests timed out, their answers are unknown responses if they ever come
+            _ = prune.tick() => pending.retain(|_, reply| !reply.is_closed()),
+            request = requests.recv() => match request {
+                Some((id, frame, reply)) => match ws.send(Message::Text(frame)).await {
+                    Ok(()) => {
+                        pending.insert(id, reply);
+                    }
+                    Err(e) => {
+                        let _ = reply.send(Err(e));
+                        break true;
+                    }
+                },
+                None => break false,
+            },
+            msg = ws.recv() => match msg {
+                Ok(Some(Message::Text(text))) => answer(&text, rest, &mut pending),
+                Ok(Some(Message::Ping(ping))) => {
+                    if let Err(e) = ws.send(Message::Pong(ping)).await {
+                        error!("{}", e);
+                        break true;
+                    }
+                }
+                Ok(Some(_)) => (),
+                Ok(None) => break true,
+                Err(e) => {
+                    error!("{}", e);
+                    break true;
+                }
+            },
+        }
+    };
+
+    for (_, reply) in pending {
+        let _ = reply.send(Err(anyhow::anyhow!("Websocket api is disconnected")));
+    }
+    running
+}
+
+fn answer(text: &str, rest: &Rest, pending: &mut HashMap<u64, Reply>) {
+    let mut rsp = match serde_json::from_str::<Value>(text) {
+        Ok(rsp) => rsp,
+        Err(e) => {
+            error!("{} {}", e, text);
+            return;
+        }
+    };
+    debug!("{}", rsp);
+
+    // requests and orders count the same as rest ones, retryAfter is a time in milliseconds
+    if let Ok(now) = rest.now() {
+        let mut rate_limits = rest.rate_limits().lock().unwrap();
+        if let Ok(limits) = serde_json::from_value::<Vec<ExchangeLimit>>(rsp["rateLimits"].take()) {
+            rate_limits.update_limits(&limits, now);
+        }
+        if rsp["status"] == 429 || rsp["status"] == 418 {
+            let until = rsp["error"]["data"]["retryAfter"].as_i64();
+            rate_limits.ban(until.unwrap_or(now + 60000));
+        }
+    }
+
+    let reply = match rsp["id"].as_u64().and_then(|id| pending.remove(&id)) {
+        Some(reply) => reply,
+        None => {
+            warn!("Unknown websocket api response {}", rsp);
+            return;
+        }
+    };
+    let body = match rsp.get("error") {
+        Some(_) => rsp["error"].take(),
+        None => rsp["result"].take(),
+    };
+    let _ = reply.send(Ok(body));
+}